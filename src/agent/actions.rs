@@ -1,26 +1,54 @@
+use std::{collections::VecDeque, sync::Arc};
+
 use bevy::{
+    ecs::system::SystemParam,
     prelude::{
-        default, info, warn, Commands, Component, Entity, EventWriter, GlobalTransform, Query, Res,
-        ResMut, Transform, With,
+        default, info, warn, Commands, Component, Entity, EventWriter, Quat, Query, Res, ResMut,
+        Transform, With,
     },
     time::Time,
+    utils::HashSet,
 };
 use bevy_turborand::{DelegatedRng, GlobalRng};
 use big_brain::{
     prelude::{ActionBuilder, ActionState},
     thinker::{ActionSpan, Actor},
 };
-use bracket_pathfinding::prelude::a_star_search;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    agent::navigation::{
+        cancel_path, request_path, AwaitingPath, NoPath, PathGoal, PathRequest, PathRequests,
+    },
     fauna::{
         needs::{Hunger, Reproduction, Thirst},
         SpawnFauna,
     },
-    map::{tiles::MapIndex, Map, TileQuery},
-    resource::{FoodSource, WaterSource},
+    map::{
+        flow::SourceFlow,
+        occupancy::{BodySize, Occupancy},
+        pathfinding::Locomotion,
+        regions::Regions,
+        spatial::SpatialIndex,
+        tiles::MapIndex,
+        Map, TileQuery,
+    },
+    resource::{FoodEaten, FoodSource, WaterSource},
+    serial::{by_serial, Serial},
 };
 
+/// The slowest an agent moves, so it never gets stuck on a tile it can't normally cross.
+const MIN_TERRAIN_SPEED: f32 = 0.1;
+
+/// How quickly agents turn to face the way they are heading. Higher is quicker.
+const TURN_SPEED: f32 = 8.0;
+
+/// How many seconds an agent waits for room on a full tile before squeezing past.
+const MAX_WAIT: f32 = 1.0;
+
+/// How many of the nearest food sources an agent picks the one with the shortest path from.
+const FOOD_CHOICES: usize = 8;
+
 // ACTIONS
 
 /// Action that moves to a target.
@@ -70,7 +98,39 @@ pub(crate) struct DrinkTarget {
 /// Component that contians the path to follow.
 #[derive(Component, Debug)]
 pub(crate) struct MovementPath {
-    pub(crate) path: Vec<usize>,
+    /// The tiles left to walk through, from the next one to the destination.
+    pub(crate) path: VecDeque<usize>,
+    /// What the path leads to. If it disappears, the path is dropped.
+    pub(crate) target: Option<Entity>,
+    /// How long the agent has been waiting for room on the next tile.
+    pub(crate) waited: f32,
+}
+
+impl MovementPath {
+    pub(crate) fn new(path: Vec<usize>, target: Option<Entity>) -> Self {
+        Self {
+            path: path.into(),
+            target,
+            waited: 0.0,
+        }
+    }
+
+    /// The tile the path ends on, if there is any of it left.
+    pub(crate) fn destination(&self) -> Option<usize> {
+        self.path.back().copied()
+    }
+
+    /// Whether any of the tiles left to walk through can no longer be crossed, including the
+    /// tiles passed on the way from one waypoint to the next.
+    pub(crate) fn is_blocked(&self, map: &Map, locomotion: Locomotion) -> bool {
+        let pathing = map.pathing(locomotion);
+        self.path
+            .iter()
+            .zip(self.path.iter().skip(1))
+            .flat_map(|(from, to)| pathing.line(*from, *to))
+            .chain(self.path.front().copied())
+            .any(|index| map.terrain_speed(index, locomotion) <= 0.0)
+    }
 }
 
 // Action abilities
@@ -78,19 +138,21 @@ pub(crate) struct MovementPath {
 // TODO: Observe ability
 
 /// Marker component that an entity can move.
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct MoveAbility {
     pub speed: f32,
+    /// How the entity gets around, which decides how fast it is on different terrain.
+    pub locomotion: Locomotion,
 }
 
 /// Marker component that an entity can eat food.
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct EatAbility {
     pub speed: f32,
 }
 
 /// Marker component that an entity can eat food.
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct DrinkAbility {
     pub speed: f32,
 }
@@ -102,8 +164,11 @@ pub(crate) fn eat_action(
     mut eaters: Query<(&mut Hunger, &EatAbility, &EatTarget)>,
     mut food_sources: Query<&mut FoodSource>,
     mut eat_actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<EatAction>>,
+    mut eaten: EventWriter<FoodEaten>,
+    serials: Query<&Serial>,
 ) {
-    for (Actor(actor), mut state, _) in &mut eat_actions {
+    let eat_actions = by_serial(&mut eat_actions, |(Actor(actor), ..)| *actor, &serials);
+    for (Actor(actor), mut state, _) in eat_actions {
         // let _guard = span.span().enter();
 
         match *state {
@@ -120,10 +185,16 @@ pub(crate) fn eat_action(
                         }
 
                         hunger.value -= eating_ability.speed * time.delta_seconds();
-                        food_source.content -= eating_ability.speed * time.delta_seconds();
+                        food_source.content = (food_source.content
+                            - eating_ability.speed * time.delta_seconds())
+                        .max(0.0);
 
                         if hunger.value <= 0.0 {
                             hunger.value = 0.0;
+                            eaten.send(FoodEaten {
+                                eater: *actor,
+                                source: eat_target.target,
+                            });
                             *state = ActionState::Success;
                         }
                     } else {
@@ -152,8 +223,10 @@ pub(crate) fn drink_action(
     mut drinkers: Query<(&mut Thirst, &DrinkAbility, &DrinkTarget)>,
     mut water_sources: Query<&mut WaterSource>,
     mut drink_actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<DrinkAction>>,
+    serials: Query<&Serial>,
 ) {
-    for (Actor(actor), mut state, _) in &mut drink_actions {
+    let drink_actions = by_serial(&mut drink_actions, |(Actor(actor), ..)| *actor, &serials);
+    for (Actor(actor), mut state, _) in drink_actions {
         // let _guard = span.span().enter();
 
         match *state {
@@ -196,44 +269,101 @@ pub(crate) fn drink_action(
 }
 
 /// Defines how an aget should move to a supplied target.
-// TODO: Move through waypoints
+///
+/// The agent walks straight from one waypoint of its path to the next, keeping to the ground and
+/// turning to face the way it is going.
+#[allow(clippy::type_complexity)]
 pub(crate) fn move_to_target(
     mut cmd: Commands,
     time: Res<Time>,
-    mut agents: Query<(&mut Transform, &MapIndex, &mut MovementPath, &MoveAbility)>,
+    mut agents: Query<(
+        &mut Transform,
+        &MapIndex,
+        Option<&mut MovementPath>,
+        &MoveAbility,
+        Option<&AwaitingPath>,
+        Option<&BodySize>,
+    )>,
     mut actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<MoveAction>>,
     map: Res<Map>,
+    mut occupancy: ResMut<Occupancy>,
+    serials: Query<&Serial>,
 ) {
-    for (Actor(actor), mut state, _) in &mut actions {
+    let actions = by_serial(&mut actions, |(Actor(actor), ..)| *actor, &serials);
+    for (Actor(actor), mut state, _) in actions {
         match *state {
             ActionState::Requested => *state = ActionState::Executing,
             ActionState::Cancelled => {
                 if agents.get(*actor).is_ok() {
                     cmd.entity(*actor).remove::<MovementPath>();
                 }
+                occupancy.release(*actor);
                 *state = ActionState::Failure;
             }
             ActionState::Executing => {
                 // info!("Moving to target");
-                if let Ok((mut transform, _, mut path, ability)) = agents.get_mut(*actor) {
-                    let mut available_movement = time.delta_seconds() * ability.speed;
-
-                    while available_movement > 0.0 && !path.path.is_empty() {
-                        let delta = map.index_to_world(path.path[0].into()) - transform.translation;
-
-                        if delta.length() > available_movement {
-                            transform.translation += delta.normalize() * available_movement;
-                            available_movement = 0.0;
-                        } else {
-                            transform.translation += delta;
-                            available_movement -= delta.length();
-                            path.path.remove(0);
+                if let Ok((mut transform, index, path, ability, awaiting, size)) =
+                    agents.get_mut(*actor)
+                {
+                    if let Some(mut path) = path {
+                        let mut available_time = time.delta_seconds();
+
+                        while available_time > 0.0 && !path.path.is_empty() {
+                            // The agent moves at the speed of the terrain it is currently on.
+                            let tile = map.world_to_index(transform.translation).unwrap_or(*index);
+                            let speed = ability.speed
+                                * map
+                                    .terrain_speed(tile.0, ability.locomotion)
+                                    .max(MIN_TERRAIN_SPEED);
+                            let available_movement = available_time * speed;
+                            // Waypoints can be several tiles apart, so the height is followed
+                            // separately from the ground below.
+                            let mut delta =
+                                map.index_to_world(path.path[0].into()) - transform.translation;
+                            delta.y = 0.0;
+
+                            // Agents wait for room on the next tile, until they have waited long
+                            // enough to squeeze past.
+                            let step = delta.clamp_length_max(available_movement);
+                            let blocked = size.is_some_and(|size| {
+                                map.world_to_index(transform.translation + step)
+                                    .is_some_and(|next| {
+                                        next != tile && !occupancy.reserve(*actor, next.0, size.0)
+                                    })
+                            });
+                            if blocked && path.waited < MAX_WAIT {
+                                path.waited += available_time;
+                                break;
+                            }
+                            path.waited = 0.0;
+
+                            if delta.length() > available_movement {
+                                transform.translation += delta.normalize() * available_movement;
+                                available_time = 0.0;
+                            } else {
+                                transform.translation += delta;
+                                available_time -= delta.length() / speed;
+                                path.path.pop_front();
+                            }
+
+                            if let Some(tile) = map.world_to_index(transform.translation) {
+                                transform.translation.y = map.world_height(tile.0);
+                            }
+                            if delta.length() > f32::EPSILON {
+                                let heading = Quat::from_rotation_y(f32::atan2(-delta.x, -delta.z));
+                                let turn = (TURN_SPEED * time.delta_seconds()).min(1.0);
+                                transform.rotation = transform.rotation.slerp(heading, turn);
+                            }
                         }
+                        if path.path.is_empty() {
+                            // info!("We arrive at the end of the path!");
+                            *state = ActionState::Success;
+                        }
+                    } else if awaiting.is_none() {
+                        info!("The path to the target is gone");
+                        *state = ActionState::Cancelled;
                     }
-                    if path.path.is_empty() {
-                        // info!("We arrive at the end of the path!");
-                        *state = ActionState::Success;
-                    }
+                    // Otherwise a new path is being searched for.
                 } else {
                     info!("No entities exist to perform this action");
                     *state = ActionState::Cancelled;
@@ -242,59 +372,116 @@ pub(crate) fn move_to_target(
             ActionState::Success => {
                 info!("Target reached");
                 cmd.entity(*actor).remove::<MovementPath>();
+                occupancy.release(*actor);
             }
             _ => {}
         }
     }
 }
 
+/// The food sources, found through the spatial index.
+#[derive(SystemParam)]
+pub(crate) struct FoodSources<'w, 's> {
+    map: Res<'w, Map>,
+    regions: Res<'w, Regions>,
+    spatial: Res<'w, SpatialIndex>,
+    sources: Query<'w, 's, &'static MapIndex, With<FoodSource>>,
+}
+
+impl<'w, 's> FoodSources<'w, 's> {
+    /// The tiles of the food sources closest to the tile that can be reached from it.
+    ///
+    /// The search spreads out from the tile until enough are found, so it only looks as far as it
+    /// needs to, however much food there is in the world.
+    fn nearest_reachable(&self, index: usize, locomotion: Locomotion) -> HashSet<usize> {
+        let graph = self.regions.graph(locomotion);
+        self.spatial
+            .nearest(&self.map, index, FOOD_CHOICES, |entity| {
+                self.sources
+                    .get(entity)
+                    .is_ok_and(|source| graph.is_reachable(index, source.0))
+            })
+            .into_iter()
+            .map(|(_, tile)| tile)
+            .collect()
+    }
+
+    /// A food source on the tile.
+    fn at(&self, index: usize) -> Option<Entity> {
+        self.spatial
+            .entities_at(index)
+            .iter()
+            .copied()
+            .find(|entity| self.sources.contains(*entity))
+    }
+}
+
 /// Defines how an agent should look for a food source.
 // TODO: Make this generic
+#[allow(clippy::type_complexity)]
 pub(crate) fn find_food(
     mut cmd: Commands,
-    agents: Query<(&GlobalTransform, &MapIndex), With<EatAbility>>,
-    food_sources: Query<(Entity, &GlobalTransform, &MapIndex), With<FoodSource>>,
+    mut agents: Query<
+        (
+            &MapIndex,
+            &MoveAbility,
+            Option<&mut MovementPath>,
+            Option<&NoPath>,
+        ),
+        With<EatAbility>,
+    >,
+    food: FoodSources,
     mut actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<FindFoodAction>>,
-    map: Res<Map>,
+    mut requests: ResMut<PathRequests>,
+    serials: Query<&Serial>,
 ) {
-    for (Actor(actor), mut state, _) in &mut actions {
+    let actions = by_serial(&mut actions, |(Actor(actor), ..)| *actor, &serials);
+    for (Actor(actor), mut state, _) in actions {
         match *state {
-            ActionState::Requested => *state = ActionState::Executing,
+            ActionState::Requested => {
+                if let Ok((agent_index, ability, _, _)) = agents.get(*actor) {
+                    // Search the nearest food sources the agent can reach for the one with the
+                    // shortest path.
+                    let tiles = food.nearest_reachable(agent_index.0, ability.locomotion);
+                    request_path(
+                        &mut cmd,
+                        &mut requests,
+                        PathRequest {
+                            agent: *actor,
+                            start: agent_index.0,
+                            goal: PathGoal::Nearest(Arc::new(tiles)),
+                            target: None,
+                            locomotion: ability.locomotion,
+                        },
+                    );
+                    *state = ActionState::Executing;
+                } else {
+                    info!("No entities exist to perform this action");
+                    *state = ActionState::Cancelled;
+                }
+            }
             ActionState::Executing => {
                 // info!("Looking for food");
-                if let Ok((agent_transform, agent_index)) = agents.get(*actor) {
-                    // get the food source closes to the agent's current location
-                    if let Some((source_entity, _, source_index)) =
-                        food_sources.iter().min_by(|(_, ta, _), (_, tb, _)| {
-                            let a_distance =
-                                (ta.translation() - agent_transform.translation()).length_squared();
-                            let b_distance =
-                                (tb.translation() - agent_transform.translation()).length_squared();
-                            a_distance.partial_cmp(&b_distance).unwrap()
-                        })
-                    {
-                        // Find the path
-                        let path = a_star_search(agent_index.0, source_index.0, &*map);
-
-                        if path.success {
-                            cmd.entity(*actor)
-                                .insert(MovementPath {
-                                    // Project this to zero for now.
-                                    path: path.steps,
-                                })
-                                .insert(EatTarget {
-                                    target: source_entity,
-                                });
-
+                if let Ok((_, _, path, no_path)) = agents.get_mut(*actor) {
+                    if let Some(mut path) = path {
+                        // The food might have been eaten while the path was searched for.
+                        let end = path.destination().unwrap();
+                        if let Some(source_entity) = food.at(end) {
+                            path.target = Some(source_entity);
+                            cmd.entity(*actor).insert(EatTarget {
+                                target: source_entity,
+                            });
                             *state = ActionState::Success;
                         } else {
-                            info!("Unable to find a valid path to the food-source");
+                            info!("The food-source has disappeared");
+                            cmd.entity(*actor).remove::<MovementPath>();
                             *state = ActionState::Cancelled;
                         }
-                    } else {
-                        info!("No food sources are closest");
+                    } else if no_path.is_some() {
+                        info!("No food sources can be reached");
                         *state = ActionState::Cancelled;
                     }
+                    // Otherwise the path is still being searched for.
                 } else {
                     info!("No entities exist to perform this action");
                     *state = ActionState::Cancelled;
@@ -303,12 +490,23 @@ pub(crate) fn find_food(
             ActionState::Success => {
                 info!("Found food source!");
             }
-            ActionState::Cancelled => *state = ActionState::Failure,
+            ActionState::Cancelled => {
+                cancel_path(&mut cmd, &mut requests, *actor);
+                *state = ActionState::Failure;
+            }
             _ => {}
         }
     }
 }
 
+/// The water sources, along with the flow fields leading to them.
+#[derive(SystemParam)]
+pub(crate) struct WaterSources<'w, 's> {
+    flow: Res<'w, SourceFlow<WaterSource>>,
+    spatial: Res<'w, SpatialIndex>,
+    sources: Query<'w, 's, (), With<WaterSource>>,
+}
+
 /// Defines how an agent should look for a water-source.
 ///
 /// TODO: This should be defined based on observing ability and discovered knowledge.
@@ -316,46 +514,45 @@ pub(crate) fn find_food(
 /// that should be used instead.
 pub(crate) fn find_drink(
     mut cmd: Commands,
-    agents: Query<(&GlobalTransform, &MapIndex), With<DrinkAbility>>,
-    water_sources: Query<(Entity, &GlobalTransform, &MapIndex), With<WaterSource>>,
+    agents: Query<(&MapIndex, &MoveAbility), With<DrinkAbility>>,
     mut actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<FindDrinkAction>>,
+    water: WaterSources,
     map: Res<Map>,
+    mut requests: ResMut<PathRequests>,
 ) {
     for (Actor(actor), mut state, _) in &mut actions {
         match *state {
             ActionState::Requested => *state = ActionState::Executing,
             ActionState::Executing => {
                 // info!("Looking for water");
-                if let Ok((agent_transform, agent_index)) = agents.get(*actor) {
-                    // get the food source closes to the agent's current location
-                    if let Some((source_entity, _, source_index)) =
-                        water_sources.iter().min_by(|(_, ta, _), (_, tb, _)| {
-                            let a_distance =
-                                (ta.translation() - agent_transform.translation()).length_squared();
-                            let b_distance =
-                                (tb.translation() - agent_transform.translation()).length_squared();
-                            a_distance.partial_cmp(&b_distance).unwrap()
-                        })
-                    {
-                        let path = a_star_search(agent_index.0, source_index.0, &*map);
-
-                        if path.success {
-                            cmd.entity(*actor)
-                                .insert(MovementPath {
-                                    // Project this to zero for now.
-                                    path: path.steps,
-                                })
-                                .insert(DrinkTarget {
-                                    target: source_entity,
-                                });
+                if let Ok((agent_index, ability)) = agents.get(*actor) {
+                    // Follow the flow field to the water source with the shortest path.
+                    let field = water.flow.field(ability.locomotion);
+                    let path = field
+                        .and_then(|field| field.path(agent_index.0))
+                        .map(|path| map.pathing(ability.locomotion).smooth(&path));
+                    let source_entity = field
+                        .and_then(|field| field.nearest_source(agent_index.0))
+                        .and_then(|source_index| {
+                            water
+                                .spatial
+                                .entities_at(source_index)
+                                .iter()
+                                .find(|e| water.sources.contains(**e))
+                        });
+
+                    if let (Some(path), Some(source_entity)) = (path, source_entity) {
+                        // A search from an earlier plan would otherwise replace the path.
+                        cancel_path(&mut cmd, &mut requests, *actor);
+                        cmd.entity(*actor)
+                            .insert(MovementPath::new(path, Some(*source_entity)))
+                            .insert(DrinkTarget {
+                                target: *source_entity,
+                            });
 
-                            *state = ActionState::Success;
-                        } else {
-                            info!("Unable to find a valid path to the water-source");
-                            *state = ActionState::Cancelled;
-                        }
+                        *state = ActionState::Success;
                     } else {
-                        info!("No water sources are closest");
+                        info!("Unable to find a reachable water-source");
                         *state = ActionState::Cancelled;
                     }
                 } else {
@@ -377,8 +574,10 @@ pub(crate) fn reproduce_action(
     mut writer: EventWriter<SpawnFauna>,
     mut reproducers: Query<(&mut Reproduction, &MapIndex)>,
     mut actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<ReproduceAction>>,
+    serials: Query<&Serial>,
 ) {
-    for (Actor(actor), mut state, _) in &mut actions {
+    let actions = by_serial(&mut actions, |(Actor(actor), ..)| *actor, &serials);
+    for (Actor(actor), mut state, _) in actions {
         match *state {
             ActionState::Requested => *state = ActionState::Executing,
             ActionState::Executing => {
@@ -388,7 +587,7 @@ pub(crate) fn reproduce_action(
                         info!("SUCESS!");
                         *state = ActionState::Success;
                         reproducer.value = 0.0;
-                        // TODO: Spawn new entity in an available spot.
+                        // The offspring is placed on the closest tile with room for it.
                         writer.send(SpawnFauna(Some(*map_index)));
                     } else {
                         *state = ActionState::Cancelled;
@@ -412,18 +611,24 @@ pub(crate) fn reproduce_action(
 
 pub(crate) fn idle_action(
     mut cmd: Commands,
-    agents: Query<&MapIndex, With<MoveAbility>>,
+    agents: Query<(
+        &MapIndex,
+        &MoveAbility,
+        Option<&MovementPath>,
+        Option<&NoPath>,
+    )>,
     mut actions: Query<(&Actor, &mut ActionState, &ActionSpan), With<IdleAction>>,
     map: Res<Map>,
     mut rng: ResMut<GlobalRng>,
+    mut requests: ResMut<PathRequests>,
+    serials: Query<&Serial>,
 ) {
-    for (Actor(actor), mut state, _) in &mut actions {
+    let actions = by_serial(&mut actions, |(Actor(actor), ..)| *actor, &serials);
+    for (Actor(actor), mut state, _) in actions {
         // info!("Actor {:?} is idling", actor);
         match *state {
-            ActionState::Requested => *state = ActionState::Executing,
-            ActionState::Cancelled => *state = ActionState::Failure,
-            ActionState::Executing => {
-                if let Ok(agent_index) = agents.get(*actor) {
+            ActionState::Requested => {
+                if let Ok((agent_index, ability, _, _)) = agents.get(*actor) {
                     // Find a random valid spot on the map within some radius of agent
                     // find a navigation path to it
                     let query = TileQuery {
@@ -434,14 +639,18 @@ pub(crate) fn idle_action(
                     };
                     let target = map.rand_from_query(rng.get_mut(), &query);
                     if let Some(target_location) = target {
-                        let path = a_star_search(agent_index.0, target_location.0, &*map);
-                        if path.success {
-                            cmd.entity(*actor).insert(MovementPath { path: path.steps });
-                            *state = ActionState::Success;
-                        } else {
-                            warn!("Unable to find a valid path to target");
-                            *state = ActionState::Failure;
-                        }
+                        request_path(
+                            &mut cmd,
+                            &mut requests,
+                            PathRequest {
+                                agent: *actor,
+                                start: agent_index.0,
+                                goal: PathGoal::Tile(target_location.0),
+                                target: None,
+                                locomotion: ability.locomotion,
+                            },
+                        );
+                        *state = ActionState::Executing;
                     } else {
                         warn!("Unable to find a random walkable tile in range.");
                         *state = ActionState::Failure;
@@ -451,7 +660,208 @@ pub(crate) fn idle_action(
                     *state = ActionState::Failure;
                 }
             }
+            ActionState::Cancelled => {
+                cancel_path(&mut cmd, &mut requests, *actor);
+                *state = ActionState::Failure;
+            }
+            ActionState::Executing => {
+                if let Ok((_, _, path, no_path)) = agents.get(*actor) {
+                    if path.is_some() {
+                        *state = ActionState::Success;
+                    } else if no_path.is_some() {
+                        warn!("Unable to find a valid path to target");
+                        *state = ActionState::Failure;
+                    }
+                } else {
+                    warn!("No agent to perform the action");
+                    *state = ActionState::Failure;
+                }
+            }
             _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::system::CommandQueue,
+        prelude::{Bundle, Commands, Entity, Stage, SystemStage, Transform, World},
+        tasks::{AsyncComputeTaskPool, TaskPool},
+    };
+    use bevy_turborand::GlobalRng;
+    use big_brain::{
+        actions::spawn_action,
+        prelude::{ActionBuilder, ActionState},
+    };
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::{
+        agent::navigation::{
+            finish_path_tasks, start_path_tasks, AwaitingPath, PathGoal, PathRequests, PathTask,
+            PathingSnapshot,
+        },
+        map::{
+            flow::{update_source_flow, SourceFlow},
+            generators::{GridGenerator, MapGenerator},
+            io::parse_ascii,
+            occupancy::Occupancy,
+            pathfinding::{DiagonalMovement, Locomotion},
+            plugin::MapSettings,
+            regions::Regions,
+            spatial::{update_spatial_index, SpatialIndex},
+            tiles::MapIndex,
+            Map,
+        },
+        resource::{FoodSource, WaterSource},
+        serial::Serials,
+    };
+
+    use super::{
+        find_drink, find_food, idle_action, DrinkAbility, DrinkTarget, EatAbility, FindDrinkAction,
+        FindFoodAction, IdleAction, MoveAbility, MovementPath, FOOD_CHOICES,
+    };
+
+    /// A world with what agents need to look for paths on the map.
+    fn world(text: &str) -> World {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let settings = MapSettings {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+        };
+        let map = GridGenerator::new(parse_ascii(text).unwrap()).generate(&settings, 0);
+
+        let mut world = World::new();
+        world.insert_resource(Regions::new(&map, DiagonalMovement::default()));
+        world.insert_resource(map);
+        world.insert_resource(DiagonalMovement::default());
+        world.insert_resource(GlobalRng::with_seed(3));
+        world.init_resource::<Serials>();
+        world.init_resource::<PathRequests>();
+        world.init_resource::<PathingSnapshot>();
+        world.init_resource::<Occupancy>();
+        world.init_resource::<SpatialIndex>();
+        world.init_resource::<SourceFlow<WaterSource>>();
+        world
+    }
+
+    fn tile(world: &World, x: i32, y: i32) -> usize {
+        world
+            .resource::<Map>()
+            .point_to_index(Point::new(x, y))
+            .unwrap()
+    }
+
+    fn spawn(world: &mut World, index: usize, bundle: impl Bundle) -> Entity {
+        let serial = world.resource_mut::<Serials>().next();
+        world.spawn((MapIndex(index), serial, bundle)).id()
+    }
+
+    fn spawn_agent(world: &mut World, index: usize) -> Entity {
+        let ability = MoveAbility {
+            speed: 1.0,
+            locomotion: Locomotion::Walker,
+        };
+        let abilities = (
+            ability,
+            EatAbility { speed: 1.0 },
+            DrinkAbility { speed: 1.0 },
+        );
+        spawn(world, index, (Transform::default(), abilities))
+    }
+
+    /// Starts the agent on the action, as its thinker would.
+    fn request(world: &mut World, action: &impl ActionBuilder, agent: Entity) -> Entity {
+        let mut queue = CommandQueue::default();
+        let action = spawn_action(action, &mut Commands::new(&mut queue, world), agent);
+        queue.apply(world);
+        *world.get_mut::<ActionState>(action).unwrap() = ActionState::Requested;
+        action
+    }
+
+    fn update_index(world: &mut World) {
+        SystemStage::single_threaded()
+            .with_system(update_spatial_index)
+            .with_system(update_source_flow::<WaterSource>)
+            .run(world);
+    }
+
+    #[test]
+    fn food_is_looked_for_nearby_and_in_reach() {
+        // The food nearest the agent is walled in.
+        let mut world = world(
+            "
+            ..........
+            .###......
+            .#.#......
+            .###......
+            ",
+        );
+        let (start, walled_in) = (tile(&world, 0, 2), tile(&world, 2, 2));
+        let agent = spawn_agent(&mut world, start);
+        let tiles = [(2, 2), (6, 2), (8, 0), (8, 1), (8, 2), (8, 3)]
+            .into_iter()
+            .chain((0..4).map(|y| (9, y)))
+            .map(|(x, y)| tile(&world, x, y))
+            .collect::<Vec<_>>();
+        for index in &tiles {
+            spawn(&mut world, *index, FoodSource { content: 10.0 });
+        }
+        update_index(&mut world);
+
+        request(&mut world, &FindFoodAction, agent);
+        SystemStage::single_threaded()
+            .with_system(find_food)
+            .run(&mut world);
+
+        let requests = world.resource::<PathRequests>();
+        let Some(PathGoal::Nearest(goals)) = requests.iter().next().map(|request| &request.goal)
+        else {
+            panic!("The agent should be looking for food");
+        };
+        // Only the nearest of the food sources in reach are searched for.
+        assert_eq!(goals.len(), FOOD_CHOICES);
+        assert!(goals.contains(&tile(&world, 6, 2)));
+        assert!(!goals.contains(&walled_in));
+        assert!(!goals.contains(&tile(&world, 9, 0)));
+    }
+
+    #[test]
+    fn drinking_replaces_the_search_of_an_earlier_plan() {
+        let mut world = world("......\n......\n.....~");
+        let agent = spawn_agent(&mut world, 0);
+        let water = spawn(&mut world, 17, WaterSource { content: 10.0 });
+        update_index(&mut world);
+
+        let idle = request(&mut world, &IdleAction, agent);
+        let mut actions = SystemStage::single_threaded()
+            .with_system(idle_action)
+            .with_system(find_drink);
+        let mut searches = SystemStage::single_threaded().with_system(start_path_tasks);
+        actions.run(&mut world);
+        searches.run(&mut world);
+        assert!(world.get::<PathTask>(agent).is_some());
+
+        // The agent gets thirsty while still looking for somewhere to idle.
+        *world.get_mut::<ActionState>(idle).unwrap() = ActionState::Cancelled;
+        request(&mut world, &FindDrinkAction, agent);
+        // Idling is given up, then the water is looked for.
+        actions.run(&mut world);
+        actions.run(&mut world);
+        if let Some(mut task) = world.get_mut::<PathTask>(agent) {
+            task.wait();
+        }
+        SystemStage::single_threaded()
+            .with_system(finish_path_tasks)
+            .run(&mut world);
+
+        assert_eq!(world.resource::<PathRequests>().len(), 0);
+        assert!(world.get::<AwaitingPath>(agent).is_none());
+        assert_eq!(
+            world.get::<MovementPath>(agent).unwrap().target,
+            Some(water)
+        );
+        assert_eq!(world.get::<DrinkTarget>(agent).unwrap().target, water);
+    }
+}
@@ -1,16 +1,24 @@
-use bevy::prelude::Plugin;
+use bevy::prelude::{CoreStage, IntoSystemDescriptor, Plugin};
 use big_brain::{BigBrainPlugin, BigBrainStage};
 
+use crate::map::regions::update_regions;
+
 use self::{
     actions::{
         drink_action, eat_action, find_drink, find_food, idle_action, move_to_target,
         reproduce_action,
     },
+    navigation::{
+        finish_path_tasks, invalidate_paths, start_path_tasks, PathRequests, PathingSnapshot,
+    },
     scorers::{hungry_scorer, reproduction_scorer, thirsty_scorer},
+    steering::separate_agents,
 };
 
 pub(crate) mod actions;
+pub(crate) mod navigation;
 pub(crate) mod scorers;
+pub(crate) mod steering;
 
 pub(crate) struct AgentPlugin;
 
@@ -26,6 +34,22 @@ impl Plugin for AgentPlugin {
             .add_system_to_stage(BigBrainStage::Actions, idle_action)
             .add_system_to_stage(BigBrainStage::Scorers, hungry_scorer)
             .add_system_to_stage(BigBrainStage::Scorers, thirsty_scorer)
-            .add_system_to_stage(BigBrainStage::Scorers, reproduction_scorer);
+            .add_system_to_stage(BigBrainStage::Scorers, reproduction_scorer)
+            .add_system(separate_agents)
+            .init_resource::<PathRequests>()
+            .init_resource::<PathingSnapshot>()
+            // Runs once despawned targets are gone, so replanned paths are searched for right away.
+            .add_system_to_stage(CoreStage::PostUpdate, invalidate_paths)
+            // Finished paths are handed out before new searches replace them.
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                finish_path_tasks.after(invalidate_paths),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                start_path_tasks
+                    .after(finish_path_tasks)
+                    .after(update_regions),
+            );
     }
 }
@@ -0,0 +1,430 @@
+//! Finds paths for agents in the background, so many agents looking for paths at once don't
+//! stall the frame.
+//!
+//! Actions queue a `PathRequest` and wait until the agent is given a `MovementPath`, or marked
+//! with `NoPath` if there isn't one. Only a limited number of searches are started each tick; the
+//! rest wait in the queue.
+
+use std::{collections::VecDeque, sync::Arc};
+
+use bevy::{
+    prelude::{info, Commands, Component, Entity, Local, Query, Res, ResMut, Resource},
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashSet,
+};
+use futures_lite::future;
+
+use crate::{
+    chronos::Lockstep,
+    map::{occupancy::Occupancy, pathfinding::Locomotion, regions::Regions, tiles::MapIndex, Map},
+    serial::{by_serial, Serial},
+};
+
+use super::actions::{MoveAbility, MovementPath};
+
+/// How many path searches are started each tick, unless configured otherwise.
+const SEARCHES_PER_TICK: usize = 64;
+
+/// Where a path should lead.
+pub(crate) enum PathGoal {
+    /// A single tile.
+    Tile(usize),
+    /// Whichever of the tiles has the cheapest path.
+    Nearest(Arc<HashSet<usize>>),
+}
+
+/// A path an agent is waiting for.
+pub(crate) struct PathRequest {
+    pub(crate) agent: Entity,
+    pub(crate) start: usize,
+    pub(crate) goal: PathGoal,
+    /// What the path leads to, handed on to the `MovementPath`.
+    pub(crate) target: Option<Entity>,
+    pub(crate) locomotion: Locomotion,
+}
+
+/// Paths waiting to be searched for, first come first served.
+#[derive(Resource)]
+pub(crate) struct PathRequests {
+    queue: VecDeque<PathRequest>,
+    /// How many searches are started each tick.
+    pub(crate) searches_per_tick: usize,
+}
+
+impl Default for PathRequests {
+    fn default() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            searches_per_tick: SEARCHES_PER_TICK,
+        }
+    }
+}
+
+impl PathRequests {
+    /// Queues a search, replacing any the agent is already waiting for.
+    pub(crate) fn request(&mut self, request: PathRequest) {
+        self.cancel(request.agent);
+        self.queue.push_back(request);
+    }
+
+    /// Forgets the search the agent is waiting for, if it has not been started yet.
+    pub(crate) fn cancel(&mut self, agent: Entity) {
+        self.queue.retain(|queued| queued.agent != agent);
+    }
+
+    /// Forgets every search that has not been started yet.
+    pub(crate) fn clear(&mut self) {
+        self.queue.clear();
+    }
+
+    /// How many searches have not been started yet.
+    pub(crate) fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The searches that have not been started yet, first come first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &PathRequest> {
+        self.queue.iter()
+    }
+}
+
+/// Queues a search for the agent, forgetting any path it had or was waiting for.
+pub(crate) fn request_path(cmd: &mut Commands, requests: &mut PathRequests, request: PathRequest) {
+    cmd.entity(request.agent)
+        .remove::<(MovementPath, NoPath, PathTask)>()
+        .insert(AwaitingPath);
+    requests.request(request);
+}
+
+/// Stops looking for a path for the agent, so a search it no longer needs can't hand it a path
+/// later on.
+pub(crate) fn cancel_path(cmd: &mut Commands, requests: &mut PathRequests, agent: Entity) {
+    if let Some(mut entity) = cmd.get_entity(agent) {
+        entity.remove::<(PathTask, AwaitingPath, NoPath)>();
+    }
+    requests.cancel(agent);
+}
+
+/// Marks that the agent is waiting for a path to be found.
+#[derive(Component)]
+pub(crate) struct AwaitingPath;
+
+/// Marks that no path could be found for the agent's last request.
+#[derive(Component)]
+pub(crate) struct NoPath;
+
+/// A search running in the background, along with the target of the path.
+#[derive(Component)]
+pub(crate) struct PathTask(Task<Option<Vec<usize>>>, Option<Entity>);
+
+impl PathTask {
+    /// A search that has already finished with the path, e.g. one that was saved.
+    pub(crate) fn finished(path: Option<Vec<usize>>, target: Option<Entity>) -> Self {
+        Self(
+            AsyncComputeTaskPool::get().spawn(future::ready(path)),
+            target,
+        )
+    }
+
+    /// Waits for the search to finish, still handing the path on as usual afterwards.
+    pub(crate) fn wait(&mut self) -> Option<Vec<usize>> {
+        let path = future::block_on(&mut self.0);
+        self.0 = AsyncComputeTaskPool::get().spawn(future::ready(path.clone()));
+        path
+    }
+
+    pub(crate) fn target(&self) -> Option<Entity> {
+        self.1
+    }
+}
+
+/// The map and its regions the searches run on, shared with the background tasks.
+///
+/// Only copied again when tiles are replaced, as nothing else on the map affects the paths.
+#[derive(Resource, Default)]
+pub(crate) struct PathingSnapshot {
+    map: Option<Arc<Map>>,
+    revision: usize,
+    regions: Option<Arc<Regions>>,
+}
+
+/// Finds a path on the map, as a background task would.
+///
+/// Paths to a single tile are found through the regions of the map. Tiles that were full of other
+/// agents when the search started are avoided where possible.
+pub(crate) fn search(
+    map: &Map,
+    regions: &Regions,
+    crowded: &HashSet<usize>,
+    start: usize,
+    goal: &PathGoal,
+    locomotion: Locomotion,
+) -> Option<Vec<usize>> {
+    let graph = regions.graph(locomotion);
+    let pathing = graph.pathing(map).with_crowding(crowded);
+    let path = match goal {
+        PathGoal::Tile(tile) => graph.path(&pathing, start, *tile),
+        // Without any goal in reach, the search would cover everything the agent can reach.
+        PathGoal::Nearest(tiles) if !tiles.iter().any(|tile| graph.is_reachable(start, *tile)) => {
+            None
+        }
+        PathGoal::Nearest(tiles) => pathing.path_to_nearest(start, |index| tiles.contains(&index)),
+    };
+    path.map(|path| pathing.smooth(&path))
+}
+
+/// Starts searching for as many of the queued paths as the budget allows.
+pub(super) fn start_path_tasks(
+    mut cmd: Commands,
+    mut requests: ResMut<PathRequests>,
+    mut snapshot: ResMut<PathingSnapshot>,
+    map: Res<Map>,
+    regions: Res<Regions>,
+    occupancy: Res<Occupancy>,
+) {
+    if requests.len() == 0 {
+        return;
+    }
+    if snapshot.map.is_none() || map.is_added() || snapshot.revision != map.revision() {
+        snapshot.map = Some(Arc::new(map.clone()));
+        snapshot.revision = map.revision();
+    }
+    if snapshot.regions.is_none() || regions.is_changed() {
+        snapshot.regions = Some(Arc::new(regions.clone()));
+    }
+
+    let crowded = Arc::new(occupancy.full_tiles());
+    let pool = AsyncComputeTaskPool::get();
+    let count = requests.searches_per_tick.min(requests.len());
+    for request in requests.queue.drain(..count).collect::<Vec<_>>() {
+        // The agent might have been despawned while waiting.
+        if let Some(mut agent) = cmd.get_entity(request.agent) {
+            let map = snapshot.map.clone().unwrap();
+            let regions = snapshot.regions.clone().unwrap();
+            let crowded = crowded.clone();
+            let task = pool.spawn(async move {
+                search(
+                    &map,
+                    &regions,
+                    &crowded,
+                    request.start,
+                    &request.goal,
+                    request.locomotion,
+                )
+            });
+            agent.insert(PathTask(task, request.target));
+        }
+    }
+}
+
+/// Drops paths whose target has disappeared, and searches again for paths crossing tiles that
+/// can no longer be crossed.
+pub(super) fn invalidate_paths(
+    mut cmd: Commands,
+    mut requests: ResMut<PathRequests>,
+    agents: Query<(Entity, &MapIndex, &MovementPath, &MoveAbility)>,
+    entities: Query<()>,
+    serials: Query<&Serial>,
+    map: Res<Map>,
+    mut revision: Local<Option<usize>>,
+) {
+    // A map put in place of another, e.g. a loaded one, is the one the paths were found on.
+    let tiles_changed = !map.is_added() && *revision != Some(map.revision());
+    *revision = Some(map.revision());
+
+    for (entity, index, path, ability) in by_serial(&agents, |(entity, ..)| *entity, &serials) {
+        if let Some(target) = path.target {
+            if !entities.contains(target) {
+                info!("The target of the path has disappeared");
+                cmd.entity(entity).remove::<MovementPath>();
+                continue;
+            }
+        }
+
+        if tiles_changed && path.is_blocked(&map, ability.locomotion) {
+            if let Some(destination) = path.destination() {
+                info!("The path is blocked, looking for another way");
+                request_path(
+                    &mut cmd,
+                    &mut requests,
+                    PathRequest {
+                        agent: entity,
+                        start: index.0,
+                        goal: PathGoal::Tile(destination),
+                        target: path.target,
+                        locomotion: ability.locomotion,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Hands the paths of finished searches to their agents.
+///
+/// In lockstep every search finishes on the next tick, however long it takes, so paths arrive at
+/// the same time on every run.
+pub(super) fn finish_path_tasks(
+    mut cmd: Commands,
+    mut tasks: Query<(Entity, &mut PathTask)>,
+    lockstep: Option<Res<Lockstep>>,
+) {
+    for (entity, mut task) in &mut tasks {
+        let finished = if lockstep.is_some() {
+            Some(future::block_on(&mut task.0))
+        } else {
+            future::block_on(future::poll_once(&mut task.0))
+        };
+        if let Some(result) = finished {
+            let mut agent = cmd.entity(entity);
+            agent.remove::<(PathTask, AwaitingPath)>();
+            match result {
+                Some(path) => agent.insert(MovementPath::new(path, task.1)),
+                None => agent.insert(NoPath),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bevy::{prelude::Entity, utils::HashSet};
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::{
+        agent::actions::MovementPath,
+        map::{
+            flow::FlowField,
+            generators::{GridGenerator, MapGenerator},
+            io::parse_ascii,
+            pathfinding::{DiagonalMovement, Locomotion},
+            plugin::MapSettings,
+            regions::Regions,
+            tiles::{get_data, TileType},
+            Map,
+        },
+    };
+
+    use super::{search, PathGoal, PathRequest, PathRequests};
+
+    #[test]
+    fn requests_replace_or_cancel_earlier_ones() {
+        let mut requests = PathRequests::default();
+        let request = |agent, start| PathRequest {
+            agent: Entity::from_raw(agent),
+            start,
+            goal: PathGoal::Tile(0),
+            target: None,
+            locomotion: Locomotion::Walker,
+        };
+
+        requests.request(request(1, 5));
+        requests.request(request(2, 5));
+        requests.request(request(1, 7));
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests.queue[1].start, 7);
+
+        requests.cancel(Entity::from_raw(1));
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests.queue[0].agent, Entity::from_raw(2));
+    }
+
+    fn grid(text: &str) -> Map {
+        let settings = MapSettings {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+        };
+        GridGenerator::new(parse_ascii(text).unwrap()).generate(&settings, 0)
+    }
+
+    fn tile(map: &Map, x: i32, y: i32) -> usize {
+        map.point_to_index(Point::new(x, y)).unwrap()
+    }
+
+    fn find(map: &Map, start: usize, goal: PathGoal) -> Option<Vec<usize>> {
+        search(
+            map,
+            &Regions::new(map, DiagonalMovement::default()),
+            &HashSet::new(),
+            start,
+            &goal,
+            Locomotion::Walker,
+        )
+    }
+
+    #[test]
+    fn searches_for_tiles_and_the_nearest_of_many() {
+        let map = grid(
+            "
+            ....
+            .##.
+            ....
+            ",
+        );
+        let start = tile(&map, 0, 1);
+
+        let path = find(&map, start, PathGoal::Tile(tile(&map, 3, 1))).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&tile(&map, 3, 1)));
+        assert_eq!(find(&map, start, PathGoal::Tile(tile(&map, 1, 1))), None);
+
+        let goals = Arc::new(HashSet::from_iter([tile(&map, 3, 2), tile(&map, 1, 2)]));
+        let path = find(&map, start, PathGoal::Nearest(goals)).unwrap();
+        assert_eq!(path.last(), Some(&tile(&map, 1, 2)));
+    }
+
+    #[test]
+    fn agents_look_past_an_unreachable_lake() {
+        // The lake in the middle is closest, but surrounded by deep water.
+        let map = grid(
+            "
+            .........
+            .#####...
+            .#~~~#...
+            .#~~~#..~
+            .#####...
+            ",
+        );
+        let (lake, pond) = (tile(&map, 3, 2), tile(&map, 8, 3));
+        let start = tile(&map, 0, 2);
+
+        let goals = Arc::new(HashSet::from_iter([lake, pond]));
+        let path = find(&map, start, PathGoal::Nearest(goals)).unwrap();
+        assert_eq!(path.last(), Some(&pond));
+
+        // The water flow field leads to the same pond.
+        let field = FlowField::new(&map.pathing(Locomotion::Walker), [lake, pond]);
+        assert_eq!(field.nearest_source(start), Some(pond));
+
+        // With only the lake left, there is nothing to find.
+        let goals = Arc::new(HashSet::from_iter([lake]));
+        assert_eq!(find(&map, start, PathGoal::Nearest(goals)), None);
+    }
+
+    #[test]
+    fn paths_notice_tiles_becoming_impassable() {
+        let mut map = grid(
+            "
+            .....
+            .....
+            ",
+        );
+        let path = MovementPath::new(
+            (0..5).map(|x| tile(&map, x, 0)).collect(),
+            Some(Entity::from_raw(1)),
+        );
+        assert_eq!(path.destination(), Some(tile(&map, 4, 0)));
+        assert!(!path.is_blocked(&map, Locomotion::Walker));
+
+        // The river has flooded a tile along the way.
+        map.set_tile(tile(&map, 2, 0), get_data(&TileType::DeepWater));
+        assert!(path.is_blocked(&map, Locomotion::Walker));
+
+        // A new path goes around it.
+        let path = find(&map, tile(&map, 0, 0), PathGoal::Tile(tile(&map, 4, 0))).unwrap();
+        assert!(!MovementPath::new(path, None).is_blocked(&map, Locomotion::Walker));
+    }
+}
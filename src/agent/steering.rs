@@ -0,0 +1,159 @@
+//! Local steering that keeps agents from piling up on the same spot.
+
+use bevy::{
+    prelude::{Entity, Query, Res, Transform, Vec3},
+    time::Time,
+};
+
+use crate::{
+    map::{spatial::SpatialIndex, tiles::MapIndex, Map},
+    serial::Serial,
+};
+
+use super::actions::MoveAbility;
+
+/// How close agents may get to each other before they start pushing apart, in tiles.
+const PERSONAL_SPACE: f32 = 0.45;
+
+/// How fast agents push apart when right on top of each other, in tiles per second.
+const SEPARATION_SPEED: f32 = 1.0;
+
+/// How far around its own tile an agent looks for others crowding it, in tiles.
+const NEIGHBOURHOOD: f32 = 1.5;
+
+/// The direction that moves an agent away from its neighbours, stronger the closer they are.
+///
+/// Only the horizontal distance counts. Agents standing exactly on top of each other are pushed
+/// apart along the x-axis, in opposite directions decided by which has the lower key.
+pub(crate) fn separation<K: Ord>(
+    (key, position): (K, Vec3),
+    neighbours: impl IntoIterator<Item = (K, Vec3)>,
+    distance: f32,
+) -> Vec3 {
+    let mut push = Vec3::ZERO;
+    for (other, other_position) in neighbours {
+        let mut offset = position - other_position;
+        offset.y = 0.0;
+        let length = offset.length();
+        if length >= distance {
+            continue;
+        }
+
+        let direction = if length > f32::EPSILON {
+            offset / length
+        } else if key < other {
+            Vec3::NEG_X
+        } else {
+            Vec3::X
+        };
+        push += direction * (1.0 - length / distance);
+    }
+    push
+}
+
+/// Pushes agents that are too close apart, without pushing them onto tiles they can't cross.
+pub(super) fn separate_agents(
+    mut agents: Query<(Entity, &Serial, &mut Transform, &MapIndex, &MoveAbility)>,
+    spatial: Res<SpatialIndex>,
+    map: Res<Map>,
+    time: Res<Time>,
+) {
+    let tile_size = map.settings.tile_size;
+
+    // Every push is worked out before any agent moves, so the order doesn't matter.
+    let mut pushes = Vec::new();
+    for (entity, serial, transform, index, _) in &agents {
+        let neighbours = spatial
+            .within(&map, index.0, NEIGHBOURHOOD, |other| {
+                other != entity && agents.contains(other)
+            })
+            .into_iter()
+            .filter_map(|(other, _)| {
+                agents
+                    .get(other)
+                    .ok()
+                    .map(|(_, serial, transform, _, _)| (*serial, transform.translation))
+            });
+        // Stacked agents split up by serial, so the same way after the simulation is loaded.
+        let push = separation(
+            (*serial, transform.translation),
+            neighbours,
+            PERSONAL_SPACE * tile_size,
+        );
+        if push != Vec3::ZERO {
+            pushes.push((entity, push));
+        }
+    }
+
+    for (entity, push) in pushes {
+        if let Ok((_, _, mut transform, _, ability)) = agents.get_mut(entity) {
+            let target =
+                transform.translation + push * SEPARATION_SPEED * tile_size * time.delta_seconds();
+            let passable = map
+                .world_to_index(target)
+                .is_some_and(|tile| map.terrain_speed(tile.0, ability.locomotion) > 0.0);
+            if passable {
+                transform.translation = target;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{Entity, Vec3};
+
+    use super::separation;
+
+    #[test]
+    fn neighbours_push_harder_the_closer_they_are() {
+        let agent = (Entity::from_raw(0), Vec3::ZERO);
+        let near = separation(
+            agent,
+            [(Entity::from_raw(1), Vec3::new(0.1, 0.0, 0.0))],
+            0.5,
+        );
+        let far = separation(
+            agent,
+            [(Entity::from_raw(1), Vec3::new(0.4, 0.0, 0.0))],
+            0.5,
+        );
+        assert!(near.x < far.x && far.x < 0.0);
+        assert_eq!(near.z, 0.0);
+
+        // Neighbours far enough away, or only above, are left alone.
+        let apart = separation(
+            agent,
+            [(Entity::from_raw(1), Vec3::new(0.0, 0.0, 0.6))],
+            0.5,
+        );
+        assert_eq!(apart, Vec3::ZERO);
+        let above = separation(
+            agent,
+            [(Entity::from_raw(1), Vec3::new(0.0, 1.0, 0.0))],
+            0.5,
+        );
+        assert_eq!(above.y, 0.0);
+
+        // Pushes from either side cancel out.
+        let squeezed = separation(
+            agent,
+            [
+                (Entity::from_raw(1), Vec3::new(0.2, 0.0, 0.0)),
+                (Entity::from_raw(2), Vec3::new(-0.2, 0.0, 0.0)),
+            ],
+            0.5,
+        );
+        assert_eq!(squeezed, Vec3::ZERO);
+    }
+
+    #[test]
+    fn stacked_agents_split_up() {
+        let (a, b) = (Entity::from_raw(3), Entity::from_raw(7));
+        let position = Vec3::new(1.0, 0.0, 1.0);
+        let push_a = separation((a, position), [(b, position)], 0.5);
+        let push_b = separation((b, position), [(a, position)], 0.5);
+        assert_eq!(push_a, -push_b);
+        assert_eq!(push_a.length(), 1.0);
+    }
+}
@@ -1,8 +1,18 @@
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
+
 use bevy::{
-    prelude::{App, EventReader, Plugin, Res, ResMut, Resource, SystemSet},
-    time::FixedTimestep,
+    ecs::schedule::{ParallelSystemExecutor, StageLabelId, SystemContainer},
+    prelude::{
+        App, CoreStage, EventReader, IntoSystemDescriptor, Plugin, Res, ResMut, Resource, Schedule,
+        SystemSet, SystemStage, World,
+    },
+    time::{FixedTimestep, TimeUpdateStrategy},
 };
 use leafwing_input_manager::Actionlike;
+use serde::{Deserialize, Serialize};
 
 pub(crate) struct ChronoPlugin;
 
@@ -20,8 +30,156 @@ impl Plugin for ChronoPlugin {
     }
 }
 
+/// How far time moves on each frame in lockstep. Rounded up to a whole nanosecond, so the clock
+/// ticks exactly once every frame.
+const LOCKSTEP_STEP: Duration = Duration::from_nanos(66_666_667);
+
+/// Runs the simulation in lockstep, so the same input always leads to the same run.
+///
+/// Every frame moves time on by the same amount whatever the frame rate, and the systems of each
+/// stage run one at a time in the same order. Only the stages added before the plugin are covered,
+/// including those of plugins like big-brain and the startup stages.
+pub(crate) struct LockstepPlugin;
+
+impl Plugin for LockstepPlugin {
+    fn build(&self, app: &mut App) {
+        let start = Instant::now();
+        app.insert_resource(Lockstep {
+            frame: 0,
+            next: start,
+            held: false,
+        })
+        .insert_resource(TimeUpdateStrategy::ManualInstant(start))
+        .add_system_to_stage(CoreStage::Last, advance_lockstep.at_end());
+        run_in_lockstep(&mut app.schedule);
+    }
+}
+
+/// Runs the systems of every stage in the schedule in lockstep, going into nested schedules.
+fn run_in_lockstep(schedule: &mut Schedule) {
+    let labels: Vec<StageLabelId> = schedule.iter_stages().map(|(label, _)| label).collect();
+    for label in labels {
+        if let Some(stage) = schedule.get_stage_mut::<SystemStage>(label) {
+            stage.set_executor(Box::<LockstepExecutor>::default());
+        } else if let Some(schedule) = schedule.get_stage_mut::<Schedule>(label) {
+            run_in_lockstep(schedule);
+        }
+    }
+}
+
+/// Runs the systems of a stage one at a time, in the same order on every run.
+///
+/// Stages sort their systems with randomly seeded hash maps, so systems without an order between
+/// them come in a different order on every run. Here they are sorted again by name, as far as the
+/// order between them allows, and their commands are then applied in that order as well.
+#[derive(Default)]
+struct LockstepExecutor {
+    /// Whether the systems have been sorted since the stage last sorted them.
+    sorted: bool,
+}
+
+impl ParallelSystemExecutor for LockstepExecutor {
+    fn rebuild_cached_data(&mut self, _: &[SystemContainer]) {
+        self.sorted = false;
+    }
+
+    fn run_systems(&mut self, systems: &mut [SystemContainer], world: &mut World) {
+        if !self.sorted {
+            self.sorted = true;
+            let order = lockstep_order(systems);
+            // Moves the systems into place, keeping track of where each one has gone.
+            let mut position: Vec<usize> = (0..systems.len()).collect();
+            let mut placed: Vec<usize> = (0..systems.len()).collect();
+            for (slot, system) in order.into_iter().enumerate() {
+                let from = position[system];
+                let displaced = placed[slot];
+                systems.swap(slot, from);
+                placed.swap(slot, from);
+                position[system] = slot;
+                position[displaced] = from;
+            }
+        }
+
+        for system in systems {
+            if system.should_run() {
+                system.system_mut().run((), world);
+            }
+        }
+    }
+}
+
+/// The order to run the systems in: whichever comes first by name of those whose dependencies
+/// have all run.
+fn lockstep_order(systems: &[SystemContainer]) -> Vec<usize> {
+    let mut waiting: Vec<usize> = systems
+        .iter()
+        .map(|system| system.dependencies().len())
+        .collect();
+    let mut dependants = vec![Vec::new(); systems.len()];
+    for (index, system) in systems.iter().enumerate() {
+        for dependency in system.dependencies() {
+            dependants[*dependency].push(index);
+        }
+    }
+
+    let mut ready: BTreeSet<_> = (0..systems.len())
+        .filter(|index| waiting[*index] == 0)
+        .map(|index| (systems[index].name(), index))
+        .collect();
+    let mut order = Vec::with_capacity(systems.len());
+    while let Some((_, index)) = ready.pop_first() {
+        order.push(index);
+        for dependant in &dependants[index] {
+            waiting[*dependant] -= 1;
+            if waiting[*dependant] == 0 {
+                ready.insert((systems[*dependant].name(), *dependant));
+            }
+        }
+    }
+    order
+}
+
+/// Counts the frames of a simulation running in lockstep.
 #[derive(Resource)]
-pub(crate) struct TimeMultiplier(u8);
+pub(crate) struct Lockstep {
+    frame: u32,
+    /// When the next frame happens, as far as the simulation knows.
+    next: Instant,
+    /// Whether time has stopped.
+    held: bool,
+}
+
+impl Lockstep {
+    pub(crate) fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Carries on counting from another frame, e.g. after restoring a save made on it.
+    pub(crate) fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+
+    /// Stops time for good. The simulation can't go on the same afterwards, as systems still run.
+    pub(crate) fn hold(&mut self) {
+        self.held = true;
+    }
+
+    pub(crate) fn is_held(&self) -> bool {
+        self.held
+    }
+}
+
+fn advance_lockstep(mut lockstep: ResMut<Lockstep>, mut strategy: ResMut<TimeUpdateStrategy>) {
+    if lockstep.held {
+        return;
+    }
+    lockstep.frame += 1;
+    lockstep.next += LOCKSTEP_STEP;
+    *strategy = TimeUpdateStrategy::ManualInstant(lockstep.next);
+}
+
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub(crate) struct TimeMultiplier(pub(crate) u8);
 
 impl TimeMultiplier {
     pub(crate) fn value(&self) -> u8 {
@@ -29,7 +187,7 @@ impl TimeMultiplier {
     }
 }
 
-#[derive(Resource, Default)]
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
 pub(crate) struct Chrono {
     // ever increasing value
     tick: u32,
@@ -41,7 +199,61 @@ pub(crate) struct Chrono {
     pub(crate) year: u32,
 }
 
-#[derive(Actionlike, Debug, Clone, Copy)]
+impl Chrono {
+    pub(crate) fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    /// How many whole days have passed since the simulation started.
+    pub(crate) fn days(&self) -> u32 {
+        self.tick / TICKS_PER_HOUR / HOURS_PER_DAY
+    }
+
+    /// How far into the current year we are. Range: 0.0..1.0
+    pub(crate) fn year_progress(&self) -> f32 {
+        (self.day * HOURS_PER_DAY + self.hour) as f32 / (DAYS_PER_YEAR * HOURS_PER_DAY) as f32
+    }
+
+    /// How far into the current day we are. Range: 0.0..1.0
+    pub(crate) fn day_progress(&self) -> f32 {
+        self.hour as f32 / HOURS_PER_DAY as f32
+    }
+}
+
+/// Keeps track of when a simulation system is due to run, given an interval in ticks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TickTimer {
+    interval: u32,
+    last: u32,
+    elapsed: u32,
+}
+
+impl TickTimer {
+    pub(crate) fn new(interval: u32) -> Self {
+        Self {
+            interval: interval.max(1),
+            last: 0,
+            elapsed: 0,
+        }
+    }
+
+    /// Returns true if at least the interval has passed since the last time it was due.
+    pub(crate) fn update(&mut self, tick: u32) -> bool {
+        if tick < self.last + self.interval {
+            return false;
+        }
+        self.elapsed = tick - self.last;
+        self.last = tick;
+        true
+    }
+
+    /// How many simulated hours passed between the last two times the timer was due.
+    pub(crate) fn elapsed_hours(&self) -> f32 {
+        self.elapsed as f32 / TICKS_PER_HOUR as f32
+    }
+}
+
+#[derive(Actionlike, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum SimulationSpeed {
     Paused,
     Normal,
@@ -60,7 +272,7 @@ pub(crate) const fn get_multiplier(speed: SimulationSpeed) -> u8 {
     }
 }
 
-const TICKS_PER_HOUR: u32 = 60;
+pub(crate) const TICKS_PER_HOUR: u32 = 60;
 const HOURS_PER_DAY: u32 = 24;
 const DAYS_PER_YEAR: u32 = 30;
 
@@ -113,7 +325,14 @@ fn years_from_tick(tick: u32) -> u32 {
 
 #[cfg(test)]
 mod tests {
-    use super::hours_from_tick;
+    use bevy::{
+        prelude::App,
+        time::{Time, TimePlugin},
+    };
+
+    use super::{
+        hours_from_tick, Chrono, ChronoPlugin, Lockstep, LockstepPlugin, TickTimer, LOCKSTEP_STEP,
+    };
     use crate::chronos::TICKS_PER_HOUR;
 
     /// When we go over
@@ -121,4 +340,40 @@ mod tests {
     fn wrap_around() {
         assert_eq!(hours_from_tick(TICKS_PER_HOUR + 1), 1);
     }
+
+    /// The timer should only be due once the interval has passed,
+    /// and report the actual time passed when the simulation skips ahead.
+    #[test]
+    fn tick_timer_interval() {
+        let mut timer = TickTimer::new(10);
+        assert!(!timer.update(5));
+        assert!(timer.update(10));
+        assert!(!timer.update(15));
+        assert!(timer.update(10 + TICKS_PER_HOUR));
+        assert_eq!(timer.elapsed_hours(), 1.0);
+    }
+
+    /// In lockstep, time moves on the same amount every frame and the clock ticks once a frame.
+    #[test]
+    fn lockstep_frames() {
+        let mut app = App::new();
+        app.add_plugin(TimePlugin)
+            .add_plugin(ChronoPlugin)
+            .add_plugin(LockstepPlugin);
+        for _ in 0..10 {
+            app.update();
+        }
+        assert_eq!(app.world.resource::<Lockstep>().frame(), 10);
+        assert_eq!(app.world.resource::<Time>().delta(), LOCKSTEP_STEP);
+        // No time has passed by the first frame.
+        assert_eq!(app.world.resource::<Chrono>().tick(), 9);
+
+        // The time of the next frame is already set by the end of the last one.
+        app.world.resource_mut::<Lockstep>().hold();
+        app.update();
+        app.update();
+        app.update();
+        assert_eq!(app.world.resource::<Lockstep>().frame(), 10);
+        assert_eq!(app.world.resource::<Chrono>().tick(), 10);
+    }
 }
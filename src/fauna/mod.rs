@@ -1,13 +1,19 @@
-use bevy::prelude::{
-    default, shape, App, Assets, Color, Commands, Entity, EventReader, IntoSystemDescriptor, Mesh,
-    PbrBundle, Plugin, Res, ResMut, StandardMaterial, Transform,
+use std::{marker::PhantomData, sync::Arc};
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::{
+        default, shape, App, Assets, Color, Commands, Component, Entity, EventReader,
+        IntoSystemDescriptor, Mesh, PbrBundle, Plugin, Res, ResMut, StandardMaterial, Transform,
+    },
 };
 use bevy_mod_picking::PickableBundle;
 use bevy_turborand::{DelegatedRng, GlobalRng};
 use big_brain::{
-    prelude::{FirstToScore, Steps},
-    thinker::Thinker,
+    prelude::{ActionBuilder, FirstToScore, Steps},
+    thinker::{Thinker, ThinkerBuilder},
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
     agent::{
@@ -18,13 +24,19 @@ use crate::{
         scorers::{Hungry, ReproductionScore, Thirsty},
         AgentPlugin,
     },
-    map::{tiles::MapIndex, Map, TileQuery},
+    map::{
+        occupancy::{BodySize, Occupancy},
+        pathfinding::{DiagonalMovement, ALL_LOCOMOTIONS},
+        tiles::MapIndex,
+        Map, TileQuery,
+    },
+    serial::Serials,
     utils::lerp_range,
 };
 
 use self::needs::{
-    death, health_update, hunger_decay, reproduction_update, thirst_decay, Health, Hunger,
-    Reproduction, Thirst,
+    crowding_stress, death, health_update, hunger_decay, reproduction_update, thirst_decay, Health,
+    Hunger, Reproduction, Thirst,
 };
 
 pub(crate) mod needs;
@@ -41,6 +53,7 @@ impl Plugin for FaunaPlugin {
             .add_system(thirst_decay.before(health_update))
             .add_system(health_update.before(reproduction_update))
             .add_system(reproduction_update)
+            .add_system(crowding_stress.after(reproduction_update))
             .add_system(death.after(health_update))
             .add_system(despawn_agent.after(death))
             .add_system(spawn_agent);
@@ -62,18 +75,117 @@ fn despawn_agent(mut cmd: Commands, mut events: EventReader<DespawnFauna>) {
     }
 }
 
+/// The colour agents are drawn with.
+pub(crate) const AGENT_COLOR: Color = Color::rgb(0.3, 0.5, 0.5);
+
+/// The shape of an agent of the given size.
+pub(crate) fn agent_mesh(size: BodySize) -> Mesh {
+    Mesh::from(shape::Capsule {
+        radius: 0.2 * size.0,
+        depth: 0.4,
+        ..default()
+    })
+}
+
+/// Decides what an agent does next, based on its needs.
+pub(crate) fn agent_thinker() -> ThinkerBuilder {
+    Thinker::build()
+        .label("AgentThinker")
+        .picker(FirstToScore { threshold: 0.8 })
+        .when(Hungry, Plan::Eat.builder())
+        .when(Thirsty, Plan::Drink.builder())
+        .when(ReproductionScore, Plan::Reproduce.builder())
+        .otherwise(Plan::Idle.builder())
+}
+
+/// What an agent has decided to do, marked on the action carrying it out.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Plan {
+    Eat,
+    Drink,
+    Reproduce,
+    Idle,
+}
+
+impl Plan {
+    /// Builds the action carrying out the plan.
+    fn builder(self) -> PlanBuilder {
+        let action: Arc<dyn ActionBuilder> = match self {
+            Plan::Eat => Arc::new(
+                Steps::build()
+                    .label("FindFoodMoveAndEat")
+                    .step(FindFoodAction)
+                    .step(MoveAction)
+                    .step(EatAction),
+            ),
+            Plan::Drink => Arc::new(
+                Steps::build()
+                    .label("FindDrinkMoveAndEat")
+                    .step(FindDrinkAction)
+                    .step(MoveAction)
+                    .step(DrinkAction),
+            ),
+            Plan::Reproduce => Arc::new(ReproduceAction),
+            Plan::Idle => Arc::new(
+                Steps::build()
+                    .label("Idle")
+                    .step(IdleAction)
+                    .step(MoveAction),
+            ),
+        };
+        PlanBuilder { plan: self, action }
+    }
+}
+
+/// Builds the action carrying out a plan, and marks it with the plan.
+#[derive(Debug)]
+struct PlanBuilder {
+    plan: Plan,
+    action: Arc<dyn ActionBuilder>,
+}
+
+impl ActionBuilder for PlanBuilder {
+    fn build(&self, cmd: &mut Commands, action: Entity, actor: Entity) {
+        self.action.build(cmd, action, actor);
+        cmd.entity(action).insert(self.plan);
+    }
+
+    fn label(&self) -> Option<&str> {
+        self.action.label()
+    }
+}
+
+/// Finds the tiles newly spawned agents have room on, and numbers the agents.
+#[derive(SystemParam)]
+struct SpawnRoom<'w, 's> {
+    map: Res<'w, Map>,
+    occupancy: ResMut<'w, Occupancy>,
+    diagonals: Res<'w, DiagonalMovement>,
+    serials: ResMut<'w, Serials>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
 fn spawn_agent(
     mut cmd: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut rng: ResMut<GlobalRng>,
     mut events: EventReader<SpawnFauna>,
-    map: Res<Map>,
+    mut room: SpawnRoom,
 ) {
+    let SpawnRoom {
+        map,
+        occupancy,
+        diagonals,
+        serials,
+        ..
+    } = &mut room;
     for event in &mut events.iter() {
-        let height = 0.4;
-        let spawn_index = if event.0.is_some() {
-            event.0.unwrap()
+        let size = BodySize(lerp_range(rng.f32(), &(0.5..1.5)));
+        let locomotion = ALL_LOCOMOTIONS[rng.usize(..ALL_LOCOMOTIONS.len())];
+        let requested_index = if let Some(index) = event.0 {
+            index
         } else {
             map.rand_from_query(
                 rng.get_mut(),
@@ -84,41 +196,16 @@ fn spawn_agent(
             )
             .unwrap()
         };
-
-        let move_and_eat = Steps::build()
-            .label("FindFoodMoveAndEat")
-            .step(FindFoodAction)
-            .step(MoveAction)
-            .step(EatAction);
-
-        let move_and_drink = Steps::build()
-            .label("FindDrinkMoveAndEat")
-            .step(FindDrinkAction)
-            .step(MoveAction)
-            .step(DrinkAction);
-
-        let idle_and_move = Steps::build()
-            .label("Idle")
-            .step(IdleAction)
-            .step(MoveAction);
-
-        let thinker = Thinker::build()
-            .label("AgentThinker")
-            .picker(FirstToScore { threshold: 0.8 })
-            .when(Hungry, move_and_eat)
-            .when(Thirsty, move_and_drink)
-            .when(ReproductionScore, ReproduceAction)
-            .otherwise(idle_and_move);
+        // Crowded tiles push newcomers out to the closest tile with room for them.
+        let spawn_index = occupancy
+            .nearest_with_room(map, requested_index.0, size.0, locomotion, **diagonals)
+            .map_or(requested_index, MapIndex);
 
         // TODO: These ranges should be given by the Fauna archetype
-        cmd.spawn((
+        let entity = cmd.spawn((
             PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Capsule {
-                    radius: 0.2,
-                    depth: height,
-                    ..default()
-                })),
-                material: materials.add(Color::rgb(0.3, 0.5, 0.5).into()),
+                mesh: meshes.add(agent_mesh(size)),
+                material: materials.add(AGENT_COLOR.into()),
                 transform: Transform::from_translation(map.index_to_world(spawn_index)),
                 ..default()
             },
@@ -144,10 +231,15 @@ fn spawn_agent(
             },
             MoveAbility {
                 speed: lerp_range(rng.f32(), &(1.5..10.0)),
+                locomotion,
             },
-            thinker,
+            size,
+            agent_thinker(),
             spawn_index,
+            serials.next(),
             PickableBundle::default(),
         ));
+        // Holds the room until the agent is counted, so others spawned now go elsewhere.
+        occupancy.reserve(entity.id(), spawn_index.0, size.0);
     }
 }
@@ -2,10 +2,17 @@ use bevy::{
     prelude::{warn, Changed, Component, Entity, EventWriter, Query, Res},
     time::Time,
 };
+use serde::{Deserialize, Serialize};
+
+use crate::map::{occupancy::Occupancy, tiles::MapIndex};
 
 use super::DespawnFauna;
 
-#[derive(Component, Debug, Copy, Clone)]
+/// How fast the reproduction need drops on an overcrowded tile, for each full tile's worth of
+/// agents too many.
+const CROWDING_STRESS: f32 = 5.0;
+
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
 pub(crate) struct Hunger {
     /// How fast the entity gets hungry.
     pub per_second: f32,
@@ -13,19 +20,19 @@ pub(crate) struct Hunger {
     pub value: f32,
 }
 
-#[derive(Component, Debug, Copy, Clone)]
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
 pub(crate) struct Thirst {
     pub per_second: f32,
     pub value: f32,
 }
 
-#[derive(Component, Debug, Copy, Clone)]
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
 pub(crate) struct Health {
     pub value: f32,
 }
 
 /// Defines the agent's current reproduction need.
-#[derive(Component, Debug, Copy, Clone)]
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
 pub(crate) struct Reproduction {
     pub value: f32,
 }
@@ -112,3 +119,19 @@ pub(crate) fn reproduction_update(mut q: Query<(&mut Reproduction, &Health), Cha
         }
     }
 }
+
+/// Overcrowded agents are stressed, which makes them less ready to reproduce.
+pub(crate) fn crowding_stress(
+    time: Res<Time>,
+    occupancy: Res<Occupancy>,
+    mut q: Query<(&mut Reproduction, &MapIndex)>,
+) {
+    for (mut reproduction, index) in &mut q {
+        let overcrowding = occupancy.crowding(index.0) - 1.0;
+        if overcrowding > 0.0 {
+            reproduction.value = (reproduction.value
+                - overcrowding * CROWDING_STRESS * time.delta_seconds())
+            .max(0.0);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! How flora compete with each other for light and space.
+//!
+//! Plants find their neighbours through the `SpatialIndex`.
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::{Entity, Query, Res, ResMut, Resource},
+    utils::HashMap,
+};
+
+use crate::map::{spatial::SpatialIndex, tiles::MapIndex, Map};
+
+use super::{species::get_data, Flora};
+
+/// How much space a fully grown plant may take up on a tile with perfect growability.
+const TILE_CAPACITY: f32 = 1.5;
+
+/// How much of the shade cast by plants on a neighbouring tile reaches this tile.
+const NEIGHBOUR_SHADE: f32 = 0.25;
+
+/// How far away plants can shade each other, reaching the diagonal neighbours.
+const SHADE_RADIUS: f32 = 1.5;
+
+/// The smallest amount of space any plant takes up, even as a seedling.
+pub(super) const SEEDLING_SPACE: f32 = 0.05;
+
+/// Space on tiles claimed by seedlings that have not been spawned yet.
+#[derive(Resource, Default)]
+pub(super) struct ReservedSpace(HashMap<usize, f32>);
+
+/// Looks up the plants standing on each tile, to see if there is room for more.
+#[derive(SystemParam)]
+pub(super) struct FloraStands<'w, 's> {
+    map: Res<'w, Map>,
+    spatial: Res<'w, SpatialIndex>,
+    flora: Query<'w, 's, &'static Flora>,
+    reserved: ResMut<'w, ReservedSpace>,
+}
+
+impl<'w, 's> FloraStands<'w, 's> {
+    /// How much space on the tile has been taken by plants and reserved for seedlings.
+    pub(super) fn space_used(&self, index: usize) -> f32 {
+        let standing: f32 = self
+            .spatial
+            .entities_at(index)
+            .iter()
+            .filter_map(|entity| self.flora.get(*entity).ok())
+            .map(flora_space)
+            .sum();
+        standing + self.reserved.0.get(&index).copied().unwrap_or(0.0)
+    }
+
+    /// Returns true if there is space left on the tile for another seedling.
+    pub(super) fn has_room(&self, index: usize) -> bool {
+        self.space_used(index) + SEEDLING_SPACE <= carrying_capacity(&self.map, index)
+    }
+
+    /// Claims space for a seedling that has not yet been spawned.
+    pub(super) fn reserve(&mut self, index: usize) {
+        *self.reserved.0.entry(index).or_insert(0.0) += SEEDLING_SPACE;
+    }
+}
+
+/// How much plant life a tile can carry.
+pub(super) fn carrying_capacity(map: &Map, index: usize) -> f32 {
+    map.growability[index] * TILE_CAPACITY
+}
+
+/// The current height of a plant.
+pub(super) fn flora_height(flora: &Flora) -> f32 {
+    get_data(&flora.species).height * flora.current_growth
+}
+
+/// The current space a plant takes up on its tile.
+pub(super) fn flora_space(flora: &Flora) -> f32 {
+    (get_data(&flora.species).footprint * flora.current_growth).max(SEEDLING_SPACE)
+}
+
+/// Reserved space is taken up by the seedlings once they have been spawned.
+pub(super) fn clear_reservations(mut reserved: ResMut<ReservedSpace>) {
+    reserved.0.clear();
+}
+
+/// Plants are slowed down by taller plants shading them, and by crowded roots on their tile.
+///
+/// Plants shorter than their neighbours end up as understory, growing slowly if at all.
+pub(super) fn compete_flora(
+    spatial: Res<SpatialIndex>,
+    map: Res<Map>,
+    mut q: Query<(Entity, &mut Flora, &MapIndex)>,
+) {
+    // Find the vigour of all plants first, so they all compete against the same heights.
+    let vigour: Vec<(Entity, f32)> = q
+        .iter()
+        .map(|(entity, flora, index)| {
+            let height = flora_height(flora);
+            let mut shade = 0.0;
+            let mut used = 0.0;
+            for (other, tile) in spatial.within(&map, index.0, SHADE_RADIUS, |e| q.contains(e)) {
+                let (_, other, _) = q.get(other).unwrap();
+                let weight = if tile == index.0 {
+                    used += flora_space(other);
+                    1.0
+                } else {
+                    NEIGHBOUR_SHADE
+                };
+                if flora_height(other) > height {
+                    shade += flora_space(other) * weight;
+                }
+            }
+
+            let light = (1.0 - shade).clamp(0.0, 1.0);
+            let roots = root_share(used, carrying_capacity(&map, index.0));
+
+            (entity, light * roots)
+        })
+        .collect();
+
+    for (entity, vigour) in vigour {
+        if let Ok((_, mut flora, _)) = q.get_mut(entity) {
+            flora.vigour = vigour;
+        }
+    }
+}
+
+/// When the plants on a tile take up more space than it can carry, they all share the shortage.
+fn root_share(used: f32, capacity: f32) -> f32 {
+    if used <= capacity {
+        1.0
+    } else {
+        capacity / used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::root_share;
+
+    #[test]
+    fn roots_only_compete_when_crowded() {
+        assert_eq!(root_share(0.5, 1.0), 1.0);
+        assert_eq!(root_share(1.0, 1.0), 1.0);
+        assert_eq!(root_share(2.0, 1.0), 0.5);
+    }
+}
@@ -0,0 +1,183 @@
+//! How flora spread their seeds across the map.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::{
+    Commands, Component, Entity, EventReader, EventWriter, Query, Res, ResMut, Vec2,
+};
+use bevy_turborand::{rng::Rng, DelegatedRng, GlobalRng, TurboRand};
+use bracket_pathfinding::prelude::Point;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{tiles::MapIndex, Map},
+    resource::FoodEaten,
+    serial::Serial,
+    weather::Weather,
+};
+
+use super::{
+    competition::FloraStands,
+    species::{get_data, FloraSpecies},
+    Flora, FloraClock, SpawnFlora,
+};
+
+/// How many simulated hours a fauna carries the seeds of a fruit it has eaten.
+const SEED_CARRY_HOURS: f32 = 5.0;
+
+/// Seeds of a fruit that a fauna has eaten, waiting to be dropped somewhere else.
+#[derive(Component, Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CarriedSeeds {
+    species: FloraSpecies,
+    /// Simulated hours left before the seeds are dropped.
+    hours_left: f32,
+}
+
+/// Mature flora randomly release seeds that are carried by the wind.
+///
+/// A seed only becomes a new flora if it lands on a tile with room left and manages to germinate.
+pub(super) fn disperse_seeds(
+    clock: Res<FloraClock>,
+    weather: Res<Weather>,
+    map: Res<Map>,
+    mut glob_rng: ResMut<GlobalRng>,
+    mut stands: FloraStands,
+    q: Query<(&Serial, &Flora, &MapIndex)>,
+    mut event: EventWriter<SpawnFlora>,
+) {
+    let rng = glob_rng.get_mut();
+
+    let mut flora: Vec<_> = q.iter().collect();
+    flora.sort_by_key(|(serial, ..)| **serial);
+    for (_, flora, index) in flora {
+        let data = get_data(&flora.species);
+        if flora.current_growth < data.maturity || rng.f32() > data.seeding_chance * clock.hours() {
+            continue;
+        }
+
+        let origin = map.index_to_point(index.0);
+        for _ in 0..data.seed_count {
+            if let Some(target) = seed_landing(&map, rng, origin, data.dispersal_radius, &weather) {
+                if !stands.has_room(target) || !germinates(&map, rng, target) {
+                    continue;
+                }
+
+                stands.reserve(target);
+                event.send(SpawnFlora {
+                    index: target,
+                    species: flora.species,
+                });
+            }
+        }
+    }
+}
+
+/// Picks the tile a seed lands on, if it lands on the map at all.
+fn seed_landing(
+    map: &Map,
+    rng: &mut Rng,
+    origin: Point,
+    radius: f32,
+    weather: &Weather,
+) -> Option<usize> {
+    let throw = Vec2::from_angle(rng.f32() * TAU) * rng.f32() * radius;
+    let offset = throw + weather.wind() * radius;
+    let target = origin + Point::new(offset.x.round() as i32, offset.y.round() as i32);
+
+    map.point_to_index(target)
+}
+
+fn germinates(map: &Map, rng: &mut Rng, index: usize) -> bool {
+    rng.f32() < map.germination_chance(&index)
+}
+
+/// Fauna that eats from fruit bearing flora carries its seeds with them.
+pub(super) fn pick_up_seeds(
+    mut cmd: Commands,
+    mut events: EventReader<FoodEaten>,
+    q: Query<&Flora>,
+) {
+    for event in events.iter() {
+        if let Ok(flora) = q.get(event.source) {
+            if !get_data(&flora.species).fruit {
+                continue;
+            }
+            if let Some(mut eater) = cmd.get_entity(event.eater) {
+                eater.insert(CarriedSeeds {
+                    species: flora.species,
+                    hours_left: SEED_CARRY_HOURS,
+                });
+            }
+        }
+    }
+}
+
+/// Once enough time has passed, the carried seeds are dropped wherever the fauna is.
+pub(super) fn drop_seeds(
+    mut cmd: Commands,
+    clock: Res<FloraClock>,
+    map: Res<Map>,
+    mut glob_rng: ResMut<GlobalRng>,
+    mut stands: FloraStands,
+    mut carriers: Query<(Entity, &Serial, &mut CarriedSeeds, &MapIndex)>,
+    mut event: EventWriter<SpawnFlora>,
+) {
+    let rng = glob_rng.get_mut();
+
+    let mut carriers: Vec<_> = carriers.iter_mut().collect();
+    carriers.sort_by_key(|(_, serial, ..)| **serial);
+    for (entity, _, mut seeds, index) in carriers {
+        seeds.hours_left -= clock.hours();
+        if seeds.hours_left > 0.0 {
+            continue;
+        }
+        cmd.entity(entity).remove::<CarriedSeeds>();
+
+        if !map.is_growable(&index.0) || !stands.has_room(index.0) {
+            continue;
+        }
+        if germinates(&map, rng, index.0) {
+            stands.reserve(index.0);
+            event.send(SpawnFlora {
+                index: index.0,
+                species: seeds.species,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_turborand::{DelegatedRng, GlobalRng};
+    use bracket_pathfinding::prelude::{DistanceAlg::Chebyshev, Point};
+
+    use crate::{
+        map::{
+            generators::{MapGenerator, PerlinGenerator},
+            plugin::MapSettings,
+        },
+        weather::Weather,
+    };
+
+    use super::seed_landing;
+
+    /// Without wind, seeds should never land further away than the dispersal radius.
+    #[test]
+    fn seeds_land_within_radius() {
+        let settings = MapSettings {
+            width: 16,
+            height: 16,
+            tile_size: 1.0,
+        };
+        let map = PerlinGenerator.generate(&settings, 0);
+        let mut rng = GlobalRng::new();
+        let mut weather = Weather::default();
+        weather.wind_strength = 0.0;
+
+        let origin = Point::new(8, 8);
+        for _ in 0..100 {
+            let target = seed_landing(&map, rng.get_mut(), origin, 2.0, &weather).unwrap();
+            assert!(Chebyshev.distance2d(origin, map.index_to_point(target)) <= 2.0);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! How flora age, suffer and eventually die.
+
+use bevy::{
+    prelude::{info, Commands, Entity, EventReader, EventWriter, Query, Res, ResMut},
+    utils::HashSet,
+};
+use bevy_turborand::{DelegatedRng, GlobalRng, TurboRand};
+
+use crate::{
+    map::{tiles::MapIndex, Map},
+    serial::{by_serial, Serial},
+    weather::Weather,
+};
+
+use super::{species::get_data, Flora, FloraClock};
+
+/// How many simulated hours of drought stress a plant survives.
+const DROUGHT_TOLERANCE: f32 = 48.0;
+
+/// Chance per simulated hour and degree below its hardiness that frost kills a plant.
+const FROST_KILL_RATE: f32 = 0.04;
+
+/// How much nutrients a fully grown plant returns to its tile when it dies.
+const NUTRIENTS_PER_GROWTH: f32 = 0.2;
+
+/// Soil can not hold more nutrients than this.
+const MAX_NUTRIENTS: f32 = 2.0;
+
+/// How much nutrients are used up from the soil for every unit of growth.
+pub(super) const NUTRIENT_UPTAKE: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum DeathCause {
+    OldAge,
+    Drought,
+    Frost,
+    Overgrazed,
+}
+
+/// Event that is sent when a flora has died.
+pub(super) struct FloraDied {
+    pub(super) entity: Entity,
+    pub(super) cause: DeathCause,
+}
+
+/// How much the nutrients on a tile speeds up or slows down growth.
+pub(super) fn fertility(nutrients: f32) -> f32 {
+    (0.5 + nutrients).min(1.5)
+}
+
+/// Flora grows older, and dies from old age, drought or frost.
+pub(super) fn age_flora(
+    clock: Res<FloraClock>,
+    weather: Res<Weather>,
+    map: Res<Map>,
+    mut glob_rng: ResMut<GlobalRng>,
+    mut q: Query<(Entity, &mut Flora, &MapIndex)>,
+    serials: Query<&Serial>,
+    mut died: EventWriter<FloraDied>,
+) {
+    let rng = glob_rng.get_mut();
+    let delta = clock.hours();
+
+    for (entity, mut flora, index) in by_serial(&mut q, |(entity, ..)| *entity, &serials) {
+        let data = get_data(&flora.species);
+
+        flora.age += delta;
+        if flora.age >= flora.lifespan {
+            died.send(FloraDied {
+                entity,
+                cause: DeathCause::OldAge,
+            });
+            continue;
+        }
+
+        // Drought stress builds up while the tile is too dry, and heals while it's wet enough.
+        let dryness = data.min_moisture - map.moisture[index.0];
+        flora.drought = (flora.drought + dryness * delta).max(0.0);
+        if flora.drought >= DROUGHT_TOLERANCE {
+            died.send(FloraDied {
+                entity,
+                cause: DeathCause::Drought,
+            });
+            continue;
+        }
+
+        let temperature = weather.local_temperature(map.temperature[index.0]);
+        let frost = (data.frost_hardiness - temperature).max(0.0);
+        if rng.f32() < frost * FROST_KILL_RATE * delta {
+            died.send(FloraDied {
+                entity,
+                cause: DeathCause::Frost,
+            });
+        }
+    }
+}
+
+/// Despawns dead flora, returning their biomass to the soil as nutrients.
+pub(super) fn decompose_flora(
+    mut cmd: Commands,
+    mut events: EventReader<FloraDied>,
+    mut map: ResMut<Map>,
+    q: Query<(&Flora, &MapIndex)>,
+) {
+    // The same flora might die from several causes in the same frame.
+    let mut dead = HashSet::new();
+
+    for event in events.iter() {
+        if !dead.insert(event.entity) {
+            continue;
+        }
+        if let Ok((flora, index)) = q.get(event.entity) {
+            info!(
+                "{:?} {:?} died: {:?}",
+                flora.species, event.entity, event.cause
+            );
+
+            let nutrients = &mut map.nutrients[index.0];
+            *nutrients =
+                (*nutrients + flora.current_growth * NUTRIENTS_PER_GROWTH).min(MAX_NUTRIENTS);
+            cmd.entity(event.entity).despawn();
+        }
+    }
+}
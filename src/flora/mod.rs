@@ -1,45 +1,110 @@
 use bevy::{
+    ecs::schedule::ShouldRun,
     prelude::{
-        default, info, shape, App, Assets, Color, Commands, Component, EventReader, EventWriter,
-        IntoSystemDescriptor, Mesh, PbrBundle, Plugin, Query, Res, ResMut, StandardMaterial,
-        Transform, Vec3,
+        default, info, shape, App, Assets, Changed, Color, Commands, Component, Entity,
+        EventReader, EventWriter, IntoSystemDescriptor, Mesh, PbrBundle, Plugin, Query, Res,
+        ResMut, Resource, StandardMaterial, SystemSet, Transform, Vec3,
     },
-    utils::HashMap,
 };
 use bevy_mod_picking::PickableBundle;
 use bevy_turborand::{DelegatedRng, GlobalRng, TurboRand};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    chronos::{Chrono, TickTimer},
     map::{
         tiles::{MapIndex, TileType},
         Map, TileQuery,
     },
     resource::{FoodSource, WaterSource},
+    serial::{by_serial, Serial, Serials},
     utils::lerp_range,
     AppStage,
 };
 
-pub(crate) struct FloraPlugin;
+use self::{
+    competition::{clear_reservations, compete_flora, ReservedSpace},
+    dispersal::{disperse_seeds, drop_seeds, pick_up_seeds},
+    lifecycle::{age_flora, decompose_flora, fertility, DeathCause, FloraDied, NUTRIENT_UPTAKE},
+    species::{get_data, FloraSpecies, ALL_SPECIES},
+};
+
+mod competition;
+pub(crate) mod dispersal;
+mod lifecycle;
+pub(crate) mod species;
+
+pub(crate) struct FloraPlugin {
+    /// How many simulation ticks pass between each update of the flora.
+    pub(crate) update_interval: u32,
+}
+
+impl Default for FloraPlugin {
+    fn default() -> Self {
+        Self {
+            update_interval: 10,
+        }
+    }
+}
 
 // Plants grow and become food. The more they grow, the more food they contain.
 impl Plugin for FloraPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<SpawnFlora>()
+            .add_event::<FloraDied>()
+            .init_resource::<ReservedSpace>()
+            .insert_resource(FloraClock::new(self.update_interval))
             .add_startup_system_to_stage(AppStage::SpawnFlora, generate_flora)
             .add_startup_system_to_stage(AppStage::SpawnFlora, spawn_water)
-            // TODO: This should be a fixed time step
-            .add_system(grow_flora)
-            .add_system(scale_flora.after(grow_flora))
-            .add_system(spread_flora.after(grow_flora))
-            .add_system(spawn_flora.after(spread_flora));
+            .add_system(graze_flora.before(compete_flora))
+            // The flora is simulated at a fixed rate of simulation ticks, not every frame.
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(flora_tick)
+                    .with_system(clear_reservations)
+                    .with_system(compete_flora)
+                    .with_system(grow_flora.after(compete_flora))
+                    .with_system(scale_flora.after(grow_flora))
+                    .with_system(disperse_seeds.after(grow_flora).after(clear_reservations))
+                    .with_system(drop_seeds.after(disperse_seeds))
+                    .with_system(age_flora.after(grow_flora)),
+            )
+            .add_system(pick_up_seeds)
+            .add_system(spawn_flora.after(disperse_seeds).after(drop_seeds))
+            .add_system(decompose_flora.after(age_flora).after(graze_flora));
+    }
+}
+
+/// Decides when the flora should next be simulated.
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub(crate) struct FloraClock(TickTimer);
+
+impl FloraClock {
+    pub(crate) fn new(interval: u32) -> Self {
+        Self(TickTimer::new(interval))
+    }
+
+    /// How many simulated hours have passed since the flora was last updated.
+    fn hours(&self) -> f32 {
+        self.0.elapsed_hours()
+    }
+}
+
+fn flora_tick(chrono: Res<Chrono>, mut clock: ResMut<FloraClock>) -> ShouldRun {
+    if clock.0.update(chrono.tick()) {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
     }
 }
 
-const FOOD_COLOR: Color = Color::rgb(0.0, 1.0, 0.0);
-const WATER_COLOR: Color = Color::rgb(0.0, 0.0, 1.0);
+pub(crate) const WATER_COLOR: Color = Color::rgb(0.0, 0.0, 1.0);
 
 // Event that spawns a new flora at a map location.
-struct SpawnFlora(usize);
+struct SpawnFlora {
+    index: usize,
+    species: FloraSpecies,
+}
 
 // Plants should be able to grow
 // More grown plants should provide more foot
@@ -51,83 +116,113 @@ struct SpawnFlora(usize);
 // Bushes / trees: Spends time growing until fully grown, then will start producing food on a cycle.
 //  When eaten, reduces food but not growth.
 
-#[derive(Component)]
-struct Flora {
-    /// The speed at which the flora grows each cycle.
+#[derive(Component, Clone, Serialize, Deserialize)]
+pub(crate) struct Flora {
+    species: FloraSpecies,
+    /// The speed at which the flora grows each simulated hour.
     growing_speed: f32,
+    /// How much of its growing speed the flora manages to reach given its competition.
+    /// Range: 0.0..=1.0
+    vigour: f32,
     /// The current growth of the flora. Range: 0.0..=1.0
     current_growth: f32,
+    /// How many simulated hours the flora has been alive.
+    age: f32,
+    /// How many simulated hours the flora will live, if nothing else kills it first.
+    lifespan: f32,
+    /// How much drought stress the flora has built up.
+    drought: f32,
+}
+
+impl Flora {
+    /// The colour the flora is drawn with.
+    pub(crate) fn color(&self) -> Color {
+        get_data(&self.species).color
+    }
+}
+
+/// How much food a fully grown flora contains.
+const FOOD_PER_GROWTH: f32 = 100.0;
+
+/// Flora that are grazed below this growth will not recover and dies.
+const OVERGRAZED_GROWTH: f32 = 0.05;
+
+/// Converts the growth of a flora into the amount of food it provides.
+fn food_from_growth(growth: f32) -> f32 {
+    growth * FOOD_PER_GROWTH
+}
+
+/// Converts the amount of food left in a flora back into its growth.
+fn growth_from_food(content: f32) -> f32 {
+    (content / FOOD_PER_GROWTH).clamp(0.0, 1.0)
 }
 
 /// Based on local growing conditions, flora should grow this cycle.
-fn grow_flora(mut q: Query<&mut Flora>) {
-    for mut flora in &mut q {
+///
+/// As the flora grows, so does the amount of food it provides.
+/// Growing uses up some of the nutrients in the soil.
+fn grow_flora(
+    mut q: Query<(Entity, &mut Flora, &mut FoodSource, &MapIndex)>,
+    serials: Query<&Serial>,
+    mut map: ResMut<Map>,
+    clock: Res<FloraClock>,
+) {
+    let hours = clock.hours();
+
+    // Plants sharing a tile take up its nutrients one after the other, so always in the same order.
+    for (_, mut flora, mut food, index) in by_serial(&mut q, |(entity, ..)| *entity, &serials) {
         if flora.current_growth == 1.0 {
             continue;
         }
-        flora.current_growth = (flora.current_growth + flora.growing_speed).clamp(0.0, 1.0);
+        let nutrients = &mut map.nutrients[index.0];
+        let growth = (flora.growing_speed * flora.vigour * fertility(*nutrients) * hours)
+            .min(1.0 - flora.current_growth);
+
+        flora.current_growth += growth;
+        *nutrients = (*nutrients - growth * NUTRIENT_UPTAKE).max(0.0);
+        food.content = food_from_growth(flora.current_growth);
+    }
+}
+
+/// Food that has been eaten from a flora reduces its growth.
+///
+/// If the flora has been grazed too far down it dies.
+fn graze_flora(
+    mut q: Query<(Entity, &mut Flora, &FoodSource), Changed<FoodSource>>,
+    serials: Query<&Serial>,
+    mut died: EventWriter<FloraDied>,
+) {
+    for (entity, mut flora, food) in by_serial(&mut q, |(entity, ..)| *entity, &serials) {
+        flora.current_growth = growth_from_food(food.content);
+
+        if flora.current_growth <= OVERGRAZED_GROWTH {
+            died.send(FloraDied {
+                entity,
+                cause: DeathCause::Overgrazed,
+            });
+        }
     }
 }
 
 fn scale_flora(mut q: Query<(&mut Transform, &Flora)>) {
     for (mut transform, flora) in &mut q {
-        transform.scale = get_flora_scale(flora.current_growth);
+        transform.scale = get_flora_scale(flora.species, flora.current_growth);
     }
 }
 
-fn get_flora_scale(growth: f32) -> Vec3 {
-    Vec3::ONE * lerp_range(growth, &(0.1..2.0))
-}
-
-const THRESHOLD: f32 = 2.0;
-
-/// Based on the current growth of the flora, it should spread its seeds to nearby tiles.
-fn spread_flora(q: Query<(&Flora, &MapIndex)>, map: Res<Map>, mut event: EventWriter<SpawnFlora>) {
-    let existing_flora: HashMap<usize, &Flora> = q
-        .iter()
-        .map(|(flora, map_index)| (map_index.0, flora))
-        .collect();
-
-    // Find all growable tiles that does not yet have flora on them, but have flora on neighboring tiles.
-    let growable_tiles: Vec<usize> = map
-        .query(&TileQuery {
-            growable: Some(true),
-            ..default()
-        })
-        .iter()
-        .map(|index| **index)
-        .collect();
-
-    let mut spread_score: Vec<(usize, f32)> = vec![];
-    for tile in growable_tiles {
-        // If this tile already have a flora on it, set score to 0
-        if existing_flora.iter().any(|(index, _)| *index == tile) {
-            spread_score.push((tile, 0.0));
-        } else {
-            let mut sum = 0.0;
-            let neighbours = map.query_neighbours(
-                tile,
-                &TileQuery {
-                    growable: Some(true),
-                    ..default()
-                },
-            );
-            for neighbour in neighbours {
-                // Sum the total growth score of all neighbouring tiles
-                // Which informs the chance of spawning a new flora on that tile
-                if existing_flora.contains_key(&neighbour) {
-                    sum += &existing_flora[&neighbour].current_growth;
-                }
-            }
-            spread_score.push((tile, sum));
-        }
-    }
+fn get_flora_scale(species: FloraSpecies, growth: f32) -> Vec3 {
+    let data = get_data(&species);
+    Vec3::new(data.footprint, data.height, data.footprint) * lerp_range(growth, &(0.1..1.0))
+}
 
-    for (index, score) in spread_score {
-        if score > THRESHOLD {
-            event.send(SpawnFlora(index));
-        }
-    }
+/// The shape of a flora, before it is scaled by its growth.
+pub(crate) fn flora_mesh() -> Mesh {
+    Mesh::from(shape::Cube { size: 1.0 })
+}
+
+/// The shape of a water source.
+pub(crate) fn water_mesh() -> Mesh {
+    Mesh::from(shape::Cube { size: 0.2 })
 }
 
 fn spawn_flora(
@@ -136,33 +231,45 @@ fn spawn_flora(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut glob_rng: ResMut<GlobalRng>,
+    mut serials: ResMut<Serials>,
     map: Res<Map>,
 ) {
     let rng = glob_rng.get_mut();
 
     for event in event.iter() {
-        info!("Spawning flora for tile {:?}", event.0);
+        info!("Spawning {:?} for tile {:?}", event.species, event.index);
+        let data = get_data(&event.species);
         let flora = Flora {
-            growing_speed: rng.f32() * 0.01,
-            current_growth: rng.f32() * 0.5,
+            species: event.species,
+            growing_speed: lerp_range(rng.f32(), &data.growing_speed),
+            vigour: 1.0,
+            age: 0.0,
+            lifespan: lerp_range(rng.f32(), &data.lifespan),
+            drought: 0.0,
+            current_growth: OVERGRAZED_GROWTH + rng.f32() * 0.5,
         };
 
         cmd.spawn((
             PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Cube { size: 0.2 })),
-                material: materials.add(FOOD_COLOR.into()),
+                mesh: meshes.add(flora_mesh()),
+                material: materials.add(flora.color().into()),
                 transform: Transform {
-                    translation: map.index_to_world(event.0.into()),
-                    scale: get_flora_scale(flora.current_growth),
+                    // Several plants can share a tile, so spread them out within it.
+                    translation: map.index_to_world(event.index.into())
+                        + Vec3::new(rng.f32() - 0.5, 0.0, rng.f32() - 0.5)
+                            * map.settings.tile_size
+                            * 0.8,
+                    scale: get_flora_scale(flora.species, flora.current_growth),
                     ..default()
                 },
                 ..default()
             },
             FoodSource {
-                content: flora.current_growth * 100.0,
+                content: food_from_growth(flora.current_growth),
             },
             flora,
-            MapIndex(event.0),
+            MapIndex(event.index),
+            serials.next(),
             PickableBundle::default(),
         ));
     }
@@ -184,7 +291,8 @@ fn generate_flora(
     for index in tiles {
         if rng.f32() * 100.0 > 80.0 {
             info!("Generating flora for tile {:?}", index);
-            event.send(SpawnFlora(*index));
+            let species = ALL_SPECIES[rng.usize(0..ALL_SPECIES.len())];
+            event.send(SpawnFlora { index, species });
         }
     }
 }
@@ -195,21 +303,44 @@ fn spawn_water(
     mut cmd: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mut serials: ResMut<Serials>,
 ) {
     info!("spawning water");
-    for n in 0..map.data.len() {
-        if map.data[&n].tile_type == TileType::ShallowWater {
+    for n in 0..map.tile_count() {
+        // Rivers are long and narrow water sources running across the land.
+        if matches!(map.tile_types[n], TileType::ShallowWater | TileType::River) {
             cmd.spawn((
                 PbrBundle {
-                    mesh: meshes.add(Mesh::from(shape::Cube { size: 0.2 })),
+                    mesh: meshes.add(water_mesh()),
                     material: materials.add(WATER_COLOR.into()),
                     transform: Transform::from_translation(map.index_to_world(n.into())),
                     ..default()
                 },
                 WaterSource { content: 100.0 },
                 MapIndex(n),
+                serials.next(),
                 PickableBundle::default(),
             ));
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{food_from_growth, growth_from_food};
+
+    /// Food and growth should be two views of the same state.
+    #[test]
+    fn food_and_growth_round_trip() {
+        for growth in [0.0, 0.05, 0.5, 1.0] {
+            assert_eq!(growth_from_food(food_from_growth(growth)), growth);
+        }
+    }
+
+    /// Overeating a flora should never produce negative growth.
+    #[test]
+    fn growth_is_clamped() {
+        assert_eq!(growth_from_food(-10.0), 0.0);
+        assert_eq!(growth_from_food(food_from_growth(2.0)), 1.0);
+    }
+}
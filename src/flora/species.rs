@@ -0,0 +1,97 @@
+//! Collection of data describing the different species of flora.
+
+use std::ops::Range;
+
+use bevy::prelude::Color;
+use serde::{Deserialize, Serialize};
+
+/// Defines the data a species of flora has.
+pub(crate) struct FloraData {
+    /// The range the growing speed per simulated hour of a single plant is picked from.
+    pub(crate) growing_speed: Range<f32>,
+    /// How grown a plant must be before it starts to spread seeds.
+    pub(crate) maturity: f32,
+    /// Chance per simulated hour that a mature plant releases its seeds.
+    pub(crate) seeding_chance: f32,
+    /// How many seeds are released each time the plant spreads.
+    pub(crate) seed_count: u32,
+    /// How many tiles away from the plant a seed can land, before wind is applied.
+    pub(crate) dispersal_radius: f32,
+    /// Does this plant bear fruit that fauna carries the seeds of?
+    pub(crate) fruit: bool,
+    /// How tall the plant is when fully grown.
+    pub(crate) height: f32,
+    /// How much of a tile the plant takes up when fully grown.
+    pub(crate) footprint: f32,
+    /// The range the lifespan in simulated hours of a single plant is picked from.
+    pub(crate) lifespan: Range<f32>,
+    /// The driest tile the plant can live on without suffering from drought.
+    pub(crate) min_moisture: f32,
+    /// The coldest temperature the plant survives without risking frost damage.
+    pub(crate) frost_hardiness: f32,
+    pub(crate) color: Color,
+}
+
+// TODO: Move this to a file or asset
+
+const GRASS_DATA: FloraData = FloraData {
+    growing_speed: 0.02..0.1,
+    maturity: 0.5,
+    seeding_chance: 0.2,
+    seed_count: 3,
+    dispersal_radius: 1.5,
+    fruit: false,
+    height: 0.3,
+    footprint: 0.25,
+    lifespan: 75.0..150.0,
+    min_moisture: 0.3,
+    frost_hardiness: -2.0,
+    color: Color::rgb(0.0, 1.0, 0.0),
+};
+const BUSH_DATA: FloraData = FloraData {
+    growing_speed: 0.01..0.04,
+    maturity: 0.8,
+    seeding_chance: 0.08,
+    seed_count: 2,
+    dispersal_radius: 3.0,
+    fruit: true,
+    height: 1.0,
+    footprint: 0.6,
+    lifespan: 375.0..750.0,
+    min_moisture: 0.4,
+    frost_hardiness: -5.0,
+    color: Color::rgb(0.6, 0.1, 0.4),
+};
+const TREE_DATA: FloraData = FloraData {
+    growing_speed: 0.002..0.01,
+    maturity: 0.9,
+    seeding_chance: 0.04,
+    seed_count: 1,
+    dispersal_radius: 4.0,
+    fruit: false,
+    height: 3.0,
+    footprint: 1.0,
+    lifespan: 1500.0..3000.0,
+    min_moisture: 0.6,
+    frost_hardiness: -15.0,
+    color: Color::rgb(0.05, 0.35, 0.1),
+};
+
+pub(crate) const fn get_data(species: &FloraSpecies) -> &'static FloraData {
+    match species {
+        FloraSpecies::Grass => &GRASS_DATA,
+        FloraSpecies::Bush => &BUSH_DATA,
+        FloraSpecies::Tree => &TREE_DATA,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum FloraSpecies {
+    Grass,
+    Bush,
+    Tree,
+}
+
+/// All the species of flora that exists.
+pub(crate) const ALL_SPECIES: [FloraSpecies; 3] =
+    [FloraSpecies::Grass, FloraSpecies::Bush, FloraSpecies::Tree];
@@ -1,3 +1,8 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
 use agent::actions::{MoveAbility, MovementPath};
 use bevy::prelude::*;
 use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
@@ -6,12 +11,19 @@ use chronos::ChronoPlugin;
 use fauna::{FaunaPlugin, SpawnFauna};
 use flora::FloraPlugin;
 use map::{
+    generators::{self, MapGenerator, PerlinGenerator},
+    pathfinding::DiagonalMovement,
     plugin::MapPlugin,
-    tiles::{world_to_index, MapIndex},
+    tiles::MapIndex,
     Map, TileQuery,
 };
 use player::PlayerPlugin;
+use replay::{Replay, ReplayMode, ReplayPlugin, RunConfig};
 use resource::ResourcePlugin;
+use save::{Autosave, SavePlugin};
+use serial::Serials;
+use utils::{arg_value, parse_size};
+use weather::WeatherPlugin;
 
 mod agent;
 mod chronos;
@@ -19,8 +31,12 @@ mod fauna;
 mod flora;
 mod map;
 mod player;
+mod replay;
 mod resource;
+mod save;
+mod serial;
 mod utils;
+mod weather;
 
 #[derive(StageLabel)]
 enum AppStage {
@@ -31,8 +47,86 @@ enum AppStage {
 }
 
 fn main() {
-    App::new()
-        .add_startup_stage(AppStage::SeedMap, SystemStage::parallel())
+    // A run recorded with `--record <path>` can be played back with `--replay <path>`, stopping at
+    // a simulation tick with `--seek <tick>`. Played back runs are set up as they were recorded.
+    let replay = arg_value("--replay")
+        .map(|path| Replay::load(Path::new(&path)).expect("Could not load the replay"));
+    let config = match &replay {
+        Some(replay) => replay.config.clone(),
+        None => RunConfig {
+            // Runs are random, unless seeded with e.g. `--seed 42`.
+            seed: arg_value("--seed").map_or_else(
+                || GlobalRng::new().u64(..),
+                |seed| seed.parse().expect("Invalid seed"),
+            ),
+            // The map generator can be picked on the command line, e.g. `--map island`.
+            map: arg_value("--map"),
+            // Generated maps can be made larger with e.g. `--map-size 512x512`.
+            map_size: arg_value("--map-size")
+                .map(|size| parse_size(&size).expect("Invalid map size, expected e.g. 512x512"))
+                .unwrap_or((16, 16)),
+            // Diagonal steps can be set to `never`, `no-corner-cutting` or `always` with
+            // `--diagonals`.
+            diagonals: arg_value("--diagonals")
+                .map(|name| DiagonalMovement::from_name(&name).expect("Invalid diagonal movement"))
+                .unwrap_or_default(),
+        },
+    };
+    let replay_mode = match replay {
+        Some(replay) => Some(ReplayMode::Play {
+            replay,
+            seek: arg_value("--seek").map(|tick| tick.parse().expect("Invalid tick to seek to")),
+        }),
+        None => arg_value("--record").map(|path| ReplayMode::Record {
+            path: path.into(),
+            config: config.clone(),
+        }),
+    };
+    let generator = match &config.map {
+        Some(spec) => generators::from_spec(spec).expect("Invalid map generator"),
+        None => Arc::new(PerlinGenerator),
+    };
+    // Checkpoints are saved every few simulated days with `--autosave-days <days>`, keeping the
+    // last `--autosave-keep` (5) of them in `--autosave-dir` (`checkpoints`).
+    let autosave = arg_value("--autosave-days").map(|days| {
+        let keep = arg_value("--autosave-keep").map_or(5, |keep| {
+            keep.parse().expect("Invalid number of checkpoints to keep")
+        });
+        let directory = arg_value("--autosave-dir").unwrap_or_else(|| "checkpoints".into());
+        Autosave::new(
+            days.parse()
+                .expect("Invalid number of days between autosaves"),
+            keep,
+            directory,
+        )
+    });
+    // A saved simulation can be carried on from with `--checkpoint <path>`.
+    let start_from = arg_value("--checkpoint").map(PathBuf::from);
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins)
+        .add_plugin(DebugLinesPlugin::default());
+    // The generated map can be saved with `--export-map <path>`, as a `.png` or ASCII grid.
+    add_simulation(&mut app, &config, generator, arg_value("--export-map"))
+        .add_plugin(PlayerPlugin)
+        .add_plugin(SavePlugin {
+            autosave,
+            start_from,
+        })
+        .add_plugin(ReplayPlugin { mode: replay_mode })
+        .add_startup_system_to_stage(AppStage::SpawnMap, setup)
+        .add_system(draw_paths)
+        .run();
+}
+
+/// Adds everything that is simulated, as opposed to drawn or controlled by the player.
+fn add_simulation<'a>(
+    app: &'a mut App,
+    config: &RunConfig,
+    generator: Arc<dyn MapGenerator>,
+    export_path: Option<String>,
+) -> &'a mut App {
+    app.add_startup_stage(AppStage::SeedMap, SystemStage::parallel())
         .add_startup_stage_after(
             AppStage::SeedMap,
             AppStage::SpawnMap,
@@ -48,22 +142,41 @@ fn main() {
             AppStage::SpawnFauna,
             SystemStage::parallel(),
         )
-        .add_plugins(DefaultPlugins)
-        .add_plugin(RngPlugin::default())
-        .add_plugin(DebugLinesPlugin::default())
+        .add_plugin(RngPlugin::new().with_rng_seed(config.seed))
+        .init_resource::<Serials>()
         .add_plugin(MapPlugin {
             tile_size: 1.0,
-            map_size: (16, 16),
+            map_size: config.map_size,
+            generator,
+            export_path,
+            diagonals: config.diagonals,
         })
         .add_plugin(FaunaPlugin)
-        .add_plugin(FloraPlugin)
+        .add_plugin(FloraPlugin::default())
         .add_plugin(ResourcePlugin)
-        .add_plugin(PlayerPlugin)
         .add_plugin(ChronoPlugin)
-        .add_startup_system_to_stage(AppStage::SpawnMap, setup)
-        .add_system(draw_paths)
+        .add_plugin(WeatherPlugin)
         .add_system(update_tile_pos)
-        .run();
+}
+
+/// A simulation without a window, rendering or player, for tests to run frame by frame.
+///
+/// Player commands are still carried out, so runs can be recorded and replayed.
+#[cfg(test)]
+pub(crate) fn headless_simulation(config: &RunConfig, generator: Arc<dyn MapGenerator>) -> App {
+    use bevy::{asset::AssetPlugin, transform::TransformPlugin};
+
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin::default())
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .add_event::<player::PlayerCommand>()
+        .add_system_to_stage(CoreStage::PreUpdate, player::apply_player_commands);
+    add_simulation(&mut app, config, generator, None);
+    app
 }
 
 fn setup(
@@ -132,6 +245,8 @@ fn update_tile_pos(
     map: Res<Map>,
 ) {
     for (mut index, transform) in &mut q {
-        *index = world_to_index(&transform.translation(), &map);
+        if let Some(tile) = map.world_to_index(transform.translation()) {
+            *index = tile;
+        }
     }
 }
@@ -0,0 +1,276 @@
+//! Renders the map as square chunks of tiles, each drawn as a single mesh.
+//!
+//! The render entities only hold meshes built from the map. When tiles change, only the chunks
+//! they are in are rebuilt.
+
+use bevy::{
+    prelude::{
+        default, info, Assets, Color, Commands, Component, DespawnRecursiveExt, Entity, Handle,
+        Local, Mesh, PbrBundle, Query, Res, ResMut, StandardMaterial, Vec3,
+    },
+    render::{mesh::Indices, primitives::Aabb, render_resource::PrimitiveTopology},
+    utils::HashSet,
+};
+use bevy_mod_picking::PickableBundle;
+use bracket_pathfinding::prelude::Point;
+
+use super::{tiles::get_color, Map};
+
+/// How many tiles across each chunk is.
+pub(crate) const CHUNK_SIZE: i32 = 32;
+
+/// How far below sea level the tile columns reach.
+const TILE_BASE_DEPTH: f32 = 0.1;
+
+/// The directions to the sides of a tile, on the grid.
+const SIDES: [Point; 4] = [
+    Point { x: -1, y: 0 },
+    Point { x: 1, y: 0 },
+    Point { x: 0, y: -1 },
+    Point { x: 0, y: 1 },
+];
+
+/// A chunk of the map that is rendered as a single mesh.
+#[derive(Component)]
+pub(crate) struct MapChunk {
+    /// Row-major index of the chunk among all chunks of the map.
+    pub(crate) index: usize,
+}
+
+/// How many chunks the map is split into, across and down.
+pub(crate) fn chunk_dimensions(map: &Map) -> Point {
+    Point::new(
+        (map.settings.width + CHUNK_SIZE - 1) / CHUNK_SIZE,
+        (map.settings.height + CHUNK_SIZE - 1) / CHUNK_SIZE,
+    )
+}
+
+/// The chunk a tile belongs to.
+pub(crate) fn chunk_of(map: &Map, index: usize) -> usize {
+    let point = map.index_to_point(index);
+    let chunks = chunk_dimensions(map);
+    ((point.y / CHUNK_SIZE) * chunks.x + point.x / CHUNK_SIZE) as usize
+}
+
+/// The tiles that make up a chunk.
+pub(crate) fn chunk_tiles(map: &Map, chunk: usize) -> impl Iterator<Item = usize> + '_ {
+    let chunks = chunk_dimensions(map);
+    let corner = Point::new(
+        (chunk as i32 % chunks.x) * CHUNK_SIZE,
+        (chunk as i32 / chunks.x) * CHUNK_SIZE,
+    );
+
+    (corner.y..corner.y + CHUNK_SIZE).flat_map(move |y| {
+        (corner.x..corner.x + CHUNK_SIZE).filter_map(move |x| map.point_to_index(Point::new(x, y)))
+    })
+}
+
+/// Collects the faces of the tile columns into a mesh.
+#[derive(Default)]
+struct MeshBuilder {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    /// Adds a flat, four cornered face, facing the direction of the normal.
+    fn quad(&mut self, mut corners: [Vec3; 4], normal: Vec3, color: Color) {
+        // Faces are only visible from the side their corners wind counter-clockwise around.
+        let facing = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+        if facing.dot(normal) < 0.0 {
+            corners.reverse();
+        }
+
+        let first = self.positions.len() as u32;
+        for corner in corners {
+            self.positions.push(corner.to_array());
+            self.normals.push(normal.to_array());
+            self.colors.push(color.as_linear_rgba_f32());
+        }
+        self.indices
+            .extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+
+    fn build(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+/// Builds the mesh of a chunk.
+///
+/// Each tile is a column reaching from below sea level up to its surface. Only the top of the
+/// column and the sides standing above their neighbours are visible, so nothing else is added.
+pub(crate) fn chunk_mesh(map: &Map, chunk: usize) -> Mesh {
+    let mut builder = MeshBuilder::default();
+    let half = map.settings.tile_size / 2.0;
+
+    for index in chunk_tiles(map, chunk) {
+        let point = map.index_to_point(index);
+        let surface = map.index_to_world(index.into());
+        let color = get_color(map.tile_types[index]);
+
+        builder.quad(
+            [
+                surface + Vec3::new(-half, 0.0, -half),
+                surface + Vec3::new(-half, 0.0, half),
+                surface + Vec3::new(half, 0.0, half),
+                surface + Vec3::new(half, 0.0, -half),
+            ],
+            Vec3::Y,
+            color,
+        );
+
+        for side in SIDES {
+            let bottom = match map.point_to_index(point + side) {
+                Some(neighbour) => map.world_height(neighbour),
+                None => -TILE_BASE_DEPTH,
+            };
+            if bottom >= surface.y {
+                continue;
+            }
+
+            let normal = Vec3::new(side.x as f32, 0.0, side.y as f32);
+            let edge = surface + normal * half;
+            let along = Vec3::new(normal.z.abs(), 0.0, normal.x.abs()) * half;
+            let drop = Vec3::Y * (surface.y - bottom);
+            builder.quad(
+                [
+                    edge + along,
+                    edge - along,
+                    edge - along - drop,
+                    edge + along - drop,
+                ],
+                normal,
+                color,
+            );
+        }
+    }
+
+    builder.build()
+}
+
+/// Spawns the chunks of a newly created map, and rebuilds chunks whose tiles have changed.
+pub(super) fn update_chunks(
+    mut cmd: Commands,
+    map: Res<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    chunks: Query<(Entity, &MapChunk, &Handle<Mesh>)>,
+    // The revision of the map the chunks were last built from.
+    mut revision: Local<usize>,
+) {
+    if map.is_added() {
+        *revision = map.revision();
+        for (entity, _, _) in &chunks {
+            cmd.entity(entity).despawn_recursive();
+        }
+
+        // The colour of each tile is stored in the mesh.
+        let material = materials.add(Color::WHITE.into());
+        let count = chunk_dimensions(&map);
+        for chunk in 0..(count.x * count.y) as usize {
+            cmd.spawn((
+                PbrBundle {
+                    mesh: meshes.add(chunk_mesh(&map, chunk)),
+                    material: material.clone(),
+                    ..default()
+                },
+                MapChunk { index: chunk },
+                PickableBundle::default(),
+            ));
+        }
+        info!("Spawned {} map chunks", count.x * count.y);
+        return;
+    }
+
+    if *revision == map.revision() {
+        return;
+    }
+    // The sides of neighbouring tiles depend on the height of the changed tile as well. If the
+    // changes are no longer known, every chunk is rebuilt.
+    let dirty = map.changes_since(*revision).map(|changes| {
+        let mut dirty = HashSet::new();
+        for index in changes {
+            let point = map.index_to_point(index);
+            dirty.insert(chunk_of(&map, index));
+            for side in SIDES {
+                if let Some(neighbour) = map.point_to_index(point + side) {
+                    dirty.insert(chunk_of(&map, neighbour));
+                }
+            }
+        }
+        dirty
+    });
+    *revision = map.revision();
+
+    for (entity, chunk, mesh) in &chunks {
+        if dirty
+            .as_ref()
+            .is_none_or(|dirty| dirty.contains(&chunk.index))
+        {
+            let _ = meshes.set(mesh, chunk_mesh(&map, chunk.index));
+            // Recalculate the bounds, since the heights of the tiles might have changed.
+            cmd.entity(entity).remove::<Aabb>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Mesh;
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::map::{
+        generators::{FlatGenerator, MapGenerator},
+        plugin::MapSettings,
+        tiles::{get_data, TileType},
+    };
+
+    use super::{chunk_dimensions, chunk_mesh, chunk_of, chunk_tiles, CHUNK_SIZE};
+
+    #[test]
+    fn map_is_split_into_chunks() {
+        let settings = MapSettings {
+            width: CHUNK_SIZE + 8,
+            height: 20,
+            tile_size: 1.0,
+        };
+        let map = FlatGenerator::default().generate(&settings, 0);
+
+        assert_eq!(chunk_dimensions(&map), Point::new(2, 1));
+        assert_eq!(chunk_tiles(&map, 0).count(), (CHUNK_SIZE * 20) as usize);
+        assert_eq!(chunk_tiles(&map, 1).count(), 8 * 20);
+
+        // Every tile is in exactly the chunk it says it belongs to.
+        for chunk in 0..2 {
+            assert!(chunk_tiles(&map, chunk).all(|index| chunk_of(&map, index) == chunk));
+        }
+    }
+
+    #[test]
+    fn only_visible_faces_are_built() {
+        let settings = MapSettings {
+            width: 2,
+            height: 2,
+            tile_size: 1.0,
+        };
+        let mut map = FlatGenerator::default().generate(&settings, 0);
+
+        // Four tops, and two sides of each tile along the edge of the map.
+        let faces = |mesh: Mesh| mesh.count_vertices() / 4;
+        assert_eq!(faces(chunk_mesh(&map, 0)), 4 + 8);
+
+        // A raised tile shows its two sides facing the other tiles.
+        let mut hill = get_data(&TileType::Rock);
+        hill.elevation = 0.5;
+        map.set_tile(0, hill);
+        assert_eq!(faces(chunk_mesh(&map, 0)), 4 + 8 + 2);
+    }
+}
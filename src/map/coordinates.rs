@@ -0,0 +1,202 @@
+//! Conversions between the three ways of addressing a tile: its index, its point on the grid and
+//! its position in the world.
+//!
+//! Indices are row-major (`y * width + x`), the same as `Algorithm2D`. The x of a point runs along
+//! the world X axis and its y along the world Z axis, with the map centred on the world origin.
+
+use bevy::prelude::Vec3;
+use bracket_pathfinding::prelude::{Algorithm2D, Point};
+
+use super::{tiles::MapIndex, Map};
+
+impl Map {
+    /// The point on the grid of the tile at an index.
+    pub(crate) fn index_to_point(&self, index: usize) -> Point {
+        self.index_to_point2d(index)
+    }
+
+    /// The index of the tile at a point, if the point is on the map.
+    pub(crate) fn point_to_index(&self, point: Point) -> Option<usize> {
+        if self.in_bounds(point) {
+            Some(self.point2d_to_index(point))
+        } else {
+            None
+        }
+    }
+
+    /// The world-position of the centre of the tile at a point, at sea level.
+    pub(crate) fn point_to_world(&self, point: Point) -> Vec3 {
+        let tile_size = self.settings.tile_size;
+        let origin = self.world_origin();
+        Vec3::new(
+            origin.x + (point.x as f32 + 0.5) * tile_size,
+            0.0,
+            origin.z + (point.y as f32 + 0.5) * tile_size,
+        )
+    }
+
+    /// The point of the tile covering a world-position, which may be outside the map.
+    pub(crate) fn world_to_point(&self, world: Vec3) -> Point {
+        let tile_size = self.settings.tile_size;
+        let origin = self.world_origin();
+        Point::new(
+            ((world.x - origin.x) / tile_size).floor() as i32,
+            ((world.z - origin.z) / tile_size).floor() as i32,
+        )
+    }
+
+    /// Converts from a tile-index to the world-position of the tile's surface.
+    pub(crate) fn index_to_world(&self, index: MapIndex) -> Vec3 {
+        let mut world = self.point_to_world(self.index_to_point(index.0));
+        world.y = self.world_height(index.0);
+        world
+    }
+
+    /// The tile covering a world-position, if the position is on the map.
+    pub(crate) fn world_to_index(&self, world: Vec3) -> Option<MapIndex> {
+        self.point_to_index(self.world_to_point(world))
+            .map(MapIndex)
+    }
+
+    /// The world-position of the corner of the map at point `0, 0`.
+    fn world_origin(&self) -> Vec3 {
+        let tile_size = self.settings.tile_size;
+        Vec3::new(
+            -(self.settings.width as f32) * tile_size / 2.0,
+            0.0,
+            -(self.settings.height as f32) * tile_size / 2.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Vec3;
+    use bracket_pathfinding::prelude::{DistanceAlg::Chebyshev, Point};
+    use proptest::prelude::*;
+
+    use crate::map::{
+        generators::{FlatGenerator, MapGenerator},
+        plugin::MapSettings,
+        tiles::MapIndex,
+        Map,
+    };
+
+    fn flat_map(width: i32, height: i32, tile_size: f32) -> Map {
+        let settings = MapSettings {
+            width,
+            height,
+            tile_size,
+        };
+        FlatGenerator::default().generate(&settings, 0)
+    }
+
+    /// The width, height and tile size of a map of random shape, along with an index on it.
+    fn map_and_index() -> impl Strategy<Value = ((i32, i32, f32), usize)> {
+        (1..40, 1..40, 0.25f32..4.0).prop_flat_map(|(width, height, tile_size)| {
+            (
+                Just((width, height, tile_size)),
+                0..(width * height) as usize,
+            )
+        })
+    }
+
+    #[test]
+    fn indices_are_row_major() {
+        // 0 1 2
+        // 3 4 5
+        let map = flat_map(3, 2, 2.0);
+
+        assert_eq!(map.index_to_point(4), Point::new(1, 1));
+        assert_eq!(map.point_to_index(Point::new(2, 0)), Some(2));
+        assert_eq!(map.point_to_index(Point::new(3, 0)), None);
+        assert_eq!(map.point_to_index(Point::new(0, -1)), None);
+
+        // The map is centred on the world origin.
+        assert_eq!(
+            map.point_to_world(Point::new(0, 0)),
+            Vec3::new(-2.0, 0.0, -1.0)
+        );
+        assert_eq!(
+            map.point_to_world(Point::new(2, 1)),
+            Vec3::new(2.0, 0.0, 1.0)
+        );
+        assert_eq!(
+            map.world_to_index(Vec3::new(-2.5, 0.0, 0.5)),
+            Some(MapIndex(3))
+        );
+        assert_eq!(map.world_to_index(Vec3::new(-2.5, 0.0, 2.5)), None);
+    }
+
+    proptest! {
+        #[test]
+        fn index_point_round_trip(((width, height, tile_size), index) in map_and_index()) {
+            let map = flat_map(width, height, tile_size);
+            let point = map.index_to_point(index);
+            prop_assert_eq!(map.point_to_index(point), Some(index));
+        }
+
+        #[test]
+        fn index_world_round_trip(
+            ((width, height, tile_size), index) in map_and_index(),
+            offset_x in -0.49f32..0.49,
+            offset_z in -0.49f32..0.49,
+        ) {
+            let map = flat_map(width, height, tile_size);
+            let world = map.index_to_world(MapIndex(index));
+            prop_assert_eq!(map.world_to_index(world), Some(MapIndex(index)));
+
+            // Anywhere on the tile belongs to the same index.
+            let offset = Vec3::new(offset_x, 0.0, offset_z) * map.settings.tile_size;
+            prop_assert_eq!(map.world_to_index(world + offset), Some(MapIndex(index)));
+        }
+
+        #[test]
+        fn outside_the_map_has_no_index(
+            ((width, height, tile_size), index) in map_and_index(),
+            direction in 0..4usize,
+        ) {
+            let map = flat_map(width, height, tile_size);
+            let point = map.index_to_point(index);
+            let outside = match direction {
+                0 => Point::new(-1, point.y),
+                1 => Point::new(map.settings.width, point.y),
+                2 => Point::new(point.x, -1),
+                _ => Point::new(point.x, map.settings.height),
+            };
+
+            prop_assert_eq!(map.point_to_index(outside), None);
+            prop_assert_eq!(map.world_to_index(map.point_to_world(outside)), None);
+        }
+
+        #[test]
+        fn neighbours_are_adjacent(((width, height, tile_size), index) in map_and_index()) {
+            let map = flat_map(width, height, tile_size);
+            let point = map.index_to_point(index);
+            let neighbours = map.get_neighbours(index);
+
+            // Every tile within one step is a neighbour, and nothing else.
+            let mut expected = 0;
+            for y in point.y - 1..=point.y + 1 {
+                for x in point.x - 1..=point.x + 1 {
+                    let other = Point::new(x, y);
+                    if other != point && map.point_to_index(other).is_some() {
+                        expected += 1;
+                    }
+                }
+            }
+            prop_assert_eq!(neighbours.len(), expected);
+
+            for neighbour in neighbours {
+                let other = map.index_to_point(neighbour);
+                prop_assert_eq!(Chebyshev.distance2d(point, other), 1.0);
+                prop_assert!(map.get_neighbours(neighbour).contains(&index));
+
+                // Neighbours are also neighbours in the world.
+                let step = map.index_to_world(MapIndex(neighbour)) - map.index_to_world(MapIndex(index));
+                prop_assert!(step.x.abs() <= map.settings.tile_size * 1.01);
+                prop_assert!(step.z.abs() <= map.settings.tile_size * 1.01);
+            }
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Flow fields leading from every tile on the map to the nearest of a set of sources.
+//!
+//! Instead of each agent searching for its own path to e.g. water, the cost of reaching the
+//! nearest source is found once for the whole map. Agents then only look up which way to go.
+
+use std::{
+    collections::{BTreeMap, BinaryHeap},
+    marker::PhantomData,
+};
+
+use bevy::{
+    prelude::{Added, Component, Entity, Query, RemovedComponents, Res, ResMut, Resource},
+    utils::HashMap,
+};
+use bracket_pathfinding::prelude::BaseMap;
+
+use super::{
+    pathfinding::{DiagonalMovement, Locomotion, PathingMap, Visit, ALL_LOCOMOTIONS},
+    tiles::MapIndex,
+    Map,
+};
+
+/// The cost of reaching the nearest source from every tile, and which way to go to get there.
+pub(crate) struct FlowField {
+    cost: Vec<f32>,
+    /// The next tile on the way to the nearest source.
+    toward: Vec<Option<usize>>,
+    /// The source each tile is closest to.
+    nearest: Vec<Option<usize>>,
+}
+
+impl FlowField {
+    /// Creates the field leading to the sources, as seen by the pathing map.
+    pub(crate) fn new(pathing: &PathingMap, sources: impl IntoIterator<Item = usize>) -> Self {
+        let count = pathing.map().tile_count();
+        let mut field = Self {
+            cost: vec![f32::INFINITY; count],
+            toward: vec![None; count],
+            nearest: vec![None; count],
+        };
+
+        let mut queue = BinaryHeap::new();
+        for source in sources {
+            field.set_source(source, &mut queue);
+        }
+        field.flow(pathing, queue);
+        field
+    }
+
+    /// The cost of reaching the nearest source, if any can be reached.
+    pub(crate) fn distance(&self, index: usize) -> Option<f32> {
+        Some(self.cost[index]).filter(|cost| cost.is_finite())
+    }
+
+    /// The tile of the nearest source, if any can be reached.
+    pub(crate) fn nearest_source(&self, index: usize) -> Option<usize> {
+        self.nearest[index]
+    }
+
+    /// The next tile on the way to the nearest source. None when at the source.
+    pub(crate) fn next_step(&self, index: usize) -> Option<usize> {
+        self.toward[index]
+    }
+
+    /// The tiles leading to the nearest source, from the tile itself to the source.
+    pub(crate) fn path(&self, index: usize) -> Option<Vec<usize>> {
+        self.distance(index)?;
+
+        let mut path = vec![index];
+        while let Some(next) = self.next_step(*path.last().unwrap()) {
+            path.push(next);
+        }
+        Some(path)
+    }
+
+    /// Adds a source, only updating the tiles that are now closer to it.
+    pub(crate) fn add_source(&mut self, pathing: &PathingMap, index: usize) {
+        let mut queue = BinaryHeap::new();
+        self.set_source(index, &mut queue);
+        self.flow(pathing, queue);
+    }
+
+    /// Removes a source, only updating the tiles that were closest to it.
+    pub(crate) fn remove_source(&mut self, pathing: &PathingMap, index: usize) {
+        let orphans: Vec<usize> = (0..self.cost.len())
+            .filter(|tile| self.nearest[*tile] == Some(index))
+            .collect();
+        for tile in &orphans {
+            self.cost[*tile] = f32::INFINITY;
+            self.toward[*tile] = None;
+            self.nearest[*tile] = None;
+        }
+
+        // Flow back in from the tiles around them, which still lead to other sources.
+        let mut queue = BinaryHeap::new();
+        for tile in orphans {
+            for (neighbour, _) in pathing.get_available_exits(tile) {
+                if self.cost[neighbour].is_finite() {
+                    queue.push(Visit {
+                        cost: self.cost[neighbour],
+                        index: neighbour,
+                    });
+                }
+            }
+        }
+        self.flow(pathing, queue);
+    }
+
+    fn set_source(&mut self, index: usize, queue: &mut BinaryHeap<Visit>) {
+        self.cost[index] = 0.0;
+        self.toward[index] = None;
+        self.nearest[index] = Some(index);
+        queue.push(Visit { cost: 0.0, index });
+    }
+
+    /// Spreads out from the queued tiles, lowering the cost of any tile it can.
+    ///
+    /// Steps cost the same in both directions, so the cost of reaching a tile from the sources is
+    /// the same as reaching the sources from the tile. Tiles as close to several neighbours lead to
+    /// the lowest of them, so the field comes out the same however the sources were added.
+    fn flow(&mut self, pathing: &PathingMap, mut queue: BinaryHeap<Visit>) {
+        while let Some(Visit { cost, index }) = queue.pop() {
+            if cost > self.cost[index] {
+                continue;
+            }
+            for (neighbour, step) in pathing.get_available_exits(index) {
+                let cost = cost + step;
+                let closer = cost < self.cost[neighbour];
+                let tied = cost == self.cost[neighbour]
+                    && match self.toward[neighbour] {
+                        Some(toward) if toward == index => {
+                            self.nearest[neighbour] != self.nearest[index]
+                        }
+                        Some(toward) => index < toward,
+                        None => false,
+                    };
+                if closer || tied {
+                    self.cost[neighbour] = cost;
+                    self.toward[neighbour] = Some(index);
+                    self.nearest[neighbour] = self.nearest[index];
+                    queue.push(Visit {
+                        cost,
+                        index: neighbour,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Flow fields leading to the nearest entity with the component `T`, one for each locomotion.
+///
+/// The sources are assumed to stay on the tile they were added on.
+#[derive(Resource)]
+pub(crate) struct SourceFlow<T> {
+    fields: HashMap<Locomotion, FlowField>,
+    /// The tile of each source.
+    sources: HashMap<Entity, usize>,
+    /// How many sources are on each tile.
+    tiles: BTreeMap<usize, usize>,
+    /// The revision of the map the fields were built from.
+    revision: Option<usize>,
+    marker: PhantomData<T>,
+}
+
+impl<T> Default for SourceFlow<T> {
+    fn default() -> Self {
+        Self {
+            fields: HashMap::default(),
+            sources: HashMap::default(),
+            tiles: BTreeMap::new(),
+            revision: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> SourceFlow<T> {
+    /// The field as seen by agents getting around in the given way.
+    pub(crate) fn field(&self, locomotion: Locomotion) -> Option<&FlowField> {
+        self.fields.get(&locomotion)
+    }
+}
+
+/// Keeps the flow fields up to date as sources appear and vanish, and as the map changes.
+pub(crate) fn update_source_flow<T: Component>(
+    mut flow: ResMut<SourceFlow<T>>,
+    map: Res<Map>,
+    diagonals: Res<DiagonalMovement>,
+    added: Query<(Entity, &MapIndex), Added<T>>,
+    removed: RemovedComponents<T>,
+) {
+    // Any replaced tile might change the cost of every path.
+    let rebuild = map.is_added() || flow.revision != Some(map.revision()) || diagonals.is_changed();
+    let flow = &mut *flow;
+
+    let mut vacated = Vec::new();
+    for entity in removed.iter() {
+        if let Some(index) = flow.sources.remove(&entity) {
+            let count = flow.tiles.get_mut(&index).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                flow.tiles.remove(&index);
+                vacated.push(index);
+            }
+        }
+    }
+    let mut occupied = Vec::new();
+    for (entity, index) in &added {
+        // Sources counted already, e.g. when the simulation was loaded, are only counted once.
+        if flow.sources.insert(entity, index.0).is_some() {
+            continue;
+        }
+        let count = flow.tiles.entry(index.0).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            occupied.push(index.0);
+        }
+    }
+
+    if rebuild {
+        flow.revision = Some(map.revision());
+        for locomotion in ALL_LOCOMOTIONS {
+            let pathing = map.pathing(locomotion).with_diagonals(*diagonals);
+            let field = FlowField::new(&pathing, flow.tiles.keys().copied());
+            flow.fields.insert(locomotion, field);
+        }
+        return;
+    }
+
+    for (locomotion, field) in flow.fields.iter_mut() {
+        let pathing = map.pathing(*locomotion).with_diagonals(*diagonals);
+        for index in &vacated {
+            field.remove_source(&pathing, *index);
+        }
+        for index in &occupied {
+            field.add_source(&pathing, *index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::map::{
+        generators::{GridGenerator, MapGenerator},
+        io::parse_ascii,
+        pathfinding::Locomotion,
+        plugin::MapSettings,
+        Map,
+    };
+
+    use super::FlowField;
+
+    fn grid(text: &str) -> Map {
+        let settings = MapSettings {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+        };
+        GridGenerator::new(parse_ascii(text).unwrap()).generate(&settings, 0)
+    }
+
+    fn tile(map: &Map, x: i32, y: i32) -> usize {
+        map.point_to_index(Point::new(x, y)).unwrap()
+    }
+
+    const MAP: &str = "
+        ........
+        .####...
+        .#......
+        .#.###..
+        ........
+        ###.....
+        ..#.....
+        ";
+
+    #[test]
+    fn flows_to_the_nearest_reachable_source() {
+        let map = grid(MAP);
+        let pathing = map.pathing(Locomotion::Walker);
+        // The walled-in source is closest as the crow flies, but can't be reached.
+        let (walled, open) = (tile(&map, 0, 6), tile(&map, 7, 0));
+        let field = FlowField::new(&pathing, [walled, open]);
+
+        let start = tile(&map, 2, 4);
+        assert_eq!(field.nearest_source(start), Some(open));
+        let path = field.path(start).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&open));
+
+        // Every step leads to a neighbour, and the cost only goes down along the way.
+        for pair in path.windows(2) {
+            assert!(map.get_neighbours(pair[0]).contains(&pair[1]));
+            assert!(field.distance(pair[1]) < field.distance(pair[0]));
+        }
+        assert_eq!(field.distance(tile(&map, 1, 6)), Some(1.0));
+        assert_eq!(field.nearest_source(tile(&map, 1, 6)), Some(walled));
+    }
+
+    #[test]
+    fn incremental_updates_match_a_rebuild() {
+        let map = grid(MAP);
+        let pathing = map.pathing(Locomotion::Walker);
+        let sources = [tile(&map, 7, 0), tile(&map, 2, 2), tile(&map, 5, 6)];
+
+        let mut field = FlowField::new(&pathing, [sources[0]]);
+        field.add_source(&pathing, sources[1]);
+        field.add_source(&pathing, sources[2]);
+        field.remove_source(&pathing, sources[1]);
+        let rebuilt = FlowField::new(&pathing, [sources[0], sources[2]]);
+
+        for index in 0..map.tile_count() {
+            assert_eq!(field.distance(index), rebuilt.distance(index));
+            assert_eq!(field.nearest_source(index), rebuilt.nearest_source(index));
+            assert_eq!(field.next_step(index), rebuilt.next_step(index));
+        }
+
+        // Without any sources, nothing can be reached.
+        field.remove_source(&pathing, sources[0]);
+        field.remove_source(&pathing, sources[2]);
+        assert!((0..map.tile_count()).all(|index| field.path(index).is_none()));
+    }
+}
@@ -0,0 +1,52 @@
+use crate::map::{
+    plugin::MapSettings,
+    tiles::{get_data, TileType},
+    Map,
+};
+
+use super::{build_map, MapGenerator};
+
+/// Generates a completely flat map where every tile is of the same type.
+pub(crate) struct FlatGenerator {
+    pub(crate) tile_type: TileType,
+}
+
+impl Default for FlatGenerator {
+    fn default() -> Self {
+        Self {
+            tile_type: TileType::Grass,
+        }
+    }
+}
+
+impl MapGenerator for FlatGenerator {
+    fn generate(&self, settings: &MapSettings, _seed: u32) -> Map {
+        build_map(settings, |_, _| get_data(&self.tile_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{generators::MapGenerator, plugin::MapSettings, tiles::TileType};
+
+    use super::FlatGenerator;
+
+    #[test]
+    fn all_tiles_are_the_same() {
+        let settings = MapSettings {
+            width: 4,
+            height: 3,
+            tile_size: 1.0,
+        };
+        let map = FlatGenerator {
+            tile_type: TileType::Sand,
+        }
+        .generate(&settings, 0);
+
+        assert_eq!(map.tile_count(), 12);
+        assert!(map
+            .tile_types
+            .iter()
+            .all(|tile_type| *tile_type == TileType::Sand));
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+
+use crate::map::{
+    io::{load_rows, MapFormatError, TileRows},
+    plugin::MapSettings,
+    tiles::get_data,
+    Map,
+};
+
+use super::{build_map, MapGenerator};
+
+/// Generates a map from a hand-authored grid of tiles.
+///
+/// The grid is either ASCII, where each line is a row of the map and each symbol a tile, or an
+/// image where each pixel is a tile coloured by its type. See `map::io`.
+pub(crate) struct GridGenerator {
+    rows: TileRows,
+}
+
+impl GridGenerator {
+    /// Creates a generator from rows of tiles, which must all be of the same length.
+    pub(crate) fn new(rows: TileRows) -> Self {
+        Self { rows }
+    }
+
+    /// Loads the grid from a file, as an image if it's a `.png` and as ASCII otherwise.
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self, MapFormatError> {
+        load_rows(path).map(Self::new)
+    }
+}
+
+impl MapGenerator for GridGenerator {
+    fn generate(&self, settings: &MapSettings, _seed: u32) -> Map {
+        let settings = MapSettings {
+            width: self.rows[0].len() as i32,
+            height: self.rows.len() as i32,
+            ..settings.clone()
+        };
+
+        build_map(&settings, |x, y| {
+            get_data(&self.rows[y as usize][x as usize])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::map::{
+        generators::MapGenerator,
+        io::{parse_ascii, MapFormatError},
+        plugin::MapSettings,
+        tiles::TileType,
+    };
+
+    use super::GridGenerator;
+
+    const SETTINGS: MapSettings = MapSettings {
+        width: 16,
+        height: 16,
+        tile_size: 1.0,
+    };
+
+    #[test]
+    fn grid_defines_the_map() {
+        let rows = parse_ascii(
+            "
+            ..~#
+            ,^*=
+            ",
+        )
+        .unwrap();
+        let map = GridGenerator::new(rows).generate(&SETTINGS, 0);
+
+        assert_eq!(map.settings.width, 4);
+        assert_eq!(map.settings.height, 2);
+
+        let tile_at = |x, y| map.tile_types[map.point_to_index(Point::new(x, y)).unwrap()];
+        assert_eq!(tile_at(0, 0), TileType::Grass);
+        assert_eq!(tile_at(2, 0), TileType::ShallowWater);
+        assert_eq!(tile_at(3, 0), TileType::DeepWater);
+        assert_eq!(tile_at(0, 1), TileType::Sand);
+        assert_eq!(tile_at(1, 1), TileType::Rock);
+        assert_eq!(tile_at(2, 1), TileType::Snow);
+        assert_eq!(tile_at(3, 1), TileType::River);
+    }
+
+    #[test]
+    fn invalid_grids() {
+        assert!(matches!(
+            GridGenerator::open("missing-grid.txt"),
+            Err(MapFormatError::Io(_))
+        ));
+    }
+}
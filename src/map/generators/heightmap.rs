@@ -0,0 +1,82 @@
+use std::path::Path;
+
+use image::{GrayImage, ImageResult};
+
+use crate::map::{hydrology::apply_hydrology, plugin::MapSettings, terrain::TerrainNoise, Map};
+
+use super::{build_map, MapGenerator};
+
+/// Generates a map from a grayscale heightmap, where black is the deepest sea and white the
+/// highest mountain. Each pixel becomes one tile.
+///
+/// The climate is still generated from noise.
+pub(crate) struct HeightmapGenerator {
+    heightmap: GrayImage,
+}
+
+impl HeightmapGenerator {
+    pub(crate) fn from_image(heightmap: GrayImage) -> Self {
+        Self { heightmap }
+    }
+
+    /// Loads the heightmap from an image file, such as a PNG.
+    pub(crate) fn open(path: impl AsRef<Path>) -> ImageResult<Self> {
+        Ok(Self::from_image(image::open(path)?.into_luma8()))
+    }
+}
+
+impl MapGenerator for HeightmapGenerator {
+    fn generate(&self, settings: &MapSettings, seed: u32) -> Map {
+        let settings = MapSettings {
+            width: self.heightmap.width() as i32,
+            height: self.heightmap.height() as i32,
+            ..settings.clone()
+        };
+        let noise = TerrainNoise::new(seed, settings.width, settings.height);
+
+        let mut map = build_map(&settings, |x, y| {
+            let mut sample = noise.sample(x, y);
+            let luma = self.heightmap.get_pixel(x as u32, y as u32).0[0];
+            sample.elevation = luma as f32 / u8::MAX as f32 * 2.0 - 1.0;
+            sample.to_tile()
+        });
+        apply_hydrology(&mut map);
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use crate::map::{generators::MapGenerator, plugin::MapSettings, tiles::TileType};
+
+    use super::HeightmapGenerator;
+
+    #[test]
+    fn pixels_become_tiles() {
+        let mut heightmap = GrayImage::from_pixel(6, 4, Luma([0]));
+        heightmap.put_pixel(3, 2, Luma([255]));
+
+        let settings = MapSettings {
+            width: 16,
+            height: 16,
+            tile_size: 1.0,
+        };
+        let map = HeightmapGenerator::from_image(heightmap).generate(&settings, 0);
+
+        assert_eq!(map.settings.width, 6);
+        assert_eq!(map.settings.height, 4);
+
+        for index in 0..map.tile_count() {
+            let point = map.index_to_point(index);
+            if point.x == 3 && point.y == 2 {
+                assert_eq!(map.elevation[index], 1.0);
+                assert_ne!(map.tile_types[index], TileType::DeepWater);
+            } else {
+                assert_eq!(map.tile_types[index], TileType::DeepWater);
+            }
+        }
+    }
+}
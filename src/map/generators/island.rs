@@ -0,0 +1,64 @@
+use crate::map::{hydrology::apply_hydrology, plugin::MapSettings, terrain::TerrainNoise, Map};
+
+use super::{build_map, MapGenerator};
+
+/// How far out from the centre the land reaches, relative to the size of the map.
+const ISLAND_RADIUS: f32 = 0.8;
+
+/// Generates a single island surrounded by sea.
+pub(crate) struct IslandGenerator;
+
+impl MapGenerator for IslandGenerator {
+    fn generate(&self, settings: &MapSettings, seed: u32) -> Map {
+        let noise = TerrainNoise::new(seed, settings.width, settings.height);
+
+        let mut map = build_map(settings, |x, y| {
+            let mut sample = noise.sample(x, y);
+
+            // Distance from the centre of the map, where the edges are at 1.0.
+            let dx = x as f32 / (settings.width - 1).max(1) as f32 * 2.0 - 1.0;
+            let dy = y as f32 / (settings.height - 1).max(1) as f32 * 2.0 - 1.0;
+            let distance = (dx * dx + dy * dy).sqrt() / ISLAND_RADIUS;
+
+            // Raise the centre into land, and sink everything near the edges into the sea.
+            sample.elevation =
+                ((sample.elevation + 1.0) / 2.0 * (1.0 - distance) * 2.0 - 0.5).clamp(-1.0, 1.0);
+            sample.to_tile()
+        });
+        apply_hydrology(&mut map);
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::map::{generators::MapGenerator, plugin::MapSettings, tiles::TileType};
+
+    use super::IslandGenerator;
+
+    /// The island should always be surrounded by deep water.
+    #[test]
+    fn edges_are_sea() {
+        let settings = MapSettings {
+            width: 24,
+            height: 16,
+            tile_size: 1.0,
+        };
+
+        for seed in 0..5 {
+            let map = IslandGenerator.generate(&settings, seed);
+            for index in 0..map.tile_count() {
+                let point = map.index_to_point(index);
+                let edge = point.x == 0
+                    || point.y == 0
+                    || point.x == settings.width - 1
+                    || point.y == settings.height - 1;
+                if edge {
+                    assert_eq!(map.tile_types[index], TileType::DeepWater, "{point:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Different ways of generating the tiles of a map.
+
+use std::sync::Arc;
+
+use super::{plugin::MapSettings, tiles::TileData, Map};
+
+pub(crate) use self::{
+    flat::FlatGenerator, grid::GridGenerator, heightmap::HeightmapGenerator,
+    island::IslandGenerator, perlin::PerlinGenerator,
+};
+
+mod flat;
+mod grid;
+mod heightmap;
+mod island;
+mod perlin;
+
+/// Something that can generate the tiles of a map.
+pub(crate) trait MapGenerator: Send + Sync {
+    /// Generates a new map.
+    ///
+    /// Generators that define the size of the map themselves ignore the size in the settings.
+    fn generate(&self, settings: &MapSettings, seed: u32) -> Map;
+}
+
+/// Creates a generator from a textual description, such as a command line argument.
+///
+/// Available generators are `perlin`, `island`, `flat`, `grid:<path>` and `heightmap:<path>`,
+/// where the grid file contains tile symbols or tile colours (see `map::io`) and the heightmap is a grayscale image.
+pub(crate) fn from_spec(spec: &str) -> Result<Arc<dyn MapGenerator>, String> {
+    let (kind, path) = match spec.split_once(':') {
+        Some((kind, path)) => (kind, Some(path)),
+        None => (spec, None),
+    };
+
+    match (kind, path) {
+        ("perlin", None) => Ok(Arc::new(PerlinGenerator)),
+        ("island", None) => Ok(Arc::new(IslandGenerator)),
+        ("flat", None) => Ok(Arc::new(FlatGenerator::default())),
+        ("grid", Some(path)) => {
+            let generator = GridGenerator::open(path).map_err(|e| format!("{path}: {e}"))?;
+            Ok(Arc::new(generator))
+        }
+        ("heightmap", Some(path)) => {
+            let generator = HeightmapGenerator::open(path).map_err(|e| format!("{path}: {e}"))?;
+            Ok(Arc::new(generator))
+        }
+        _ => Err(format!("Unknown map generator: {spec}")),
+    }
+}
+
+/// Builds a map by asking for the data of each tile, row by row.
+fn build_map(settings: &MapSettings, mut tile: impl FnMut(i32, i32) -> TileData) -> Map {
+    let tiles = (0..settings.height)
+        .flat_map(|y| (0..settings.width).map(move |x| (x, y)))
+        .map(|(x, y)| tile(x, y));
+
+    Map::from_tiles(settings.clone(), tiles)
+}
@@ -0,0 +1,17 @@
+use crate::map::{hydrology::apply_hydrology, plugin::MapSettings, terrain::TerrainNoise, Map};
+
+use super::{build_map, MapGenerator};
+
+/// Generates natural looking land from layered perlin noise, with lakes and rivers.
+pub(crate) struct PerlinGenerator;
+
+impl MapGenerator for PerlinGenerator {
+    fn generate(&self, settings: &MapSettings, seed: u32) -> Map {
+        let noise = TerrainNoise::new(seed, settings.width, settings.height);
+
+        let mut map = build_map(settings, |x, y| noise.sample(x, y).to_tile());
+        apply_hydrology(&mut map);
+
+        map
+    }
+}
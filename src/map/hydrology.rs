@@ -0,0 +1,289 @@
+//! Shapes lakes and rivers into the terrain, based on how water would flow across it.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bracket_pathfinding::prelude::{Algorithm2D, DistanceAlg::Chebyshev};
+
+use super::{
+    tiles::{get_data, TileType},
+    Map,
+};
+
+/// Basins shallower than this are not deep enough to hold a lake.
+const LAKE_MIN_DEPTH: f32 = 0.01;
+/// Lakes deeper than this become deep water.
+const LAKE_DEEP_DEPTH: f32 = 0.15;
+/// Rivers only spring from land at least this high up.
+const RIVER_SOURCE_ELEVATION: f32 = 0.4;
+/// Rivers only spring from land at least this wet.
+const RIVER_SOURCE_MOISTURE: f32 = 0.4;
+/// How many tiles of map there are for every river.
+const TILES_PER_RIVER: usize = 128;
+/// The closest two river sources can be to each other.
+const RIVER_SOURCE_SPACING: f32 = 4.0;
+/// The moisture of tiles along the banks of a river.
+const RIVERBANK_MOISTURE: f32 = 0.8;
+
+/// A tile waiting to be flooded, ordered so the lowest tile is flooded first.
+struct Flood {
+    level: f32,
+    index: usize,
+}
+
+impl PartialEq for Flood {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Flood {}
+
+impl PartialOrd for Flood {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Flood {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, since the heap pops the greatest element first.
+        other
+            .level
+            .total_cmp(&self.level)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+/// How water drains across the map.
+struct Drainage {
+    /// The level water would rise to on each tile before spilling over.
+    level: Vec<f32>,
+    /// Which tile the water on each tile drains into, if any.
+    downstream: Vec<Option<usize>>,
+}
+
+/// Floods the map from its edges and existing water, raising every basin to the point where it
+/// spills over. Every tile ends up draining towards the tile it was flooded from.
+fn drain(map: &Map) -> Drainage {
+    let count = map.tile_count();
+    let mut level = vec![f32::NAN; count];
+    let mut downstream = vec![None; count];
+    let mut queue = BinaryHeap::new();
+
+    let dimensions = map.dimensions();
+    for (index, tile_level) in level.iter_mut().enumerate() {
+        let point = map.index_to_point(index);
+        let border = point.x == 0
+            || point.y == 0
+            || point.x == dimensions.x - 1
+            || point.y == dimensions.y - 1;
+
+        if border || is_water(map, index) {
+            *tile_level = map.elevation[index];
+            queue.push(Flood {
+                level: *tile_level,
+                index,
+            });
+        }
+    }
+
+    while let Some(Flood {
+        level: current,
+        index,
+    }) = queue.pop()
+    {
+        for neighbour in map.get_neighbours(index) {
+            if !level[neighbour].is_nan() {
+                continue;
+            }
+            level[neighbour] = map.elevation[neighbour].max(current);
+            downstream[neighbour] = Some(index);
+            queue.push(Flood {
+                level: level[neighbour],
+                index: neighbour,
+            });
+        }
+    }
+
+    Drainage { level, downstream }
+}
+
+fn is_water(map: &Map, index: usize) -> bool {
+    matches!(
+        map.tile_types[index],
+        TileType::ShallowWater | TileType::DeepWater | TileType::River
+    )
+}
+
+/// Replaces the type of a tile, keeping the shape and climate of the land.
+fn convert(map: &mut Map, index: usize, tile_type: TileType) {
+    let mut converted = get_data(&tile_type);
+    converted.elevation = map.elevation[index];
+    converted.temperature = map.temperature[index];
+    map.set_tile(index, converted);
+}
+
+/// Fills basins with lakes and carves rivers flowing from high ground down to the water.
+pub(crate) fn apply_hydrology(map: &mut Map) {
+    let drainage = drain(map);
+
+    fill_lakes(map, &drainage);
+    carve_rivers(map, &drainage);
+}
+
+fn fill_lakes(map: &mut Map, drainage: &Drainage) {
+    for index in 0..map.tile_count() {
+        let depth = drainage.level[index] - map.elevation[index];
+        if depth < LAKE_MIN_DEPTH || is_water(map, index) {
+            continue;
+        }
+
+        convert(
+            map,
+            index,
+            if depth > LAKE_DEEP_DEPTH {
+                TileType::DeepWater
+            } else {
+                TileType::ShallowWater
+            },
+        );
+        // The surface of the lake is flat, at the level it spills over.
+        map.elevation[index] = drainage.level[index];
+    }
+}
+
+/// Picks the highest, wettest tiles as sources, spaced out so rivers don't all start together.
+fn river_sources(map: &Map) -> Vec<usize> {
+    let mut candidates: Vec<usize> = (0..map.tile_count())
+        .filter(|index| {
+            !is_water(map, *index)
+                && map.elevation[*index] >= RIVER_SOURCE_ELEVATION
+                && map.moisture[*index] >= RIVER_SOURCE_MOISTURE
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        map.elevation[*b]
+            .total_cmp(&map.elevation[*a])
+            .then_with(|| a.cmp(b))
+    });
+
+    let mut sources: Vec<usize> = Vec::new();
+    let max_sources = (map.tile_count() / TILES_PER_RIVER).max(1);
+    for candidate in candidates {
+        if sources.len() >= max_sources {
+            break;
+        }
+        let point = map.index_to_point(candidate);
+        let spaced = sources.iter().all(|source| {
+            Chebyshev.distance2d(point, map.index_to_point(*source)) >= RIVER_SOURCE_SPACING
+        });
+        if spaced {
+            sources.push(candidate);
+        }
+    }
+
+    sources
+}
+
+fn carve_rivers(map: &mut Map, drainage: &Drainage) {
+    for source in river_sources(map) {
+        let mut current = Some(source);
+
+        // Follow the water downstream until it reaches a lake, the sea or another river.
+        while let Some(index) = current {
+            if is_water(map, index) {
+                break;
+            }
+            convert(map, index, TileType::River);
+            current = drainage.downstream[index];
+        }
+    }
+
+    // Land along rivers is wetter.
+    for index in 0..map.tile_count() {
+        if map.tile_types[index] != TileType::River {
+            continue;
+        }
+        for neighbour in map.get_neighbours(index) {
+            map.moisture[neighbour] = map.moisture[neighbour].max(RIVERBANK_MOISTURE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{
+        plugin::MapSettings,
+        tiles::{get_data, TileType},
+        Map,
+    };
+
+    use super::{apply_hydrology, is_water};
+
+    /// Builds a map of grass where each tile has the given elevation.
+    fn map_from_elevation(width: i32, elevation: &[f32]) -> Map {
+        let settings = MapSettings {
+            width,
+            height: elevation.len() as i32 / width,
+            tile_size: 1.0,
+        };
+        let tiles = elevation.iter().map(|value| {
+            let mut tile = get_data(&TileType::Grass);
+            tile.elevation = *value;
+            tile
+        });
+
+        Map::from_tiles(settings, tiles)
+    }
+
+    #[test]
+    fn basin_becomes_lake() {
+        #[rustfmt::skip]
+        let mut map = map_from_elevation(5, &[
+            0.3, 0.3, 0.3, 0.3, 0.3,
+            0.3, 0.2, 0.2, 0.2, 0.3,
+            0.3, 0.2, 0.1, 0.2, 0.3,
+            0.3, 0.2, 0.2, 0.2, 0.3,
+            0.3, 0.3, 0.3, 0.3, 0.3,
+        ]);
+
+        apply_hydrology(&mut map);
+
+        assert_eq!(map.tile_types[12], TileType::DeepWater);
+        assert_eq!(map.tile_types[6], TileType::ShallowWater);
+        // The lake surface is flat at the level of the rim.
+        assert_eq!(map.elevation[12], 0.3);
+        assert_eq!(map.tile_types[0], TileType::Grass);
+    }
+
+    #[test]
+    fn river_flows_down_to_the_sea() {
+        #[rustfmt::skip]
+        let mut map = map_from_elevation(5, &[
+            0.5, 0.5, 0.5, 0.5, 0.5,
+            0.5, 0.5, 0.6, 0.5, 0.5,
+            0.5, 0.5, 0.4, 0.5, 0.5,
+            0.3, 0.3, 0.3, 0.3, 0.3,
+            -0.5, -0.5, -0.5, -0.5, -0.5,
+        ]);
+        for index in 20..25 {
+            map.tile_types[index] = TileType::DeepWater;
+        }
+
+        apply_hydrology(&mut map);
+
+        let rivers: Vec<usize> = (0..25)
+            .filter(|index| map.tile_types[*index] == TileType::River)
+            .collect();
+        // The river springs from the highest point.
+        assert!(rivers.contains(&7));
+
+        // Every river tile flows into either more river or the sea.
+        for river in rivers {
+            assert!(map
+                .get_neighbours(river)
+                .iter()
+                .any(|n| is_water(&map, *n) && *n != river));
+        }
+    }
+}
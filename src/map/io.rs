@@ -0,0 +1,357 @@
+//! Saving and loading maps in formats that can be edited by hand.
+//!
+//! Maps can be stored either as an ASCII grid, or as an image where each pixel is one tile,
+//! coloured by its type. Only the tile types are stored, so the shape and climate of the land is
+//! lost when saving.
+
+use std::{fmt, fs, path::Path};
+
+use image::{ImageError, Rgb, RgbImage};
+
+use super::{
+    tiles::{get_color, get_symbol, tile_from_symbol, TileType, ALL_TILE_TYPES},
+    Map,
+};
+
+/// Marks the start of the legend in an ASCII map.
+const LEGEND_HEADER: &str = "legend:";
+/// Marks the start of the grid in an ASCII map.
+const MAP_HEADER: &str = "map:";
+
+#[derive(Debug)]
+pub(crate) enum MapFormatError {
+    /// The map does not contain any tiles.
+    Empty,
+    /// A row of the map has a different length than the first row.
+    UnevenRow {
+        row: usize,
+    },
+    /// A line in the legend could not be understood.
+    InvalidLegend {
+        line: String,
+    },
+    /// A symbol in the grid is not part of the legend.
+    UnknownSymbol {
+        symbol: char,
+        row: usize,
+    },
+    /// A pixel in the image does not have the colour of any tile type.
+    UnknownColor {
+        x: u32,
+        y: u32,
+    },
+    Io(std::io::Error),
+    Image(ImageError),
+}
+
+impl fmt::Display for MapFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapFormatError::Empty => write!(f, "the map is empty"),
+            MapFormatError::UnevenRow { row } => {
+                write!(f, "row {row} is not as long as the first row")
+            }
+            MapFormatError::InvalidLegend { line } => write!(f, "invalid legend: '{line}'"),
+            MapFormatError::UnknownSymbol { symbol, row } => {
+                write!(f, "unknown symbol '{symbol}' in row {row}")
+            }
+            MapFormatError::UnknownColor { x, y } => {
+                write!(f, "the pixel at {x}, {y} is not the colour of any tile")
+            }
+            MapFormatError::Io(error) => error.fmt(f),
+            MapFormatError::Image(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MapFormatError {}
+
+impl From<std::io::Error> for MapFormatError {
+    fn from(error: std::io::Error) -> Self {
+        MapFormatError::Io(error)
+    }
+}
+
+impl From<ImageError> for MapFormatError {
+    fn from(error: ImageError) -> Self {
+        MapFormatError::Image(error)
+    }
+}
+
+/// The rows of tiles that make up a map, from the top row down.
+pub(crate) type TileRows = Vec<Vec<TileType>>;
+
+/// Reads the tile rows from an ASCII map.
+///
+/// The map may start with a legend defining which symbol is used for each tile type:
+///
+/// ```text
+/// legend:
+/// . = Grass
+/// # = DeepWater
+/// map:
+/// ..#
+/// .##
+/// ```
+///
+/// Without a legend, the default symbols are used.
+pub(crate) fn parse_ascii(text: &str) -> Result<TileRows, MapFormatError> {
+    let mut legend: Option<Vec<(char, TileType)>> = None;
+
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .peekable();
+
+    if lines.peek() == Some(&LEGEND_HEADER) {
+        lines.next();
+
+        let mut symbols = Vec::new();
+        for line in lines.by_ref() {
+            if line == MAP_HEADER {
+                break;
+            }
+            symbols.push(parse_legend(line)?);
+        }
+        legend = Some(symbols);
+    }
+
+    let mut rows: TileRows = Vec::new();
+    for (row, line) in lines.enumerate() {
+        let tiles = line
+            .chars()
+            .map(|symbol| {
+                match &legend {
+                    Some(legend) => legend
+                        .iter()
+                        .find(|(legend_symbol, _)| *legend_symbol == symbol)
+                        .map(|(_, tile_type)| *tile_type),
+                    None => tile_from_symbol(symbol),
+                }
+                .ok_or(MapFormatError::UnknownSymbol { symbol, row })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.push(tiles);
+    }
+
+    validate(rows)
+}
+
+/// Parses a line of the legend, such as `. = Grass`.
+fn parse_legend(line: &str) -> Result<(char, TileType), MapFormatError> {
+    let invalid = || MapFormatError::InvalidLegend {
+        line: line.to_string(),
+    };
+
+    // The symbol comes first, since it may itself be an `=`.
+    let mut chars = line.chars();
+    let symbol = chars.next().ok_or_else(invalid)?;
+    let name = chars
+        .as_str()
+        .trim_start()
+        .strip_prefix('=')
+        .ok_or_else(invalid)?
+        .trim();
+
+    ALL_TILE_TYPES
+        .into_iter()
+        .find(|tile_type| format!("{tile_type:?}") == name)
+        .map(|tile_type| (symbol, tile_type))
+        .ok_or_else(invalid)
+}
+
+/// Reads the tile rows from an image, where each pixel has the colour of a tile type.
+pub(crate) fn parse_image(image: &RgbImage) -> Result<TileRows, MapFormatError> {
+    let rows = (0..image.height())
+        .map(|y| {
+            (0..image.width())
+                .map(|x| {
+                    let pixel = image.get_pixel(x, y);
+                    ALL_TILE_TYPES
+                        .into_iter()
+                        .find(|tile_type| tile_pixel(*tile_type) == *pixel)
+                        .ok_or(MapFormatError::UnknownColor { x, y })
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<TileRows, _>>()?;
+
+    validate(rows)
+}
+
+fn validate(rows: TileRows) -> Result<TileRows, MapFormatError> {
+    let width = rows.first().map_or(0, Vec::len);
+    if width == 0 {
+        return Err(MapFormatError::Empty);
+    }
+    if let Some(row) = rows.iter().position(|row| row.len() != width) {
+        return Err(MapFormatError::UnevenRow { row });
+    }
+
+    Ok(rows)
+}
+
+/// The colour of a tile type as an 8-bit pixel.
+fn tile_pixel(tile_type: TileType) -> Rgb<u8> {
+    let to_byte = |value: f32| (value * u8::MAX as f32).round() as u8;
+    let [red, green, blue, _] = get_color(tile_type).as_rgba_f32();
+    Rgb([to_byte(red), to_byte(green), to_byte(blue)])
+}
+
+/// Returns true if the file should be stored as an image rather than as text.
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+impl Map {
+    /// The tile types of the map, from the top row down.
+    fn tile_rows(&self) -> TileRows {
+        self.tile_types
+            .chunks(self.settings.width as usize)
+            .map(<[TileType]>::to_vec)
+            .collect()
+    }
+
+    /// Writes the map as an ASCII grid, starting with a legend of the symbols used.
+    pub(crate) fn to_ascii(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str(LEGEND_HEADER);
+        text.push('\n');
+        for tile_type in ALL_TILE_TYPES {
+            text.push_str(&format!("{} = {:?}\n", get_symbol(tile_type), tile_type));
+        }
+
+        text.push_str(MAP_HEADER);
+        text.push('\n');
+        for row in self.tile_rows() {
+            text.extend(row.into_iter().map(get_symbol));
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Draws the map as an image where each pixel is a tile.
+    pub(crate) fn to_image(&self) -> RgbImage {
+        let mut image = RgbImage::new(self.settings.width as u32, self.settings.height as u32);
+        for (y, row) in self.tile_rows().into_iter().enumerate() {
+            for (x, tile_type) in row.into_iter().enumerate() {
+                image.put_pixel(x as u32, y as u32, tile_pixel(tile_type));
+            }
+        }
+        image
+    }
+
+    /// Saves the map to a file, as an image if it's a `.png` and as an ASCII grid otherwise.
+    pub(crate) fn save(&self, path: impl AsRef<Path>) -> Result<(), MapFormatError> {
+        let path = path.as_ref();
+        if is_image(path) {
+            self.to_image().save(path)?;
+        } else {
+            fs::write(path, self.to_ascii())?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the tile rows from a file, as an image if it's a `.png` and as an ASCII grid otherwise.
+pub(crate) fn load_rows(path: impl AsRef<Path>) -> Result<TileRows, MapFormatError> {
+    let path = path.as_ref();
+    if is_image(path) {
+        parse_image(&image::open(path)?.into_rgb8())
+    } else {
+        parse_ascii(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{
+        generators::{GridGenerator, IslandGenerator, MapGenerator},
+        plugin::MapSettings,
+        tiles::TileType,
+        Map,
+    };
+
+    use super::{parse_ascii, parse_image, MapFormatError};
+
+    fn generated_map() -> Map {
+        let settings = MapSettings {
+            width: 20,
+            height: 12,
+            tile_size: 1.0,
+        };
+        IslandGenerator.generate(&settings, 3)
+    }
+
+    #[test]
+    fn ascii_round_trip() {
+        let map = generated_map();
+        let rows = parse_ascii(&map.to_ascii()).unwrap();
+        let loaded = GridGenerator::new(rows).generate(&map.settings, 0);
+
+        assert_eq!(loaded.settings.width, 20);
+        assert_eq!(loaded.settings.height, 12);
+        assert_eq!(loaded.tile_rows(), map.tile_rows());
+        assert_eq!(loaded.to_ascii(), map.to_ascii());
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let map = generated_map();
+        let rows = parse_image(&map.to_image()).unwrap();
+        let loaded = GridGenerator::new(rows).generate(&map.settings, 0);
+
+        assert_eq!(loaded.tile_rows(), map.tile_rows());
+    }
+
+    #[test]
+    fn custom_legend() {
+        let rows = parse_ascii(
+            "
+            legend:
+            G = Grass
+            W = DeepWater
+            map:
+            GGW
+            GWW
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![
+                vec![TileType::Grass, TileType::Grass, TileType::DeepWater],
+                vec![TileType::Grass, TileType::DeepWater, TileType::DeepWater],
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_maps() {
+        assert!(matches!(parse_ascii(""), Err(MapFormatError::Empty)));
+        assert!(matches!(
+            parse_ascii("..\n."),
+            Err(MapFormatError::UnevenRow { row: 1 })
+        ));
+        assert!(matches!(
+            parse_ascii("..\n.?"),
+            Err(MapFormatError::UnknownSymbol {
+                symbol: '?',
+                row: 1
+            })
+        ));
+        assert!(matches!(
+            parse_ascii("legend:\n. = Lava\nmap:\n."),
+            Err(MapFormatError::InvalidLegend { .. })
+        ));
+        assert!(matches!(
+            parse_ascii("legend:\n.. = Grass\nmap:\n."),
+            Err(MapFormatError::InvalidLegend { .. })
+        ));
+    }
+}
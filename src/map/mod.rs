@@ -1,18 +1,33 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
 
-use bevy::prelude::{Resource, Vec3};
+use bevy::prelude::Resource;
 use bevy_turborand::{rng::Rng, TurboRand};
-use bracket_pathfinding::prelude::{Algorithm2D, BaseMap, Point, SmallVec};
+use bracket_pathfinding::prelude::{BaseMap, Point, SmallVec};
+use serde::{Deserialize, Serialize};
 
 use self::{
     plugin::MapSettings,
-    tiles::{pos_to_world, MapIndex, TileData, TileType},
+    tiles::{world_height, MapIndex, TileData, TileType},
 };
 
+pub(crate) mod chunks;
+pub(crate) mod coordinates;
+pub(crate) mod flow;
+pub(crate) mod generators;
+pub(crate) mod hydrology;
+pub(crate) mod io;
+pub(crate) mod occupancy;
 pub(crate) mod pathfinding;
 pub(crate) mod plugin;
+pub(crate) mod regions;
+pub(crate) mod spatial;
+pub(crate) mod terrain;
 pub(crate) mod tiles;
 
+/// How many of the latest tile changes the map remembers. Anything that falls further behind has
+/// to be rebuilt from the whole map.
+const CHANGE_LOG_LENGTH: usize = 4096;
+
 #[derive(Default)]
 pub(crate) struct TileQuery {
     pub walkable: Option<bool>,
@@ -22,97 +37,197 @@ pub(crate) struct TileQuery {
     pub types: Option<Vec<TileType>>,
 }
 
-#[derive(Resource)]
+/// The tiles of the map, stored as one layer per field of `TileData`.
+///
+/// Every layer holds a value for each tile in row-major order (`y * width + x`), the same order
+/// as `Algorithm2D::point2d_to_index`.
+#[derive(Resource, Clone, Serialize, Deserialize)]
 pub(crate) struct Map {
-    pub(crate) indexes: HashMap<usize, Point>,
-    pub(crate) data: HashMap<usize, TileData>,
     pub(crate) settings: MapSettings,
+    pub(crate) tile_types: Vec<TileType>,
+    pub(crate) movement_speed: Vec<f32>,
+    pub(crate) growability: Vec<f32>,
+    pub(crate) moisture: Vec<f32>,
+    pub(crate) nutrients: Vec<f32>,
+    pub(crate) elevation: Vec<f32>,
+    pub(crate) temperature: Vec<f32>,
+    /// The tiles most recently replaced with `set_tile`, oldest first. The last one took the map
+    /// to the current revision.
+    #[serde(skip)]
+    changes: VecDeque<usize>,
+    /// How many times tiles have been replaced with `set_tile`.
+    revision: usize,
 }
 
 impl Map {
+    /// Creates a map from the data of each tile, given in row-major order.
+    pub(crate) fn from_tiles(
+        settings: MapSettings,
+        tiles: impl IntoIterator<Item = TileData>,
+    ) -> Self {
+        let count = (settings.width * settings.height) as usize;
+        let mut map = Map {
+            settings,
+            tile_types: Vec::with_capacity(count),
+            movement_speed: Vec::with_capacity(count),
+            growability: Vec::with_capacity(count),
+            moisture: Vec::with_capacity(count),
+            nutrients: Vec::with_capacity(count),
+            elevation: Vec::with_capacity(count),
+            temperature: Vec::with_capacity(count),
+            changes: VecDeque::new(),
+            revision: 0,
+        };
+
+        for tile in tiles {
+            map.tile_types.push(tile.tile_type);
+            map.movement_speed.push(tile.movement_speed);
+            map.growability.push(tile.growability);
+            map.moisture.push(tile.moisture);
+            map.nutrients.push(tile.nutrients);
+            map.elevation.push(tile.elevation);
+            map.temperature.push(tile.temperature);
+        }
+        assert_eq!(
+            map.tile_types.len(),
+            count,
+            "Wrong number of tiles for the map"
+        );
+
+        map
+    }
+
+    /// Overwrites all data of a tile.
+    ///
+    /// Changes to the type or elevation of tiles after the map has been spawned must go through
+    /// here, so the tile is drawn again and paths across it are found again.
+    pub(crate) fn set_tile(&mut self, index: usize, tile: TileData) {
+        if self.changes.len() == CHANGE_LOG_LENGTH {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(index);
+        self.revision += 1;
+        self.tile_types[index] = tile.tile_type;
+        self.movement_speed[index] = tile.movement_speed;
+        self.growability[index] = tile.growability;
+        self.moisture[index] = tile.moisture;
+        self.nutrients[index] = tile.nutrients;
+        self.elevation[index] = tile.elevation;
+        self.temperature[index] = tile.temperature;
+    }
+
+    /// The tiles replaced since the map was at the revision, oldest first.
+    ///
+    /// None if the map no longer remembers back that far, or never was at the revision, in which
+    /// case anything built from the tiles has to be rebuilt.
+    pub(crate) fn changes_since(
+        &self,
+        revision: usize,
+    ) -> Option<impl Iterator<Item = usize> + '_> {
+        let first = self.revision - self.changes.len();
+        if revision < first || revision > self.revision {
+            return None;
+        }
+        Some(self.changes.range(revision - first..).copied())
+    }
+
+    /// Goes up every time a tile is replaced, so anything built from the tiles can tell when it
+    /// is out of date.
+    pub(crate) fn revision(&self) -> usize {
+        self.revision
+    }
+
+    /// How many tiles there are on the map.
+    pub(crate) fn tile_count(&self) -> usize {
+        self.tile_types.len()
+    }
+
     pub(crate) fn is_walkable(&self, index: &usize) -> bool {
-        self.data[index].movement_speed > 0.0
+        self.movement_speed[*index] > 0.0
     }
 
     pub(crate) fn is_growable(&self, index: &usize) -> bool {
-        self.data[index].growability > 0.0
+        self.growability[*index] > 0.0
+    }
+
+    /// Chance that a seed landing on this tile takes root.
+    pub(crate) fn germination_chance(&self, index: &usize) -> f32 {
+        self.growability[*index] * self.moisture[*index]
     }
 
-    pub(crate) fn index_to_world(&self, index: MapIndex) -> Vec3 {
-        pos_to_world(self.index_to_point2d(index.into()).into(), &self.settings)
+    /// The height of the surface of a tile in the world. See `tiles::world_height`.
+    pub(crate) fn world_height(&self, index: usize) -> f32 {
+        world_height(self.elevation[index])
     }
 
     /// Queries for a collection of tiles from the map.
-    pub(crate) fn query(&self, query: &TileQuery) -> Vec<&usize> {
-        self.data
-            .iter()
+    pub(crate) fn query(&self, query: &TileQuery) -> Vec<usize> {
+        self.query_area(query)
             // Filter by range
-            .filter(|(i, _)| {
+            .filter(|i| {
                 if let Some((distance, origin)) = query.distance {
-                    self.get_pathing_distance(**i, origin) < distance
+                    self.get_pathing_distance(*i, origin) < distance
                 } else {
                     true
                 }
             })
             // Filter by walkable
-            .filter(|(i, _)| {
+            .filter(|i| {
                 if let Some(walkable) = query.walkable {
                     self.is_walkable(i) == walkable
                 } else {
                     true
                 }
             })
-            .filter(|(i, _)| {
+            .filter(|i| {
                 if let Some(growable) = query.growable {
                     self.is_growable(i) == growable
                 } else {
                     true
                 }
             })
-            .filter(|(i, _)| {
+            .filter(|i| {
                 if let Some(excludes) = &query.exclude {
                     !excludes.contains(i)
                 } else {
                     true
                 }
             })
-            .filter(|(_, e)| {
+            .filter(|i| {
                 if let Some(types) = &query.types {
-                    types.contains(&e.tile_type)
+                    types.contains(&self.tile_types[*i])
                 } else {
                     true
                 }
             })
-            .map(|(i, _)| i)
             .collect()
     }
 
-    pub(crate) fn query_neighbours(
-        &self,
-        index: usize,
-        query: &TileQuery,
-    ) -> SmallVec<[usize; 10]> {
-        let neighbours = self.get_neighbours(index);
-        let mut result = SmallVec::new();
-        for index in neighbours {
-            let mut include = true;
-            // Filter tile
-            if let Some(walkable) = query.walkable {
-                include = self.is_walkable(&index) == walkable;
-            }
-            if let Some(growable) = query.growable {
-                include = self.is_growable(&index) == growable;
-            }
-            if let Some(types) = &query.types {
-                include = types.contains(&self.data[&index].tile_type);
-            }
-
-            if include {
-                result.push(index);
+    /// The tiles that might match a query.
+    ///
+    /// Queries limited by distance only need to look at the square around their origin.
+    fn query_area<'a>(&'a self, query: &TileQuery) -> impl Iterator<Item = usize> + 'a {
+        let (min, max) = match query.distance {
+            Some((distance, origin)) => {
+                let origin = self.index_to_point(origin);
+                let reach = distance.ceil() as i32;
+                (
+                    Point::new((origin.x - reach).max(0), (origin.y - reach).max(0)),
+                    Point::new(
+                        (origin.x + reach).min(self.settings.width - 1),
+                        (origin.y + reach).min(self.settings.height - 1),
+                    ),
+                )
             }
-        }
+            None => (
+                Point::zero(),
+                Point::new(self.settings.width - 1, self.settings.height - 1),
+            ),
+        };
 
-        result
+        (min.y..=max.y).flat_map(move |y| {
+            (min.x..=max.x).filter_map(move |x| self.point_to_index(Point::new(x, y)))
+        })
     }
 
     /// Returns a random tile from the query result
@@ -124,19 +239,19 @@ impl Map {
         } else {
             // Grab a random from the list
             let index = result[rng.usize(0..result.len())];
-            Some(<usize as Into<MapIndex>>::into(*index))
+            Some(index.into())
         }
     }
 
     /// Returns true if the index exists on the map.
     #[allow(dead_code)]
     pub(crate) fn index_exist(&self, index: usize) -> bool {
-        (0..self.data.len()).contains(&index)
+        index < self.tile_count()
     }
 
     pub(crate) fn get_neighbours(&self, index: usize) -> SmallVec<[usize; 10]> {
         let mut neighbours = SmallVec::new();
-        let location = self.index_to_point2d(index);
+        let location = self.index_to_point(index);
 
         if let Some(neighbour_index) = self.valid_neighbour(location, Point::new(-1, 0)) {
             neighbours.push(neighbour_index);
@@ -167,23 +282,28 @@ impl Map {
     }
 
     fn valid_neighbour(&self, location: Point, delta: Point) -> Option<usize> {
-        let new_point = location + delta;
-        if self.in_bounds(new_point) {
-            Some(self.point2d_to_index(new_point))
-        } else {
-            None
-        }
+        self.point_to_index(location + delta)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use bevy::prelude::default;
     use bevy_turborand::{DelegatedRng, GlobalRng};
+    use bracket_pathfinding::prelude::{
+        Algorithm2D, BaseMap, DistanceAlg::Pythagoras, Point, SmallVec,
+    };
 
-    use crate::map::TileQuery;
+    use crate::map::{Map, TileQuery};
 
-    use super::plugin::{generate_map, MapSettings};
+    use super::{
+        generators::{FlatGenerator, MapGenerator, PerlinGenerator},
+        plugin::MapSettings,
+        tiles::{get_data, world_height, TileData, TileType},
+        CHANGE_LOG_LENGTH,
+    };
 
     const SETTINGS: MapSettings = MapSettings {
         width: 8,
@@ -195,7 +315,7 @@ mod tests {
     fn no_invalid_random_points() {
         let mut rng = GlobalRng::new();
 
-        let map = generate_map(&SETTINGS, 0);
+        let map = PerlinGenerator.generate(&SETTINGS, 0);
 
         for n in 0..8 * 8 {
             let query = TileQuery {
@@ -224,7 +344,7 @@ mod tests {
             tile_size: 1.0,
         };
 
-        let map = generate_map(&settings, 0);
+        let map = PerlinGenerator.generate(&settings, 0);
 
         let top_left = map.get_neighbours(0);
         assert!(!top_left.contains(&0));
@@ -327,30 +447,171 @@ mod tests {
     }
 
     #[test]
-    fn neighbour_query_filters_default() {
-        // The map has the following indexes:
-        // 0 1 2
-        // 3 4 5
-        // 6 7 8
+    fn changes_are_logged_by_revision() {
+        let mut map = FlatGenerator::default().generate(&SETTINGS, 0);
+        let changes = |map: &Map, revision| map.changes_since(revision).map(Vec::from_iter);
+        assert_eq!(changes(&map, 0), Some(vec![]));
+
+        map.set_tile(3, get_data(&TileType::Sand));
+        map.set_tile(5, get_data(&TileType::Rock));
+        assert_eq!(map.revision(), 2);
+        assert_eq!(changes(&map, 0), Some(vec![3, 5]));
+        assert_eq!(changes(&map, 1), Some(vec![5]));
+        assert_eq!(changes(&map, 2), Some(vec![]));
+        assert_eq!(changes(&map, 3), None);
+
+        // Only the latest changes are remembered.
+        for _ in 0..CHANGE_LOG_LENGTH {
+            map.set_tile(7, get_data(&TileType::Grass));
+        }
+        assert_eq!(changes(&map, 1), None);
+        assert_eq!(changes(&map, 2).unwrap().len(), CHANGE_LOG_LENGTH);
+    }
 
-        let settings = MapSettings {
-            width: 3,
-            height: 3,
-            tile_size: 1.0,
-        };
+    /// The tiles as they were stored before the map kept them in dense layers: hash maps from
+    /// index to point and to the data of the tile.
+    ///
+    /// Only kept so the benchmark can compare the two.
+    struct HashMapTiles {
+        settings: MapSettings,
+        indexes: HashMap<usize, Point>,
+        data: HashMap<usize, TileData>,
+    }
+
+    impl HashMapTiles {
+        fn generate(settings: &MapSettings, tile_type: TileType) -> Self {
+            let mut indexes = HashMap::new();
+            let mut data = HashMap::new();
+            for y in 0..settings.height {
+                for x in 0..settings.width {
+                    let index = indexes.len();
+                    indexes.insert(index, Point::new(x, y));
+                    data.insert(index, get_data(&tile_type));
+                }
+            }
+            Self {
+                settings: settings.clone(),
+                indexes,
+                data,
+            }
+        }
+
+        /// The walkable tiles within the distance of the origin, found by going through them all.
+        fn query_walkable(&self, distance: f32, origin: usize) -> Vec<usize> {
+            self.data
+                .iter()
+                .filter(|(i, _)| self.get_pathing_distance(**i, origin) < distance)
+                .filter(|(_, tile)| tile.movement_speed > 0.0)
+                .map(|(i, _)| *i)
+                .collect()
+        }
+
+        fn slope_cost(&self, from: usize, to: usize) -> f32 {
+            let climb =
+                world_height(self.data[&to].elevation) - world_height(self.data[&from].elevation);
+            1.0 + climb.abs() * 2.0
+        }
+    }
+
+    impl Algorithm2D for HashMapTiles {
+        fn dimensions(&self) -> Point {
+            Point::new(self.settings.width, self.settings.height)
+        }
+    }
+
+    impl BaseMap for HashMapTiles {
+        fn is_opaque(&self, idx: usize) -> bool {
+            self.data[&idx].movement_speed <= 0.0
+        }
+
+        fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
+            let location = self.indexes[&idx];
+            let mut exits = SmallVec::new();
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let dest = location + Point::new(dx, dy);
+                if self.in_bounds(dest) && !self.is_opaque(self.point2d_to_index(dest)) {
+                    let to = self.point2d_to_index(dest);
+                    exits.push((to, self.slope_cost(idx, to)));
+                }
+            }
+            for (dx, dy) in [(-1, -1), (-1, 1), (1, -1), (1, 1)] {
+                let dest = location + Point::new(dx, dy);
+                if self.in_bounds(dest) && !self.is_opaque(self.point2d_to_index(dest)) {
+                    let to = self.point2d_to_index(dest);
+                    exits.push((to, 1.4 * self.slope_cost(idx, to)));
+                }
+            }
+            exits
+        }
 
-        let map = generate_map(&settings, 0);
-
-        let result = map.query_neighbours(4, &TileQuery::default());
-        // Should fetch all neighbours except self.
-        assert!(result.contains(&0));
-        assert!(result.contains(&1));
-        assert!(result.contains(&2));
-        assert!(result.contains(&3));
-        assert!(!result.contains(&4));
-        assert!(result.contains(&5));
-        assert!(result.contains(&6));
-        assert!(result.contains(&7));
-        assert!(result.contains(&8));
+        fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+            Pythagoras.distance2d(self.indexes[&idx1], self.indexes[&idx2])
+        }
+    }
+
+    /// Times the most common map operations on large maps, with the tiles stored in hash maps
+    /// as they used to be and in dense layers as they are now.
+    ///
+    /// Run with `cargo test --release benchmark_large_maps -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_large_maps() {
+        use std::time::Instant;
+
+        use bracket_pathfinding::prelude::a_star_search;
+
+        use super::chunks::{chunk_dimensions, chunk_mesh};
+
+        for size in [256, 512, 1024] {
+            let settings = MapSettings {
+                width: size,
+                height: size,
+                tile_size: 1.0,
+            };
+            let centre = (size / 2 * size + size / 2) as usize;
+            let corner = (size * size - 1) as usize;
+
+            let start = Instant::now();
+            let old = HashMapTiles::generate(&settings, TileType::Grass);
+            let old_generate = start.elapsed();
+            let start = Instant::now();
+            let map = FlatGenerator::default().generate(&settings, 0);
+            let generate = start.elapsed();
+
+            let start = Instant::now();
+            let old_found = old.query_walkable(10.0, centre).len();
+            let old_query = start.elapsed();
+            let start = Instant::now();
+            let found = map
+                .query(&TileQuery {
+                    walkable: Some(true),
+                    distance: Some((10.0, centre)),
+                    ..default()
+                })
+                .len();
+            let query = start.elapsed();
+
+            let start = Instant::now();
+            let old_path = a_star_search(0, corner, &old);
+            let old_pathfinding = start.elapsed();
+            let start = Instant::now();
+            let path = a_star_search(0, corner, &map);
+            let pathfinding = start.elapsed();
+
+            let start = Instant::now();
+            let count = chunk_dimensions(&map);
+            for chunk in 0..(count.x * count.y) as usize {
+                chunk_mesh(&map, chunk);
+            }
+            let meshing = start.elapsed();
+
+            assert_eq!(found, old_found);
+            assert!(path.success && old_path.success);
+            println!(
+                "{size}x{size}: generate {old_generate:?} -> {generate:?}, \
+                 query {old_query:?} -> {query:?}, \
+                 pathfinding {old_pathfinding:?} -> {pathfinding:?}, meshing {meshing:?}"
+            );
+        }
     }
 }
@@ -0,0 +1,259 @@
+//! How crowded each tile is, so agents don't all pile onto the same spot.
+//!
+//! Every agent takes up room on its tile according to its body size. Agents about to step onto a
+//! tile reserve room on it first, and wait for a moment if there is none.
+
+use bevy::{
+    prelude::{Component, Entity, Query, Res, ResMut, Resource},
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::serial::{by_serial, Serial};
+
+use super::{
+    pathfinding::{DiagonalMovement, Locomotion},
+    tiles::MapIndex,
+    Map,
+};
+
+/// How much body size fits on a single tile.
+pub(crate) const TILE_CAPACITY: f32 = 2.0;
+
+/// How much room an agent takes up on its tile.
+#[derive(Component, Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct BodySize(pub(crate) f32);
+
+/// The room taken up on each tile, by the agents standing on it and the agents about to enter it.
+///
+/// The agents on each tile are counted again at the end of each frame.
+#[derive(Resource, Default)]
+pub(crate) struct Occupancy {
+    /// The total body size of the agents on each tile.
+    occupied: HashMap<usize, f32>,
+    /// The tile each agent is about to enter, along with its body size.
+    reservations: HashMap<Entity, (usize, f32)>,
+    /// The body sizes of the agents about to enter each tile, smallest first. They are added up
+    /// in that order, so the room left doesn't depend on who reserved or gave up room first.
+    reserved: HashMap<usize, Vec<f32>>,
+}
+
+impl Occupancy {
+    /// How much room is taken up on the tile, including reservations.
+    pub(crate) fn load(&self, index: usize) -> f32 {
+        self.occupied.get(&index).copied().unwrap_or(0.0)
+            + self
+                .reserved
+                .get(&index)
+                .map_or(0.0, |sizes| sizes.iter().sum())
+    }
+
+    /// How crowded the agents standing on the tile are. Above 1.0 the tile is overcrowded.
+    pub(crate) fn crowding(&self, index: usize) -> f32 {
+        self.occupied.get(&index).copied().unwrap_or(0.0) / TILE_CAPACITY
+    }
+
+    /// Whether an agent of the size fits on the tile.
+    pub(crate) fn has_room(&self, index: usize, size: f32) -> bool {
+        self.load(index) + size <= TILE_CAPACITY
+    }
+
+    /// The tiles with no room left on them.
+    pub(crate) fn full_tiles(&self) -> HashSet<usize> {
+        self.occupied
+            .keys()
+            .chain(self.reserved.keys())
+            .copied()
+            .filter(|index| self.load(*index) >= TILE_CAPACITY)
+            .collect()
+    }
+
+    /// Reserves room for the agent on the tile it is about to enter, giving up any room it
+    /// reserved elsewhere. Returns whether there was room.
+    pub(crate) fn reserve(&mut self, entity: Entity, index: usize, size: f32) -> bool {
+        if self.reservations.get(&entity).map(|(tile, _)| *tile) == Some(index) {
+            return true;
+        }
+        self.release(entity);
+        if !self.has_room(index, size) {
+            return false;
+        }
+
+        self.hold(entity, index, size);
+        true
+    }
+
+    /// Reserves room for the agent whether or not there is any, e.g. to restore a reservation
+    /// made earlier.
+    pub(crate) fn hold(&mut self, entity: Entity, index: usize, size: f32) {
+        self.release(entity);
+        self.reservations.insert(entity, (index, size));
+        let sizes = self.reserved.entry(index).or_default();
+        let at = sizes.partition_point(|other| other.total_cmp(&size).is_lt());
+        sizes.insert(at, size);
+    }
+
+    /// The tile the agent has reserved room on, along with its body size.
+    pub(crate) fn reservation(&self, entity: Entity) -> Option<(usize, f32)> {
+        self.reservations.get(&entity).copied()
+    }
+
+    /// Gives up the room the agent has reserved, if any.
+    pub(crate) fn release(&mut self, entity: Entity) {
+        if let Some((index, size)) = self.reservations.remove(&entity) {
+            let sizes = self.reserved.get_mut(&index).unwrap();
+            let at = sizes.iter().position(|other| *other == size).unwrap();
+            sizes.remove(at);
+            if sizes.is_empty() {
+                self.reserved.remove(&index);
+            }
+        }
+    }
+
+    /// The closest tile an agent of the size fits on, by path cost from the origin.
+    pub(crate) fn nearest_with_room(
+        &self,
+        map: &Map,
+        origin: usize,
+        size: f32,
+        locomotion: Locomotion,
+        diagonals: DiagonalMovement,
+    ) -> Option<usize> {
+        map.pathing(locomotion)
+            .with_diagonals(diagonals)
+            .path_to_nearest(origin, |index| self.has_room(index, size))
+            .and_then(|path| path.last().copied())
+    }
+}
+
+/// Counts the agents on each tile, and releases the reservations of agents that have arrived,
+/// turned away or been despawned.
+pub(crate) fn update_occupancy(
+    mut occupancy: ResMut<Occupancy>,
+    agents: Query<(Entity, &MapIndex, &BodySize)>,
+    serials: Query<&Serial>,
+    map: Res<Map>,
+) {
+    // Added up in the same order on every run, as the total depends on it.
+    occupancy.occupied.clear();
+    for (_, index, size) in by_serial(&agents, |(entity, ..)| *entity, &serials) {
+        *occupancy.occupied.entry(index.0).or_insert(0.0) += size.0;
+    }
+
+    let settled: Vec<Entity> = occupancy
+        .reservations
+        .iter()
+        .filter(|(entity, (tile, _))| {
+            agents.get(**entity).map_or(true, |(_, index, _)| {
+                let offset = map.index_to_point(*tile) - map.index_to_point(index.0);
+                index.0 == *tile || offset.x.abs() > 1 || offset.y.abs() > 1
+            })
+        })
+        .map(|(entity, _)| *entity)
+        .collect();
+    for entity in settled {
+        occupancy.release(entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Entity;
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::map::{
+        generators::{GridGenerator, MapGenerator},
+        io::parse_ascii,
+        pathfinding::{DiagonalMovement, Locomotion},
+        plugin::MapSettings,
+        Map,
+    };
+
+    use super::{Occupancy, TILE_CAPACITY};
+
+    fn grid(text: &str) -> Map {
+        let settings = MapSettings {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+        };
+        GridGenerator::new(parse_ascii(text).unwrap()).generate(&settings, 0)
+    }
+
+    fn tile(map: &Map, x: i32, y: i32) -> usize {
+        map.point_to_index(Point::new(x, y)).unwrap()
+    }
+
+    #[test]
+    fn reservations_take_up_room() {
+        let mut occupancy = Occupancy::default();
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        occupancy.occupied.insert(3, 1.0);
+
+        assert!(occupancy.reserve(a, 3, 0.8));
+        assert!(occupancy.reserve(a, 3, 0.8));
+        assert!(!occupancy.has_room(3, 0.5));
+        assert!(!occupancy.reserve(b, 3, 0.5));
+        assert!(occupancy.full_tiles().is_empty());
+
+        // Reserving another tile frees up the first.
+        assert!(occupancy.reserve(a, 4, 0.8));
+        assert_eq!(occupancy.load(3), 1.0);
+        assert!(occupancy.reserve(b, 3, 1.0));
+        assert_eq!(occupancy.full_tiles().into_iter().collect::<Vec<_>>(), [3]);
+        assert_eq!(occupancy.crowding(3), 1.0 / TILE_CAPACITY);
+
+        occupancy.release(b);
+        occupancy.release(a);
+        assert_eq!(occupancy.load(4), 0.0);
+        assert!(occupancy.reserved.is_empty());
+    }
+
+    #[test]
+    fn finds_the_nearest_tile_with_room() {
+        let map = grid(
+            "
+            ....
+            .#..
+            ....
+            ",
+        );
+        let mut occupancy = Occupancy::default();
+        let origin = tile(&map, 0, 0);
+        let nearest = |occupancy: &Occupancy, diagonals| {
+            occupancy.nearest_with_room(&map, origin, 1.0, Locomotion::Walker, diagonals)
+        };
+        assert_eq!(
+            nearest(&occupancy, DiagonalMovement::default()),
+            Some(origin)
+        );
+
+        occupancy.occupied.insert(origin, TILE_CAPACITY);
+        occupancy.occupied.insert(tile(&map, 1, 0), 1.5);
+        occupancy.occupied.insert(tile(&map, 0, 1), 0.5);
+        assert_eq!(
+            nearest(&occupancy, DiagonalMovement::default()),
+            Some(tile(&map, 0, 1))
+        );
+    }
+
+    #[test]
+    fn room_is_looked_for_by_the_diagonal_rule() {
+        let map = grid(
+            "
+            .#
+            #.
+            ",
+        );
+        let mut occupancy = Occupancy::default();
+        let origin = tile(&map, 0, 0);
+        occupancy.occupied.insert(origin, TILE_CAPACITY);
+
+        let nearest = |diagonals| {
+            occupancy.nearest_with_room(&map, origin, 1.0, Locomotion::Walker, diagonals)
+        };
+        assert_eq!(nearest(DiagonalMovement::Always), Some(tile(&map, 1, 1)));
+        assert_eq!(nearest(DiagonalMovement::NoCornerCutting), None);
+        assert_eq!(nearest(DiagonalMovement::Never), None);
+    }
+}
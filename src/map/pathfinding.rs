@@ -1,22 +1,404 @@
+//! How agents find their way across the map.
+//!
+//! The cost of a step is the time it takes to make it, so paths prefer easy ground over the
+//! shortest route. How easy the ground is depends on the tile and on how the agent gets around.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use bevy::{
+    prelude::Resource,
+    utils::{HashMap, HashSet},
+};
 use bracket_pathfinding::prelude::{
     Algorithm2D, BaseMap, DistanceAlg::Pythagoras, Point, SmallVec,
 };
+use serde::{Deserialize, Serialize};
+
+use super::{tiles::TileType, Map};
+
+/// How much extra it costs to move one world unit up or down between two tiles.
+const SLOPE_COST: f32 = 2.0;
+
+/// How many times longer it takes to squeeze onto a tile full of other agents.
+const CROWDED_COST: f32 = 4.0;
+
+/// The directions to the neighbours of a tile, along with how far away they are.
+const EXITS: [(Point, f32); 8] = [
+    (Point { x: -1, y: 0 }, 1.0),
+    (Point { x: 1, y: 0 }, 1.0),
+    (Point { x: 0, y: -1 }, 1.0),
+    (Point { x: 0, y: 1 }, 1.0),
+    (Point { x: -1, y: -1 }, 1.4),
+    (Point { x: -1, y: 1 }, 1.4),
+    (Point { x: 1, y: -1 }, 1.4),
+    (Point { x: 1, y: 1 }, 1.4),
+];
+
+/// The distance between two points when moving along the exits, ignoring what is in the way.
+///
+/// No step costs less than its distance, so this never overestimates the cost of a path.
+fn octile_distance(from: Point, to: Point) -> f32 {
+    let (dx, dy) = ((to.x - from.x).abs(), (to.y - from.y).abs());
+    let diagonal = EXITS[4].1 - 1.0;
+    dx.max(dy) as f32 + diagonal * dx.min(dy) as f32
+}
+
+/// A tile waiting to be visited, cheapest first.
+#[derive(PartialEq)]
+pub(super) struct Visit {
+    pub(super) cost: f32,
+    pub(super) index: usize,
+}
+
+impl Eq for Visit {}
+
+impl Ord for Visit {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Visit {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// When an agent may step diagonally between tiles.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum DiagonalMovement {
+    /// Only step to the four tiles sharing a side.
+    Never,
+    /// Step diagonally only when both tiles beside the step can be crossed, so agents don't cut
+    /// corners through blocked tiles.
+    #[default]
+    NoCornerCutting,
+    /// Step diagonally whenever the destination can be crossed.
+    Always,
+}
 
-use super::Map;
+impl DiagonalMovement {
+    /// Parses the rule from its name, e.g. `no-corner-cutting`.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "never" => Some(DiagonalMovement::Never),
+            "no-corner-cutting" => Some(DiagonalMovement::NoCornerCutting),
+            "always" => Some(DiagonalMovement::Always),
+            _ => None,
+        }
+    }
+}
+
+/// How an agent gets around, which decides how fast it is on each type of terrain.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub(crate) enum Locomotion {
+    /// Moves at the movement speed of the tiles.
+    #[default]
+    Walker,
+    /// Fast in water, but slower on land.
+    Swimmer,
+    /// Climbs rock and snow as easily as grass.
+    Climber,
+}
+
+/// All the ways an agent can get around.
+pub(crate) const ALL_LOCOMOTIONS: [Locomotion; 3] =
+    [Locomotion::Walker, Locomotion::Swimmer, Locomotion::Climber];
+
+impl Locomotion {
+    /// How much faster or slower than the tile's movement speed the agent moves on it.
+    ///
+    /// The result times the movement speed should never go above 1.0, since the pathfinding
+    /// heuristic assumes no step can be faster than on grass.
+    pub(crate) const fn terrain_modifier(&self, tile_type: TileType) -> f32 {
+        match (self, tile_type) {
+            (Locomotion::Swimmer, TileType::ShallowWater | TileType::River) => 3.0,
+            (Locomotion::Swimmer, _) => 0.7,
+            (Locomotion::Climber, TileType::Rock) => 1.25,
+            (Locomotion::Climber, TileType::Snow) => 1.5,
+            _ => 1.0,
+        }
+    }
+}
 
 impl Map {
+    /// How fast an agent moves across a tile, relative to its own speed. Zero if it can't.
+    pub(crate) fn terrain_speed(&self, index: usize, locomotion: Locomotion) -> f32 {
+        self.movement_speed[index] * locomotion.terrain_modifier(self.tile_types[index])
+    }
+
+    /// The map as seen by an agent getting around in the given way.
+    pub(crate) fn pathing(&self, locomotion: Locomotion) -> PathingMap<'_> {
+        PathingMap {
+            map: self,
+            locomotion,
+            diagonals: DiagonalMovement::default(),
+            crowded: None,
+        }
+    }
+
+    /// The cost of moving between two neighbouring tiles, given the steepness between them.
+    fn slope_cost(&self, from: usize, to: usize) -> f32 {
+        let climb = self.world_height(to) - self.world_height(from);
+        1.0 + climb.abs() * SLOPE_COST
+    }
+}
+
+/// Finds paths across the map for agents getting around in a certain way.
+pub(crate) struct PathingMap<'a> {
+    map: &'a Map,
+    locomotion: Locomotion,
+    diagonals: DiagonalMovement,
+    /// Tiles full of other agents, which are avoided where possible.
+    crowded: Option<&'a HashSet<usize>>,
+}
+
+impl<'a> PathingMap<'a> {
+    /// The map the paths are found on.
+    pub(crate) fn map(&self) -> &Map {
+        self.map
+    }
+
+    /// Changes when diagonal steps are allowed.
+    pub(crate) fn with_diagonals(mut self, diagonals: DiagonalMovement) -> Self {
+        self.diagonals = diagonals;
+        self
+    }
+
+    /// Makes stepping onto the crowded tiles costly, so paths go around them if they can.
+    pub(crate) fn with_crowding(mut self, crowded: &'a HashSet<usize>) -> Self {
+        self.crowded = Some(crowded);
+        self
+    }
+
+    /// The cheapest path from the start to the nearest tile accepted as the goal, if any can be
+    /// reached.
+    ///
+    /// The search spreads out from the start by path cost, so tiles that are close but can't be
+    /// reached are passed over for ones further away.
+    pub(crate) fn path_to_nearest(
+        &self,
+        start: usize,
+        is_goal: impl FnMut(usize) -> bool,
+    ) -> Option<Vec<usize>> {
+        self.cheapest_path(start, is_goal, |_| 0.0, |_| true)
+    }
+
+    /// The cheapest path from the start to the goal that only crosses the allowed tiles, if any.
+    pub(crate) fn path_within(
+        &self,
+        start: usize,
+        goal: usize,
+        allowed: impl FnMut(usize) -> bool,
+    ) -> Option<Vec<usize>> {
+        let target = self.map.index_to_point(goal);
+        self.cheapest_path(
+            start,
+            |index| index == goal,
+            |index| octile_distance(self.map.index_to_point(index), target),
+            allowed,
+        )
+    }
+
+    /// Searches out from the start by the cost so far plus the estimated cost of the rest of the
+    /// way. The estimate must never be more than the real cost, or the path might not be the
+    /// cheapest.
+    fn cheapest_path(
+        &self,
+        start: usize,
+        mut is_goal: impl FnMut(usize) -> bool,
+        estimate: impl Fn(usize) -> f32,
+        mut allowed: impl FnMut(usize) -> bool,
+    ) -> Option<Vec<usize>> {
+        let mut cost = HashMap::new();
+        let mut parents = HashMap::new();
+        let mut visited = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        cost.insert(start, 0.0);
+        queue.push(Visit {
+            cost: estimate(start),
+            index: start,
+        });
+
+        while let Some(Visit { index, .. }) = queue.pop() {
+            if !visited.insert(index) {
+                continue;
+            }
+            if is_goal(index) {
+                let mut path = vec![index];
+                while let Some(parent) = parents.get(path.last().unwrap()) {
+                    path.push(*parent);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            let current = cost[&index];
+            for (neighbour, step) in self.get_available_exits(index) {
+                if !allowed(neighbour) {
+                    continue;
+                }
+                let next = current + step;
+                if cost.get(&neighbour).is_none_or(|known| next < *known) {
+                    cost.insert(neighbour, next);
+                    parents.insert(neighbour, index);
+                    queue.push(Visit {
+                        cost: next + estimate(neighbour),
+                        index: neighbour,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
     fn valid_exit(&self, location: Point, delta: Point) -> Option<usize> {
-        let dest = location + delta;
-        if self.in_bounds(dest) {
-            let index = self.point2d_to_index(dest);
-            if !self.is_opaque(index) {
-                Some(index)
+        let diagonal = delta.x != 0 && delta.y != 0;
+        let allowed = !diagonal
+            || match self.diagonals {
+                DiagonalMovement::Never => false,
+                DiagonalMovement::NoCornerCutting => {
+                    self.can_cross(location + Point::new(delta.x, 0))
+                        && self.can_cross(location + Point::new(0, delta.y))
+                }
+                DiagonalMovement::Always => true,
+            };
+        if !allowed {
+            return None;
+        }
+
+        self.map
+            .point_to_index(location + delta)
+            .filter(|index| !self.is_opaque(*index))
+    }
+
+    /// Shortens a path by cutting straight across wherever nothing is in the way.
+    ///
+    /// Only the tiles where the path turns are kept. A shortcut is only taken if every tile along
+    /// it is as fast and no higher or lower than the tiles of the path it replaces, so paths still
+    /// go around water and hills.
+    pub(crate) fn smooth(&self, path: &[usize]) -> Vec<usize> {
+        if path.len() <= 2 {
+            return path.to_vec();
+        }
+
+        let mut smoothed = vec![path[0]];
+        let mut anchor = 0;
+        for end in 2..path.len() {
+            if !self.is_shortcut(&path[anchor..=end]) {
+                anchor = end - 1;
+                smoothed.push(path[anchor]);
+            }
+        }
+        smoothed.push(path[path.len() - 1]);
+        smoothed
+    }
+
+    /// Whether going straight from the first to the last tile is as good as following the path.
+    fn is_shortcut(&self, path: &[usize]) -> bool {
+        let speed = |index: usize| self.map.terrain_speed(index, self.locomotion);
+        let height = |index: usize| self.map.world_height(index);
+        let slowest = path
+            .iter()
+            .map(|index| speed(*index))
+            .fold(f32::MAX, f32::min);
+        let lowest = path
+            .iter()
+            .map(|index| height(*index))
+            .fold(f32::MAX, f32::min);
+        let highest = path
+            .iter()
+            .map(|index| height(*index))
+            .fold(f32::MIN, f32::max);
+
+        self.line(path[0], path[path.len() - 1])
+            .into_iter()
+            .all(|index| speed(index) >= slowest && (lowest..=highest).contains(&height(index)))
+    }
+
+    /// The tiles a straight line between the centres of two tiles passes through.
+    ///
+    /// Where the line passes exactly through a corner, the tiles on both sides are included.
+    pub(crate) fn line(&self, from: usize, to: usize) -> Vec<usize> {
+        let (start, end) = (self.map.index_to_point(from), self.map.index_to_point(to));
+        let (dx, dy) = (end.x - start.x, end.y - start.y);
+        let (steps_x, steps_y) = (dx.abs(), dy.abs());
+        let step = Point::new(dx.signum(), dy.signum());
+
+        let mut point = start;
+        let mut points = vec![point];
+        let (mut x, mut y) = (0, 0);
+        while x < steps_x || y < steps_y {
+            // Compares where the line crosses the next vertical and horizontal edge.
+            let decision = (1 + 2 * x) * steps_y - (1 + 2 * y) * steps_x;
+            if decision == 0 {
+                points.push(Point::new(point.x + step.x, point.y));
+                points.push(Point::new(point.x, point.y + step.y));
+                point += step;
+                x += 1;
+                y += 1;
+            } else if decision < 0 {
+                point.x += step.x;
+                x += 1;
             } else {
-                None
+                point.y += step.y;
+                y += 1;
             }
-        } else {
-            None
+            points.push(point);
         }
+
+        // Every point lies between the two tiles, so is on the map.
+        points
+            .into_iter()
+            .filter_map(|point| self.map.point_to_index(point))
+            .collect()
+    }
+
+    /// Whether the point is on the map and can be crossed.
+    fn can_cross(&self, point: Point) -> bool {
+        self.map
+            .point_to_index(point)
+            .is_some_and(|index| !self.is_opaque(index))
+    }
+
+    /// The time it takes to move between two neighbouring tiles, half of it spent on each.
+    fn step_cost(&self, from: usize, to: usize, distance: f32) -> f32 {
+        let time = 0.5 / self.map.terrain_speed(from, self.locomotion)
+            + 0.5 / self.map.terrain_speed(to, self.locomotion);
+        let crowding = if self.crowded.is_some_and(|crowded| crowded.contains(&to)) {
+            CROWDED_COST
+        } else {
+            1.0
+        };
+        distance * time * self.map.slope_cost(from, to) * crowding
+    }
+}
+
+impl Algorithm2D for PathingMap<'_> {
+    fn dimensions(&self) -> Point {
+        self.map.dimensions()
+    }
+}
+
+impl BaseMap for PathingMap<'_> {
+    fn is_opaque(&self, _idx: usize) -> bool {
+        self.map.terrain_speed(_idx, self.locomotion) <= 0.0
+    }
+
+    fn get_available_exits(&self, _idx: usize) -> SmallVec<[(usize, f32); 10]> {
+        let location = self.map.index_to_point(_idx);
+        EXITS
+            .iter()
+            .filter_map(|(delta, distance)| {
+                self.valid_exit(location, *delta)
+                    .map(|idx| (idx, self.step_cost(_idx, idx, *distance)))
+            })
+            .collect()
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        self.map.get_pathing_distance(idx1, idx2)
     }
 }
 
@@ -32,60 +414,297 @@ impl BaseMap for Map {
         !self.is_walkable(&_idx)
     }
 
+    /// The exits as seen by an agent walking on the tiles.
     fn get_available_exits(&self, _idx: usize) -> SmallVec<[(usize, f32); 10]> {
-        let mut exits = SmallVec::new();
-        let location = self.index_to_point2d(_idx);
-
-        if let Some(idx) = self.valid_exit(location, Point::new(-1, 0)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(1, 0)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(0, -1)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(0, 1)) {
-            exits.push((idx, 1.0))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(-1, -1)) {
-            exits.push((idx, 1.4))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(-1, 1)) {
-            exits.push((idx, 1.4))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(1, -1)) {
-            exits.push((idx, 1.4))
-        }
-        if let Some(idx) = self.valid_exit(location, Point::new(1, 1)) {
-            exits.push((idx, 1.4))
-        }
-
-        exits
+        self.pathing(Locomotion::Walker).get_available_exits(_idx)
     }
 
     fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
-        Pythagoras.distance2d(self.index_to_point2d(idx1), self.index_to_point2d(idx2))
+        Pythagoras.distance2d(self.index_to_point(idx1), self.index_to_point(idx2))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use bracket_pathfinding::prelude::Point;
+    use bevy::utils::HashSet;
+    use bracket_pathfinding::prelude::{a_star_search, BaseMap, Point};
+
+    use crate::map::{
+        generators::{GridGenerator, MapGenerator, PerlinGenerator},
+        io::parse_ascii,
+        plugin::MapSettings,
+        tiles::{get_data, TileType, ALL_TILE_TYPES},
+        Map,
+    };
+
+    use super::{DiagonalMovement, Locomotion, PathingMap, ALL_LOCOMOTIONS};
+
+    const SETTINGS: MapSettings = MapSettings {
+        width: 16,
+        height: 16,
+        tile_size: 1.0,
+    };
 
-    use crate::map::plugin::{generate_map, MapSettings};
+    fn grid(text: &str) -> Map {
+        GridGenerator::new(parse_ascii(text).unwrap()).generate(&SETTINGS, 0)
+    }
+
+    fn tile(map: &Map, (x, y): (i32, i32)) -> usize {
+        map.point_to_index(Point::new(x, y)).unwrap()
+    }
+
+    /// The points a path passes through, from start to end.
+    fn route(map: &Map, locomotion: Locomotion, from: Point, to: Point) -> Vec<Point> {
+        let path = a_star_search(
+            map.point_to_index(from).unwrap(),
+            map.point_to_index(to).unwrap(),
+            &map.pathing(locomotion),
+        );
+        assert!(path.success);
+        path.steps
+            .into_iter()
+            .map(|index| map.index_to_point(index))
+            .collect()
+    }
 
     #[test]
     fn out_of_bounds_should_be_none() {
-        let settings = MapSettings {
-            width: 16,
-            height: 16,
-            tile_size: 1.0,
+        let map = PerlinGenerator.generate(&SETTINGS, 0);
+
+        let result = map
+            .pathing(Locomotion::Walker)
+            .valid_exit(Point { x: 0, y: 0 }, Point { x: -1, y: -1 });
+        assert_eq!(result, None);
+    }
+
+    /// The number of steps in the shortest path between two points, if there is one.
+    fn steps(map: &Map, diagonals: DiagonalMovement, from: Point, to: Point) -> Option<usize> {
+        let path = a_star_search(
+            map.point_to_index(from).unwrap(),
+            map.point_to_index(to).unwrap(),
+            &map.pathing(Locomotion::Walker).with_diagonals(diagonals),
+        );
+        path.success.then(|| path.steps.len() - 1)
+    }
+
+    #[test]
+    fn diagonals_follow_the_rules() {
+        let map = grid(
+            "
+            ...
+            .#.
+            ...
+            ",
+        );
+        let exits = |diagonals, point| {
+            map.pathing(Locomotion::Walker)
+                .with_diagonals(diagonals)
+                .get_available_exits(map.point_to_index(point).unwrap())
+                .len()
         };
 
-        let map = generate_map(&settings, 0);
+        // Beside the deep water, the diagonal steps would cut its corners.
+        let top = Point::new(1, 0);
+        assert_eq!(exits(DiagonalMovement::Always, top), 4);
+        assert_eq!(exits(DiagonalMovement::NoCornerCutting, top), 2);
+        assert_eq!(exits(DiagonalMovement::Never, top), 2);
 
-        let result = map.valid_exit(Point { x: 0, y: 0 }, Point { x: -1, y: -1 });
-        assert_eq!(result, None);
+        let map = grid(
+            "
+            ...
+            ...
+            ...
+            ",
+        );
+        let exits = |diagonals| {
+            map.pathing(Locomotion::Walker)
+                .with_diagonals(diagonals)
+                .get_available_exits(4)
+                .len()
+        };
+        assert_eq!(exits(DiagonalMovement::Always), 8);
+        assert_eq!(exits(DiagonalMovement::NoCornerCutting), 8);
+        assert_eq!(exits(DiagonalMovement::Never), 4);
+    }
+
+    #[test]
+    fn no_squeezing_between_blocked_tiles() {
+        // The only way across is between the corners of the deep water.
+        let map = grid(
+            "
+            .#
+            #.
+            ",
+        );
+        let (from, to) = (Point::new(0, 0), Point::new(1, 1));
+
+        assert_eq!(steps(&map, DiagonalMovement::Always, from, to), Some(1));
+        assert_eq!(
+            steps(&map, DiagonalMovement::NoCornerCutting, from, to),
+            None
+        );
+        assert_eq!(steps(&map, DiagonalMovement::Never, from, to), None);
+
+        // A corner of a single blocked tile can't be cut either.
+        let map = grid(
+            "
+            .#
+            ..
+            ",
+        );
+        assert_eq!(steps(&map, DiagonalMovement::Always, from, to), Some(1));
+        assert_eq!(
+            steps(&map, DiagonalMovement::NoCornerCutting, from, to),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn walkers_go_around_shallow_water() {
+        let map = grid(
+            "
+            .......
+            .~~~~~.
+            .~~~~~.
+            ",
+        );
+
+        let steps = route(&map, Locomotion::Walker, Point::new(0, 2), Point::new(6, 2));
+        assert!(steps.iter().all(|point| {
+            map.tile_types[map.point_to_index(*point).unwrap()] != TileType::ShallowWater
+        }));
+
+        // Swimmers take the straight route through the water instead.
+        let steps = route(
+            &map,
+            Locomotion::Swimmer,
+            Point::new(0, 2),
+            Point::new(6, 2),
+        );
+        assert!(steps.iter().all(|point| point.y == 2));
+    }
+
+    #[test]
+    fn climbers_cross_rock_and_snow() {
+        let map = grid(
+            "
+            *****
+            .*^*.
+            .....
+            ",
+        );
+
+        let climber = route(
+            &map,
+            Locomotion::Climber,
+            Point::new(0, 1),
+            Point::new(4, 1),
+        );
+        assert!(climber.iter().all(|point| point.y == 1));
+
+        let walker = route(&map, Locomotion::Walker, Point::new(0, 1), Point::new(4, 1));
+        assert!(walker.iter().any(|point| point.y == 2));
+    }
+
+    #[test]
+    fn effective_speed_never_beats_grass() {
+        for locomotion in ALL_LOCOMOTIONS {
+            for tile_type in ALL_TILE_TYPES {
+                let speed =
+                    get_data(&tile_type).movement_speed * locomotion.terrain_modifier(tile_type);
+                assert!(speed <= 1.0, "{locomotion:?} on {tile_type:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn paths_go_around_crowded_tiles() {
+        let map = grid(
+            "
+            .....
+            .....
+            .....
+            ",
+        );
+        let (start, goal) = (tile(&map, (0, 1)), tile(&map, (4, 1)));
+        let crowded = HashSet::from_iter([tile(&map, (2, 1))]);
+        let find = |pathing: PathingMap| pathing.path_to_nearest(start, |index| index == goal);
+
+        let path = find(map.pathing(Locomotion::Walker)).unwrap();
+        assert!(path.contains(&tile(&map, (2, 1))));
+        let path = find(map.pathing(Locomotion::Walker).with_crowding(&crowded)).unwrap();
+        assert!(!path.contains(&tile(&map, (2, 1))));
+        assert_eq!(path.last(), Some(&goal));
+    }
+
+    #[test]
+    fn lines_cover_every_tile_they_touch() {
+        let map = grid(
+            "
+            .....
+            .....
+            .....
+            ",
+        );
+        let pathing = map.pathing(Locomotion::Walker);
+        let points = |from, to| -> Vec<Point> {
+            pathing
+                .line(tile(&map, from), tile(&map, to))
+                .into_iter()
+                .map(|index| map.index_to_point(index))
+                .collect()
+        };
+
+        assert_eq!(
+            points((0, 0), (2, 0)),
+            vec![Point::new(0, 0), Point::new(1, 0), Point::new(2, 0)]
+        );
+        // Passing exactly through a corner touches both tiles beside it.
+        assert_eq!(
+            points((0, 0), (1, 1)),
+            vec![
+                Point::new(0, 0),
+                Point::new(1, 0),
+                Point::new(0, 1),
+                Point::new(1, 1)
+            ]
+        );
+        assert_eq!(points((4, 0), (0, 2)).len(), 7);
+    }
+
+    #[test]
+    fn smoothing_only_keeps_the_turns() {
+        let map = grid(
+            "
+            ......
+            .###..
+            ......
+            .~~~~.
+            ",
+        );
+        let pathing = map.pathing(Locomotion::Walker);
+        let path: Vec<usize> = [(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 1), (5, 2)]
+            .into_iter()
+            .map(|point| tile(&map, point))
+            .collect();
+
+        let smoothed = pathing.smooth(&path);
+        assert_eq!(
+            smoothed,
+            vec![tile(&map, (0, 0)), tile(&map, (4, 0)), tile(&map, (5, 2))]
+        );
+
+        // The shortcut below the wall would cross the slower water.
+        let path: Vec<usize> = (0..6).map(|x| tile(&map, (x, 2))).collect();
+        assert_eq!(pathing.smooth(&path), vec![path[0], path[5]]);
+        let path = vec![
+            tile(&map, (0, 2)),
+            tile(&map, (1, 2)),
+            tile(&map, (2, 2)),
+            tile(&map, (3, 2)),
+            tile(&map, (4, 2)),
+            tile(&map, (5, 3)),
+        ];
+        assert_eq!(pathing.smooth(&path).len(), 3);
     }
 }
@@ -1,22 +1,21 @@
-use std::collections::HashMap;
-
-use bevy::prelude::{
-    default, shape, App, Assets, Commands, Mesh, PbrBundle, Plugin, Res, ResMut, Resource,
-    StandardMaterial, Transform,
-};
-use bevy_mod_picking::PickableBundle;
-use bevy_turborand::{DelegatedRng, GlobalRng, TurboRand};
-use bracket_pathfinding::prelude::Point;
-use noise::{NoiseFn, Perlin};
+use std::sync::Arc;
 
 use crate::AppStage;
+use bevy::prelude::{error, info, App, Commands, CoreStage, Plugin, Res, ResMut, Resource};
+use bevy_turborand::{DelegatedRng, GlobalRng, TurboRand};
+use serde::{Deserialize, Serialize};
 
 use super::{
-    tiles::{get_color, get_data, resolve_type},
+    chunks::update_chunks,
+    generators::{MapGenerator, PerlinGenerator},
+    occupancy::{update_occupancy, Occupancy},
+    pathfinding::DiagonalMovement,
+    regions::{update_regions, Regions},
+    spatial::{update_spatial_index, SpatialIndex},
     Map,
 };
 
-#[derive(Resource, Clone)]
+#[derive(Resource, Clone, Serialize, Deserialize)]
 pub(crate) struct MapSettings {
     pub(crate) width: i32,
     pub(crate) height: i32,
@@ -26,6 +25,12 @@ pub(crate) struct MapSettings {
 pub(crate) struct MapPlugin {
     pub(crate) tile_size: f32,
     pub(crate) map_size: (i32, i32),
+    /// Which generator creates the map.
+    pub(crate) generator: Arc<dyn MapGenerator>,
+    /// Where to save the generated map, if anywhere. See `Map::save`.
+    pub(crate) export_path: Option<String>,
+    /// When agents may step diagonally between tiles.
+    pub(crate) diagonals: DiagonalMovement,
 }
 
 impl Default for MapPlugin {
@@ -33,10 +38,21 @@ impl Default for MapPlugin {
         Self {
             tile_size: 1.0,
             map_size: (16, 16),
+            generator: Arc::new(PerlinGenerator),
+            export_path: None,
+            diagonals: DiagonalMovement::default(),
         }
     }
 }
 
+/// The generator the map is created with.
+#[derive(Resource)]
+struct SelectedGenerator(Arc<dyn MapGenerator>);
+
+/// Where the generated map is saved to.
+#[derive(Resource)]
+struct MapExport(String);
+
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(MapSettings {
@@ -44,72 +60,46 @@ impl Plugin for MapPlugin {
             width: self.map_size.0,
             height: self.map_size.1,
         })
+        .insert_resource(SelectedGenerator(self.generator.clone()))
+        .insert_resource(self.diagonals)
         .add_startup_system_to_stage(AppStage::SeedMap, seed_map)
-        .add_startup_system_to_stage(AppStage::SpawnMap, spawn_map);
+        .add_startup_system_to_stage(AppStage::SpawnMap, export_map)
+        .add_system(update_chunks)
+        .init_resource::<SpatialIndex>()
+        .init_resource::<Occupancy>()
+        .init_resource::<Regions>()
+        // Runs after entities have moved and been despawned during the frame.
+        .add_system_to_stage(CoreStage::PostUpdate, update_spatial_index)
+        .add_system_to_stage(CoreStage::PostUpdate, update_occupancy)
+        .add_system_to_stage(CoreStage::PostUpdate, update_regions);
+
+        if let Some(path) = &self.export_path {
+            app.insert_resource(MapExport(path.clone()));
+        }
     }
 }
 
 // 1. Create the map
-fn seed_map(mut cmd: Commands, settings: Res<MapSettings>, mut rng: ResMut<GlobalRng>) {
+fn seed_map(
+    mut cmd: Commands,
+    settings: Res<MapSettings>,
+    generator: Res<SelectedGenerator>,
+    mut rng: ResMut<GlobalRng>,
+) {
     let seed = rng.get_mut().u32(0..10_000);
-    cmd.insert_resource(generate_map(&settings, seed));
-}
-
-const SCALE: f64 = 3.5;
-
-/// Generates the map data
-pub(crate) fn generate_map(settings: &MapSettings, seed: u32) -> Map {
-    // Get a
-    let noise = Perlin::new(seed);
+    let map = generator.0.generate(&settings, seed);
 
-    let mut index: usize = 0;
-    let mut indexes = HashMap::new();
-    let mut data = HashMap::new();
-
-    for x in 0..settings.width {
-        for y in 0..settings.height {
-            let point = Point::new(x, y);
-            indexes.insert(index, point);
-
-            // Output is -1..=1
-            // Sampling must be done withing 0..=1 on X and Y
-            let noise_value = noise.get([
-                x as f64 * SCALE / settings.width as f64,
-                y as f64 * SCALE / settings.height as f64,
-            ]);
-
-            let tile_type = resolve_type(noise_value);
-
-            let tile_data = get_data(&tile_type);
-            data.insert(index, tile_data);
-            index += 1;
-        }
-    }
-
-    Map {
-        settings: settings.clone(),
-        indexes,
-        data,
-    }
+    // Some generators decide the size of the map themselves.
+    cmd.insert_resource(map.settings.clone());
+    cmd.insert_resource(map);
 }
 
-fn spawn_map(
-    mut cmd: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    map: Res<Map>,
-) {
-    for index in 0..map.indexes.len() {
-        cmd.spawn((
-            PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Plane {
-                    size: map.settings.tile_size,
-                })),
-                material: materials.add(get_color(map.data[&index].tile_type).into()),
-                transform: Transform::from_translation(map.index_to_world(index.into())),
-                ..default()
-            },
-            PickableBundle::default(),
-        ));
+/// Saves the map, so it can be edited by hand and loaded again with the grid generator.
+fn export_map(map: Res<Map>, export: Option<Res<MapExport>>) {
+    if let Some(export) = export {
+        match map.save(&export.0) {
+            Ok(()) => info!("Saved map to {}", export.0),
+            Err(error) => error!("Failed to save map to {}: {error}", export.0),
+        }
     }
 }
@@ -0,0 +1,511 @@
+//! Splits the map into regions, so paths across large maps are found without searching every
+//! tile along the way.
+//!
+//! The map is cut into square clusters, and the tiles in each cluster are grouped into regions
+//! that can reach each other without leaving it. A path is first found from region to region, and
+//! then from tile to tile only through the regions along the way. Which tiles can reach each other
+//! at all is known from which regions are connected.
+
+use std::collections::{BTreeSet, BinaryHeap};
+
+use bevy::{
+    prelude::{info, Res, ResMut, Resource, Vec2},
+    utils::{HashMap, HashSet},
+};
+use bracket_pathfinding::prelude::{BaseMap, Point};
+
+use super::{
+    pathfinding::{DiagonalMovement, Locomotion, PathingMap, Visit, ALL_LOCOMOTIONS},
+    Map,
+};
+
+/// The width and height of the clusters, in tiles.
+const CLUSTER_SIZE: i32 = 16;
+
+/// Tiles within a cluster that can all reach each other without leaving it.
+#[derive(Clone)]
+struct Region {
+    tiles: Vec<usize>,
+    /// The average position of the tiles.
+    centre: Vec2,
+    /// The regions in other clusters that can be stepped into from this one.
+    neighbours: BTreeSet<usize>,
+}
+
+/// The regions of the map as seen by agents getting around in a certain way.
+#[derive(Clone)]
+pub(crate) struct RegionGraph {
+    locomotion: Locomotion,
+    diagonals: DiagonalMovement,
+    /// How many clusters there are across the map.
+    clusters_wide: i32,
+    /// The region each tile belongs to. None for tiles that can't be crossed.
+    tile_regions: Vec<Option<usize>>,
+    /// The regions by id. Removed regions leave a gap until their id is reused.
+    regions: Vec<Option<Region>>,
+    free: Vec<usize>,
+    /// The ids of the regions in each cluster.
+    clusters: Vec<Vec<usize>>,
+    /// Whether each tile could be crossed when the regions were last updated.
+    crossable: Vec<bool>,
+    /// The connected component of each region, by id. Regions can only reach each other if they
+    /// are in the same component.
+    components: Vec<usize>,
+}
+
+impl RegionGraph {
+    /// Groups the tiles of the whole map into regions.
+    pub(crate) fn new(map: &Map, locomotion: Locomotion, diagonals: DiagonalMovement) -> Self {
+        let clusters_wide = (map.settings.width + CLUSTER_SIZE - 1) / CLUSTER_SIZE;
+        let clusters_high = (map.settings.height + CLUSTER_SIZE - 1) / CLUSTER_SIZE;
+        let mut graph = Self {
+            locomotion,
+            diagonals,
+            clusters_wide,
+            tile_regions: vec![None; map.tile_count()],
+            regions: Vec::new(),
+            free: Vec::new(),
+            clusters: vec![Vec::new(); (clusters_wide * clusters_high) as usize],
+            crossable: vec![false; map.tile_count()],
+            components: Vec::new(),
+        };
+
+        for cluster in 0..graph.clusters.len() {
+            graph.rebuild_cluster(map, cluster);
+        }
+        graph.connect_components();
+        graph
+    }
+
+    /// The map as the regions see it, for finding paths through them.
+    pub(crate) fn pathing<'a>(&self, map: &'a Map) -> PathingMap<'a> {
+        map.pathing(self.locomotion).with_diagonals(self.diagonals)
+    }
+
+    /// Regroups the clusters around the changed tiles that have become crossable or impassable.
+    pub(crate) fn update(&mut self, map: &Map, changed: impl IntoIterator<Item = usize>) {
+        // In order, so the regions are given the same ids on every run.
+        let mut dirty = BTreeSet::new();
+        for index in changed {
+            if self.crossable[index] != (map.terrain_speed(index, self.locomotion) > 0.0) {
+                // Whether corners can be cut between tiles in neighbouring clusters depends on
+                // this tile as well.
+                let point = map.index_to_point(index);
+                for y in -1..=1 {
+                    for x in -1..=1 {
+                        if let Some(neighbour) = map.point_to_index(point + Point::new(x, y)) {
+                            dirty.insert(self.cluster_of(map, neighbour));
+                        }
+                    }
+                }
+            }
+        }
+        if dirty.is_empty() {
+            return;
+        }
+
+        for cluster in dirty {
+            self.rebuild_cluster(map, cluster);
+        }
+        self.connect_components();
+    }
+
+    /// Whether there is any path between the tiles. Takes the same time however large the map.
+    pub(crate) fn is_reachable(&self, from: usize, to: usize) -> bool {
+        match (self.tile_regions[from], self.tile_regions[to]) {
+            (Some(from), Some(to)) => self.components[from] == self.components[to],
+            _ => false,
+        }
+    }
+
+    /// A path between the tiles, if there is any, as seen by the pathing map.
+    ///
+    /// Only the tiles in the regions along the cheapest way between the regions are searched, so
+    /// the path might be a little more costly than the cheapest one across the whole map.
+    pub(crate) fn path(
+        &self,
+        pathing: &PathingMap,
+        start: usize,
+        goal: usize,
+    ) -> Option<Vec<usize>> {
+        if !self.is_reachable(start, goal) {
+            return None;
+        }
+        let route = self.route(self.tile_regions[start]?, self.tile_regions[goal]?)?;
+
+        let corridor: HashSet<usize> = route.iter().copied().collect();
+        pathing.path_within(start, goal, |index| {
+            self.tile_regions[index].is_some_and(|id| corridor.contains(&id))
+        })
+    }
+
+    /// How many regions the map is split into.
+    pub(crate) fn region_count(&self) -> usize {
+        self.regions.len() - self.free.len()
+    }
+
+    fn region(&self, id: usize) -> &Region {
+        self.regions[id].as_ref().unwrap()
+    }
+
+    fn cluster_of(&self, map: &Map, index: usize) -> usize {
+        let point = map.index_to_point(index);
+        ((point.y / CLUSTER_SIZE) * self.clusters_wide + point.x / CLUSTER_SIZE) as usize
+    }
+
+    /// The cheapest way from region to region, by the distance between their centres.
+    fn route(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let target = self.region(to).centre;
+        let mut cost = HashMap::new();
+        let mut parents = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        cost.insert(from, 0.0);
+        queue.push(Visit {
+            cost: 0.0,
+            index: from,
+        });
+
+        while let Some(Visit { index, .. }) = queue.pop() {
+            if index == to {
+                let mut route = vec![index];
+                while let Some(parent) = parents.get(route.last().unwrap()) {
+                    route.push(*parent);
+                }
+                route.reverse();
+                return Some(route);
+            }
+
+            // Ids depend on how the regions came to be, so neighbours are gone through by their
+            // first tile instead, and routes come out the same however that was.
+            let region = self.region(index);
+            let mut neighbours: Vec<&usize> = region.neighbours.iter().collect();
+            neighbours.sort_by_key(|neighbour| self.region(**neighbour).tiles[0]);
+            for neighbour in neighbours {
+                let centre = self.region(*neighbour).centre;
+                let next = cost[&index] + region.centre.distance(centre);
+                if cost.get(neighbour).is_none_or(|known| next < *known) {
+                    cost.insert(*neighbour, next);
+                    parents.insert(*neighbour, index);
+                    queue.push(Visit {
+                        cost: next + centre.distance(target),
+                        index: *neighbour,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Removes the regions of the cluster, and groups its tiles into regions again.
+    fn rebuild_cluster(&mut self, map: &Map, cluster: usize) {
+        for id in std::mem::take(&mut self.clusters[cluster]) {
+            if let Some(region) = self.regions[id].take() {
+                for neighbour in &region.neighbours {
+                    if let Some(Some(other)) = self.regions.get_mut(*neighbour) {
+                        other.neighbours.remove(&id);
+                    }
+                }
+                for index in region.tiles {
+                    self.tile_regions[index] = None;
+                }
+                self.free.push(id);
+            }
+        }
+
+        let corner = Point::new(
+            (cluster as i32 % self.clusters_wide) * CLUSTER_SIZE,
+            (cluster as i32 / self.clusters_wide) * CLUSTER_SIZE,
+        );
+        let tiles: Vec<usize> = (corner.y..corner.y + CLUSTER_SIZE)
+            .flat_map(|y| (corner.x..corner.x + CLUSTER_SIZE).map(move |x| Point::new(x, y)))
+            .filter_map(|point| map.point_to_index(point))
+            .collect();
+        for index in &tiles {
+            self.crossable[*index] = map.terrain_speed(*index, self.locomotion) > 0.0;
+        }
+
+        let pathing = self.pathing(map);
+        for start in tiles {
+            if !self.crossable[start] || self.tile_regions[start].is_some() {
+                continue;
+            }
+            let id = self.free.pop().unwrap_or_else(|| {
+                self.regions.push(None);
+                self.regions.len() - 1
+            });
+
+            // Spreads out from the tile, staying within the cluster.
+            let mut region = Region {
+                tiles: vec![start],
+                centre: Vec2::ZERO,
+                neighbours: BTreeSet::new(),
+            };
+            self.tile_regions[start] = Some(id);
+            let mut open = vec![start];
+            while let Some(index) = open.pop() {
+                for (neighbour, _) in pathing.get_available_exits(index) {
+                    if self.cluster_of(map, neighbour) == cluster {
+                        if self.tile_regions[neighbour].is_none() {
+                            self.tile_regions[neighbour] = Some(id);
+                            region.tiles.push(neighbour);
+                            open.push(neighbour);
+                        }
+                    } else if let Some(other) = self.tile_regions[neighbour] {
+                        region.neighbours.insert(other);
+                    }
+                }
+            }
+
+            for other in &region.neighbours {
+                if let Some(Some(other)) = self.regions.get_mut(*other) {
+                    other.neighbours.insert(id);
+                }
+            }
+            let sum = region.tiles.iter().fold(Vec2::ZERO, |sum, index| {
+                let point = map.index_to_point(*index);
+                sum + Vec2::new(point.x as f32, point.y as f32)
+            });
+            region.centre = sum / region.tiles.len() as f32;
+            self.regions[id] = Some(region);
+            self.clusters[cluster].push(id);
+        }
+    }
+
+    /// Labels the regions that can reach each other with the same component.
+    fn connect_components(&mut self) {
+        self.components = vec![usize::MAX; self.regions.len()];
+        for start in 0..self.regions.len() {
+            if self.regions[start].is_none() || self.components[start] != usize::MAX {
+                continue;
+            }
+            self.components[start] = start;
+            let mut open = vec![start];
+            while let Some(id) = open.pop() {
+                // The fields are borrowed separately, so the components can be changed.
+                for neighbour in &self.regions[id].as_ref().unwrap().neighbours {
+                    if self.components[*neighbour] == usize::MAX {
+                        self.components[*neighbour] = start;
+                        open.push(*neighbour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The regions of the map for each locomotion, kept up to date as tiles change.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct Regions {
+    graphs: HashMap<Locomotion, RegionGraph>,
+    /// The revision of the map the regions were last updated for.
+    revision: Option<usize>,
+}
+
+impl Regions {
+    /// Groups the tiles of the map into regions for every locomotion.
+    pub(crate) fn new(map: &Map, diagonals: DiagonalMovement) -> Self {
+        Self {
+            graphs: ALL_LOCOMOTIONS
+                .into_iter()
+                .map(|locomotion| (locomotion, RegionGraph::new(map, locomotion, diagonals)))
+                .collect(),
+            revision: Some(map.revision()),
+        }
+    }
+
+    /// The regions as seen by agents getting around in the given way.
+    pub(crate) fn graph(&self, locomotion: Locomotion) -> &RegionGraph {
+        &self.graphs[&locomotion]
+    }
+}
+
+/// Builds the regions once the map exists, and regroups the tiles that change.
+pub(crate) fn update_regions(
+    mut regions: ResMut<Regions>,
+    map: Res<Map>,
+    diagonals: Res<DiagonalMovement>,
+) {
+    if regions.revision == Some(map.revision()) && !map.is_added() && !diagonals.is_changed() {
+        return;
+    }
+    let changes = regions
+        .revision
+        .filter(|_| !map.is_added() && !diagonals.is_changed())
+        .and_then(|revision| map.changes_since(revision))
+        .map(Vec::from_iter);
+
+    if let Some(changes) = changes {
+        regions.revision = Some(map.revision());
+        for graph in regions.graphs.values_mut() {
+            graph.update(&map, changes.iter().copied());
+        }
+    } else {
+        // The map is new, or has changed further back than it remembers.
+        *regions = Regions::new(&map, *diagonals);
+        let count = regions.graph(Locomotion::Walker).region_count();
+        info!("Split the map into {count} regions");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::map::{
+        generators::{GridGenerator, MapGenerator},
+        io::parse_ascii,
+        pathfinding::{DiagonalMovement, Locomotion},
+        plugin::MapSettings,
+        tiles::{get_data, TileType},
+        Map,
+    };
+
+    use super::RegionGraph;
+
+    fn grid(text: &str) -> Map {
+        let settings = MapSettings {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+        };
+        GridGenerator::new(parse_ascii(text).unwrap()).generate(&settings, 0)
+    }
+
+    fn tile(map: &Map, x: i32, y: i32) -> usize {
+        map.point_to_index(Point::new(x, y)).unwrap()
+    }
+
+    /// A map wider than a cluster, with a wall splitting it except for a gap at the bottom.
+    fn walled() -> Map {
+        let row = |wall: char| format!("{}{wall}{}\n", ".".repeat(20), ".".repeat(19));
+        let mut text = row('#').repeat(19);
+        text.push_str(&row('.'));
+        grid(&text)
+    }
+
+    #[test]
+    fn paths_lead_through_the_regions() {
+        let map = walled();
+        let graph = RegionGraph::new(&map, Locomotion::Walker, DiagonalMovement::default());
+        assert!(graph.region_count() >= 4);
+
+        let (start, goal) = (tile(&map, 2, 2), tile(&map, 37, 3));
+        assert!(graph.is_reachable(start, goal));
+        let path = graph.path(&graph.pathing(&map), start, goal).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        assert!(path.contains(&tile(&map, 20, 19)));
+        for pair in path.windows(2) {
+            assert!(map.get_neighbours(pair[0]).contains(&pair[1]));
+        }
+
+        assert!(!graph.is_reachable(start, tile(&map, 20, 0)));
+        assert_eq!(
+            graph.path(&graph.pathing(&map), start, tile(&map, 20, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn updates_match_a_rebuild() {
+        let mut map = walled();
+        let mut graph = RegionGraph::new(&map, Locomotion::Walker, DiagonalMovement::default());
+        let (left, right) = (tile(&map, 2, 2), tile(&map, 37, 3));
+
+        // Flooding the gap cuts the map in two.
+        let revision = map.revision();
+        map.set_tile(tile(&map, 20, 19), get_data(&TileType::DeepWater));
+        graph.update(&map, map.changes_since(revision).unwrap());
+        assert!(!graph.is_reachable(left, right));
+
+        // Opening a new one joins it again, while tiles that were changed but can be crossed as
+        // before are left alone.
+        let revision = map.revision();
+        map.set_tile(tile(&map, 20, 5), get_data(&TileType::Grass));
+        map.set_tile(tile(&map, 2, 2), get_data(&TileType::Sand));
+        graph.update(&map, map.changes_since(revision).unwrap());
+        assert!(graph.is_reachable(left, right));
+
+        let rebuilt = RegionGraph::new(&map, Locomotion::Walker, DiagonalMovement::default());
+        assert_eq!(graph.region_count(), rebuilt.region_count());
+        for index in 0..map.tile_count() {
+            assert_eq!(
+                graph.is_reachable(left, index),
+                rebuilt.is_reachable(left, index)
+            );
+        }
+    }
+
+    /// Compares finding paths through the regions to searching every tile.
+    ///
+    /// Run with `cargo test --release benchmark_region_paths -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn benchmark_region_paths() {
+        use std::time::Instant;
+
+        use bracket_pathfinding::prelude::a_star_search;
+
+        use crate::map::generators::PerlinGenerator;
+
+        for size in [256, 512, 1024] {
+            let settings = MapSettings {
+                width: size,
+                height: size,
+                tile_size: 1.0,
+            };
+            let map = PerlinGenerator.generate(&settings, 0);
+
+            let start = Instant::now();
+            let graph = RegionGraph::new(&map, Locomotion::Walker, DiagonalMovement::default());
+            let build = start.elapsed();
+
+            // The crossable tile closest to each point, scanning along the row.
+            let crossable = |x: i32, y: i32| {
+                (x..size)
+                    .map(|x| tile(&map, x, y))
+                    .find(|index| map.terrain_speed(*index, Locomotion::Walker) > 0.0)
+                    .unwrap()
+            };
+            let pairs = [
+                (
+                    crossable(size / 8, size / 8),
+                    crossable(size * 7 / 8, size * 7 / 8),
+                ),
+                (crossable(0, size / 2), crossable(size / 2, size - 1)),
+                (
+                    crossable(size / 4, size * 3 / 4),
+                    crossable(size / 2, size / 4),
+                ),
+            ];
+
+            let pathing = graph.pathing(&map);
+            let (mut flat, mut hierarchical, mut reachable) = (0.0, 0.0, 0.0);
+            for (from, to) in pairs {
+                let start = Instant::now();
+                let path = a_star_search(from, to, &pathing);
+                flat += start.elapsed().as_secs_f32();
+
+                let start = Instant::now();
+                let found = graph.path(&pathing, from, to);
+                hierarchical += start.elapsed().as_secs_f32();
+
+                let start = Instant::now();
+                let connected = graph.is_reachable(from, to);
+                reachable += start.elapsed().as_secs_f32();
+
+                assert_eq!(found.is_some(), connected);
+                assert!(!path.success || connected);
+            }
+
+            println!(
+                "{size}x{size}: {} regions built in {build:?}, a_star_search {:.2}ms, regions {:.2}ms, reachable {:.4}ms",
+                graph.region_count(),
+                flat * 1000.0,
+                hierarchical * 1000.0,
+                reachable * 1000.0,
+            );
+        }
+    }
+}
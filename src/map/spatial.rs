@@ -0,0 +1,276 @@
+//! Keeps track of which entities are on each tile, so nearby entities can be found without
+//! looking through every entity in the world.
+
+use bevy::{
+    prelude::{Changed, Entity, Query, RemovedComponents, ResMut, Resource},
+    utils::HashMap,
+};
+use bracket_pathfinding::prelude::{BaseMap, Point};
+
+use crate::serial::Serial;
+
+use super::{tiles::MapIndex, Map};
+
+/// Buckets of entities keyed by the tile they are on.
+///
+/// Updated automatically for every entity with a `MapIndex`, at the end of each frame.
+#[derive(Resource, Default)]
+pub(crate) struct SpatialIndex {
+    /// The entities on each tile, in the order of their serials, so they are found in the same
+    /// order however they got there. Those without a serial come first.
+    buckets: HashMap<usize, Vec<Entity>>,
+    /// The tile each entity is on, along with its serial.
+    locations: HashMap<Entity, (usize, Option<Serial>)>,
+}
+
+impl SpatialIndex {
+    /// Places an entity on a tile, moving it from wherever it was before.
+    pub(crate) fn insert(&mut self, entity: Entity, serial: Option<Serial>, index: usize) {
+        if let Some((previous, _)) = self.locations.insert(entity, (index, serial)) {
+            if previous == index {
+                return;
+            }
+            self.remove_from_bucket(entity, previous);
+        }
+        let locations = &self.locations;
+        let bucket = self.buckets.entry(index).or_default();
+        let at = bucket.partition_point(|other| (locations[other].1, *other) < (serial, entity));
+        bucket.insert(at, entity);
+    }
+
+    /// Removes an entity from the index.
+    pub(crate) fn remove(&mut self, entity: Entity) {
+        if let Some((previous, _)) = self.locations.remove(&entity) {
+            self.remove_from_bucket(entity, previous);
+        }
+    }
+
+    fn remove_from_bucket(&mut self, entity: Entity, index: usize) {
+        if let Some(bucket) = self.buckets.get_mut(&index) {
+            bucket.retain(|other| *other != entity);
+            if bucket.is_empty() {
+                self.buckets.remove(&index);
+            }
+        }
+    }
+
+    /// All entities on a tile.
+    pub(crate) fn entities_at(&self, index: usize) -> &[Entity] {
+        self.buckets.get(&index).map_or(&[], Vec::as_slice)
+    }
+
+    /// All entities within the radius of a tile, along with the tile they are on.
+    ///
+    /// Only entities accepted by the filter are included, e.g. `|e| food_sources.contains(e)`.
+    pub(crate) fn within(
+        &self,
+        map: &Map,
+        origin: usize,
+        radius: f32,
+        mut filter: impl FnMut(Entity) -> bool,
+    ) -> Vec<(Entity, usize)> {
+        let centre = map.index_to_point(origin);
+        let reach = radius.ceil() as i32;
+
+        let mut found = Vec::new();
+        for y in centre.y - reach..=centre.y + reach {
+            for x in centre.x - reach..=centre.x + reach {
+                if let Some(index) = map.point_to_index(Point::new(x, y)) {
+                    if map.get_pathing_distance(origin, index) <= radius {
+                        found.extend(
+                            self.entities_at(index)
+                                .iter()
+                                .filter(|entity| filter(**entity))
+                                .map(|entity| (*entity, index)),
+                        );
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// The entities closest to a tile, nearest first, along with the tile they are on.
+    ///
+    /// Only entities accepted by the filter are included. The search spreads out from the tile
+    /// ring by ring, so it only looks as far as it needs to.
+    pub(crate) fn nearest(
+        &self,
+        map: &Map,
+        origin: usize,
+        count: usize,
+        mut filter: impl FnMut(Entity) -> bool,
+    ) -> Vec<(Entity, usize)> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let centre = map.index_to_point(origin);
+        let max_ring = map.settings.width.max(map.settings.height);
+
+        let mut found: Vec<(f32, Entity, usize)> = Vec::new();
+        let mut seen = 0;
+        for ring in 0..=max_ring {
+            // Everything in this ring and further out is at least `ring` tiles away.
+            if found.len() >= count {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0));
+                if found[count - 1].0 <= ring as f32 {
+                    break;
+                }
+            }
+            if seen == self.locations.len() {
+                break;
+            }
+
+            for index in ring_points(centre, ring).filter_map(|point| map.point_to_index(point)) {
+                for entity in self.entities_at(index) {
+                    seen += 1;
+                    if filter(*entity) {
+                        found.push((map.get_pathing_distance(origin, index), *entity, index));
+                    }
+                }
+            }
+        }
+
+        found.sort_by(|a, b| a.0.total_cmp(&b.0));
+        found
+            .into_iter()
+            .take(count)
+            .map(|(_, entity, index)| (entity, index))
+            .collect()
+    }
+}
+
+/// The points making up the outline of a square around the centre.
+fn ring_points(centre: Point, ring: i32) -> impl Iterator<Item = Point> {
+    (-ring..=ring).flat_map(move |y| {
+        // Only the first and last rows are filled, the rest only have their ends.
+        let step = if y == -ring || y == ring {
+            1
+        } else {
+            (2 * ring).max(1)
+        };
+        (-ring..=ring)
+            .step_by(step as usize)
+            .map(move |x| centre + Point::new(x, y))
+    })
+}
+
+/// Moves entities in the index as their position on the map changes.
+pub(crate) fn update_spatial_index(
+    mut spatial: ResMut<SpatialIndex>,
+    moved: Query<(Entity, Option<&Serial>, &MapIndex), Changed<MapIndex>>,
+    removed: RemovedComponents<MapIndex>,
+) {
+    for entity in removed.iter() {
+        spatial.remove(entity);
+    }
+    for (entity, serial, index) in &moved {
+        spatial.insert(entity, serial.copied(), index.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Entity;
+    use bracket_pathfinding::prelude::Point;
+
+    use crate::{
+        map::{
+            generators::{FlatGenerator, MapGenerator},
+            plugin::MapSettings,
+            Map,
+        },
+        serial::Serials,
+    };
+
+    use super::{ring_points, SpatialIndex};
+
+    fn map() -> Map {
+        let settings = MapSettings {
+            width: 20,
+            height: 12,
+            tile_size: 1.0,
+        };
+        FlatGenerator::default().generate(&settings, 0)
+    }
+
+    fn tile(map: &Map, x: i32, y: i32) -> usize {
+        map.point_to_index(Point::new(x, y)).unwrap()
+    }
+
+    #[test]
+    fn rings_outline_a_square() {
+        assert_eq!(ring_points(Point::new(0, 0), 0).count(), 1);
+        assert_eq!(ring_points(Point::new(0, 0), 1).count(), 8);
+        assert_eq!(ring_points(Point::new(0, 0), 3).count(), 24);
+        assert!(ring_points(Point::new(5, 5), 2)
+            .all(|point| { (point.x - 5).abs().max((point.y - 5).abs()) == 2 }));
+    }
+
+    #[test]
+    fn entities_move_between_tiles() {
+        let map = map();
+        let mut spatial = SpatialIndex::default();
+        let entity = Entity::from_raw(1);
+
+        spatial.insert(entity, None, tile(&map, 1, 1));
+        spatial.insert(entity, None, tile(&map, 2, 1));
+        assert!(spatial.entities_at(tile(&map, 1, 1)).is_empty());
+        assert_eq!(spatial.entities_at(tile(&map, 2, 1)), &[entity]);
+
+        spatial.remove(entity);
+        assert!(spatial.entities_at(tile(&map, 2, 1)).is_empty());
+    }
+
+    #[test]
+    fn entities_on_a_tile_keep_to_their_serials() {
+        let map = map();
+        let mut serials = Serials::default();
+        let (first, second) = (serials.next(), serials.next());
+        let mut spatial = SpatialIndex::default();
+        let (a, b, c) = (
+            Entity::from_raw(3),
+            Entity::from_raw(2),
+            Entity::from_raw(1),
+        );
+
+        spatial.insert(a, Some(second), tile(&map, 2, 1));
+        spatial.insert(b, Some(first), tile(&map, 1, 1));
+        spatial.insert(c, None, tile(&map, 2, 1));
+        spatial.insert(b, Some(first), tile(&map, 2, 1));
+        assert_eq!(spatial.entities_at(tile(&map, 2, 1)), &[c, b, a]);
+    }
+
+    #[test]
+    fn finds_nearby_entities() {
+        let map = map();
+        let mut spatial = SpatialIndex::default();
+        let near = Entity::from_raw(1);
+        let middle = Entity::from_raw(2);
+        let far = Entity::from_raw(3);
+        let ignored = Entity::from_raw(4);
+        spatial.insert(near, None, tile(&map, 6, 5));
+        spatial.insert(middle, None, tile(&map, 5, 8));
+        spatial.insert(far, None, tile(&map, 17, 10));
+        spatial.insert(ignored, None, tile(&map, 5, 5));
+
+        let origin = tile(&map, 5, 5);
+        let accept = |entity| entity != ignored;
+
+        assert_eq!(
+            spatial.nearest(&map, origin, 2, accept),
+            vec![(near, tile(&map, 6, 5)), (middle, tile(&map, 5, 8))]
+        );
+        assert_eq!(spatial.nearest(&map, origin, 5, accept).len(), 3);
+        assert!(spatial.nearest(&map, origin, 0, accept).is_empty());
+
+        let mut within: Vec<Entity> = spatial
+            .within(&map, origin, 3.0, accept)
+            .into_iter()
+            .map(|(entity, _)| entity)
+            .collect();
+        within.sort();
+        assert_eq!(within, vec![near, middle]);
+    }
+}
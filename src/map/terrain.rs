@@ -0,0 +1,128 @@
+//! Layered noise fields that the terrain of the map is generated from.
+
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+
+use super::tiles::{get_data, TileData, TileType};
+
+/// How many times the map is covered by the lowest frequency of the noise.
+const SCALE: f64 = 3.5;
+/// How many layers of noise makes up the elevation, each with finer detail.
+const ELEVATION_OCTAVES: usize = 5;
+/// Moisture changes slower across the map than elevation.
+const MOISTURE_OCTAVES: usize = 3;
+
+/// The temperature at sea level along the equator, in degrees celsius.
+const EQUATOR_TEMPERATURE: f32 = 25.0;
+/// How much colder it is at the poles than at the equator.
+const POLE_COOLING: f32 = 20.0;
+/// How much colder it is at the highest elevation than at sea level.
+const ALTITUDE_COOLING: f32 = 30.0;
+
+/// The climate and shape of the land at a single point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct TerrainSample {
+    /// Height of the land relative to sea level. Range: -1.0..=1.0
+    pub(crate) elevation: f32,
+    /// How wet the land is. Range: 0.0..=1.0
+    pub(crate) moisture: f32,
+    /// Mean temperature in degrees celsius.
+    pub(crate) temperature: f32,
+}
+
+impl TerrainSample {
+    /// Creates the data of a tile with this shape and climate.
+    pub(crate) fn to_tile(self) -> TileData {
+        let mut tile = get_data(&classify(&self));
+        tile.elevation = self.elevation;
+        tile.temperature = self.temperature;
+        // Water is always wet, but the moisture of land follows the climate.
+        if tile.moisture < 1.0 {
+            tile.moisture = self.moisture;
+        }
+        tile
+    }
+}
+
+/// Samples elevation, moisture and temperature from separate noise fields.
+pub(crate) struct TerrainNoise {
+    elevation: Fbm<Perlin>,
+    moisture: Fbm<Perlin>,
+    width: f64,
+    height: f64,
+}
+
+impl TerrainNoise {
+    pub(crate) fn new(seed: u32, width: i32, height: i32) -> Self {
+        Self {
+            elevation: Fbm::<Perlin>::new(seed).set_octaves(ELEVATION_OCTAVES),
+            // Use a different seed so moisture does not simply follow the elevation.
+            moisture: Fbm::<Perlin>::new(seed.wrapping_add(1)).set_octaves(MOISTURE_OCTAVES),
+            width: width as f64,
+            height: height as f64,
+        }
+    }
+
+    pub(crate) fn sample(&self, x: i32, y: i32) -> TerrainSample {
+        // Sampling must be done withing 0..=1 on X and Y
+        let point = [
+            x as f64 * SCALE / self.width,
+            y as f64 * SCALE / self.height,
+        ];
+
+        // Output is roughly -1..=1
+        let elevation = (self.elevation.get(point) as f32).clamp(-1.0, 1.0);
+        let moisture = ((self.moisture.get(point) as f32 + 1.0) / 2.0).clamp(0.0, 1.0);
+
+        // The middle row of the map is the equator, with the poles at the edges.
+        let latitude = ((y as f64 / self.height) * 2.0 - 1.0).abs() as f32;
+        let temperature =
+            EQUATOR_TEMPERATURE - latitude * POLE_COOLING - elevation.max(0.0) * ALTITUDE_COOLING;
+
+        TerrainSample {
+            elevation,
+            moisture,
+            temperature,
+        }
+    }
+}
+
+/// Decides which biome a point on the map belongs to.
+pub(crate) fn classify(sample: &TerrainSample) -> TileType {
+    if sample.elevation < -0.3 {
+        TileType::DeepWater
+    } else if sample.elevation < 0.0 {
+        TileType::ShallowWater
+    } else if sample.temperature < 0.0 {
+        TileType::Snow
+    } else if sample.elevation > 0.6 {
+        TileType::Rock
+    } else if sample.elevation < 0.05 || sample.moisture < 0.3 {
+        TileType::Sand
+    } else {
+        TileType::Grass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::tiles::TileType;
+
+    use super::{classify, TerrainSample};
+
+    #[test]
+    fn classify_biomes() {
+        let sample = |elevation, moisture, temperature| TerrainSample {
+            elevation,
+            moisture,
+            temperature,
+        };
+
+        assert_eq!(classify(&sample(-0.5, 1.0, 10.0)), TileType::DeepWater);
+        assert_eq!(classify(&sample(-0.1, 1.0, 10.0)), TileType::ShallowWater);
+        assert_eq!(classify(&sample(0.02, 0.8, 10.0)), TileType::Sand);
+        assert_eq!(classify(&sample(0.3, 0.1, 20.0)), TileType::Sand);
+        assert_eq!(classify(&sample(0.3, 0.6, 15.0)), TileType::Grass);
+        assert_eq!(classify(&sample(0.8, 0.6, 5.0)), TileType::Rock);
+        assert_eq!(classify(&sample(0.8, 0.6, -5.0)), TileType::Snow);
+    }
+}
@@ -1,14 +1,10 @@
 //! Collection of functionality tied to individual tiles.
 
-use bevy::prelude::{Color, Component, Vec3};
-use bracket_pathfinding::prelude::Algorithm2D;
-
-use crate::utils::Vec2;
-
-use super::{plugin::MapSettings, Map};
+use bevy::prelude::{Color, Component};
+use serde::{Deserialize, Serialize};
 
 /// Marks where on the map an entitiy is located.
-#[derive(Copy, Clone, Debug, PartialEq, Component)]
+#[derive(Copy, Clone, Debug, PartialEq, Component, Serialize, Deserialize)]
 pub(crate) struct MapIndex(pub usize);
 
 impl From<usize> for MapIndex {
@@ -33,11 +29,26 @@ pub(crate) struct TileData {
     /// How fast can flora grow on this tile?
     pub(crate) growability: f32,
     /// How wet is this tile?
-    #[allow(dead_code)]
-    moisture: f32,
+    pub(crate) moisture: f32,
+    /// How much nutrients are available in the soil of this tile?
+    pub(crate) nutrients: f32,
     /// How much light is this tile receiving at this moment?
     #[allow(dead_code)]
     brightness: f32,
+    /// Height of the land relative to sea level. Range: -1.0..=1.0
+    pub(crate) elevation: f32,
+    /// Mean temperature of this tile in degrees celsius.
+    pub(crate) temperature: f32,
+}
+
+/// How high up in world units the highest elevation reaches.
+const HEIGHT_SCALE: f32 = 3.0;
+
+/// The height in the world of a surface at the given elevation.
+///
+/// Water has a flat surface at sea level, no matter how deep it is.
+pub(crate) fn world_height(elevation: f32) -> f32 {
+    elevation.max(0.0) * HEIGHT_SCALE
 }
 
 // TODO: Move this to a file or asset
@@ -47,35 +58,70 @@ const GRASS_DATA: TileData = TileData {
     movement_speed: 1.0,
     growability: 1.0,
     moisture: 0.7,
+    nutrients: 0.5,
     brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
 };
 const SAND_DATA: TileData = TileData {
     tile_type: TileType::Sand,
     movement_speed: 0.9,
     growability: 0.8,
     moisture: 0.5,
+    nutrients: 0.2,
     brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
 };
 const ROCK_DATA: TileData = TileData {
     tile_type: TileType::Rock,
     movement_speed: 0.8,
     growability: 0.1,
     moisture: 0.0,
+    nutrients: 0.0,
+    brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
+};
+const SNOW_DATA: TileData = TileData {
+    tile_type: TileType::Snow,
+    movement_speed: 0.6,
+    growability: 0.05,
+    moisture: 0.8,
+    nutrients: 0.0,
     brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
 };
 const SHALLOW_WATER_DATA: TileData = TileData {
     tile_type: TileType::ShallowWater,
     movement_speed: 0.3,
     growability: 0.0,
     moisture: 1.0,
+    nutrients: 0.0,
     brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
+};
+const RIVER_DATA: TileData = TileData {
+    tile_type: TileType::River,
+    movement_speed: 0.3,
+    growability: 0.0,
+    moisture: 1.0,
+    nutrients: 0.0,
+    brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
 };
 const DEEP_WATER_DATA: TileData = TileData {
     tile_type: TileType::DeepWater,
     movement_speed: 0.0,
     growability: 0.0,
     moisture: 1.0,
+    nutrients: 0.0,
     brightness: 0.0,
+    elevation: 0.0,
+    temperature: 0.0,
 };
 
 pub(crate) const fn get_data(tile_type: &TileType) -> TileData {
@@ -83,56 +129,33 @@ pub(crate) const fn get_data(tile_type: &TileType) -> TileData {
         TileType::Grass => GRASS_DATA,
         TileType::Sand => SAND_DATA,
         TileType::Rock => ROCK_DATA,
+        TileType::Snow => SNOW_DATA,
         TileType::ShallowWater => SHALLOW_WATER_DATA,
+        TileType::River => RIVER_DATA,
         TileType::DeepWater => DEEP_WATER_DATA,
     }
 }
 
-pub(crate) fn resolve_type(value: f64) -> TileType {
-    if value > 0.9 {
-        TileType::Rock
-    } else if value > 0.2 {
-        TileType::Grass
-    } else if value > 0.0 {
-        TileType::Sand
-    } else if value > -0.3 {
-        TileType::ShallowWater
-    } else {
-        TileType::DeepWater
-    }
-}
-
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum TileType {
     Grass,
     Sand,
     Rock,
+    Snow,
     ShallowWater,
+    River,
     DeepWater,
 }
-/// Converts from a tile-position to a world-position.
-pub(crate) fn pos_to_world(pos: Vec2<i32>, settings: &MapSettings) -> Vec3 {
-    Vec3::new(
-        pos.x as f32 * settings.tile_size - settings.width as f32 / 2.0,
-        0.0,
-        pos.y as f32 * settings.tile_size - settings.height as f32 / 2.0,
-    )
-}
-
-/// Converts from world-position to tile-position.
-pub(crate) fn world_to_pos(world_pos: &Vec3, settings: &MapSettings) -> Vec2<i32> {
-    Vec2::new(
-        ((world_pos.x + (settings.width as f32 / 2.0)) / settings.tile_size) as i32,
-        ((world_pos.z + (settings.height as f32 / 2.0)) / settings.tile_size) as i32,
-    )
-}
-
-pub(crate) fn world_to_index(world: &Vec3, map: &Map) -> MapIndex {
-    let pos = world_to_pos(world, &map.settings);
-    let index = map.point2d_to_index(pos.into());
-
-    MapIndex(index)
-}
+/// All the types a tile can be.
+pub(crate) const ALL_TILE_TYPES: [TileType; 7] = [
+    TileType::Grass,
+    TileType::Sand,
+    TileType::Rock,
+    TileType::Snow,
+    TileType::ShallowWater,
+    TileType::River,
+    TileType::DeepWater,
+];
 
 /// Gets the corresponding material color for a `GroundType`.
 /// TODO: Replace with actual textures and assets.
@@ -141,7 +164,29 @@ pub(crate) fn get_color(tile_type: TileType) -> Color {
         TileType::Grass => Color::rgb(0.1, 0.7, 0.25),
         TileType::Sand => Color::rgb(0.55, 0.5, 0.3),
         TileType::Rock => Color::rgb(0.5, 0.5, 0.5),
+        TileType::Snow => Color::rgb(0.9, 0.9, 0.95),
         TileType::ShallowWater => Color::rgb(0.0, 0.4, 0.6),
+        TileType::River => Color::rgb(0.1, 0.45, 0.7),
         TileType::DeepWater => Color::rgb(0.0, 0.2, 0.7),
     }
 }
+
+/// Gets the symbol that represents a `TileType` in a hand-authored grid.
+pub(crate) const fn get_symbol(tile_type: TileType) -> char {
+    match tile_type {
+        TileType::Grass => '.',
+        TileType::Sand => ',',
+        TileType::Rock => '^',
+        TileType::Snow => '*',
+        TileType::ShallowWater => '~',
+        TileType::River => '=',
+        TileType::DeepWater => '#',
+    }
+}
+
+/// Gets the `TileType` a symbol in a hand-authored grid represents, if any.
+pub(crate) fn tile_from_symbol(symbol: char) -> Option<TileType> {
+    ALL_TILE_TYPES
+        .into_iter()
+        .find(|tile_type| get_symbol(*tile_type) == symbol)
+}
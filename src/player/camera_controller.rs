@@ -126,8 +126,7 @@ fn update_camera_input(
             }
             if action.pressed(CameraMovement::Zoom) {
                 // Zoom in and out from a target
-                controller
-                    .add_zoom(action.value(CameraMovement::Zoom) * settings.zoom_speed * -1.0);
+                controller.add_zoom(-(action.value(CameraMovement::Zoom) * settings.zoom_speed));
             }
         } else {
             warn!("CameraController does not have a valid camera-target component");
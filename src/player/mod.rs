@@ -1,18 +1,27 @@
+use std::path::PathBuf;
+
 use bevy::prelude::{
-    default, info, shape, App, Assets, Camera3dBundle, Color, Commands, EventWriter, KeyCode, Mesh,
-    PbrBundle, Plugin, Query, ResMut, StandardMaterial,
+    default, info, shape, App, Assets, Camera3dBundle, Color, Commands, CoreStage, EventReader,
+    EventWriter, GlobalTransform, KeyCode, Mesh, PbrBundle, Plugin, Query, Res, ResMut,
+    StandardMaterial, With,
 };
 use bevy_mod_picking::{DefaultPickingPlugins, PickingCameraBundle, Selection};
 use leafwing_input_manager::{
     axislike::VirtualAxis,
     prelude::{ActionState, InputManagerPlugin, InputMap, SingleAxis, VirtualDPad},
-    InputManagerBundle,
+    Actionlike, InputManagerBundle,
 };
+use serde::{Deserialize, Serialize};
 
 use crate::{
     chronos::{SimulationSpeed, TimeMultiplierEvent},
-    fauna::needs::{Health, Hunger, Reproduction, Thirst},
+    fauna::{
+        needs::{Health, Hunger, Reproduction, Thirst},
+        SpawnFauna,
+    },
+    map::{tiles::MapIndex, Map},
     resource::{FoodSource, WaterSource},
+    save::{LoadGame, SaveAction, SaveGame, QUICKSAVE_PATH},
 };
 
 use self::{
@@ -34,7 +43,8 @@ pub(crate) struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(DefaultPickingPlugins)
+        app.add_event::<PlayerCommand>()
+            .add_plugins(DefaultPickingPlugins)
             .add_plugin(CameraControllerPlugin {
                 settings: CameraControllerSettings {
                     translation_speed: 0.1,
@@ -44,16 +54,54 @@ impl Plugin for PlayerPlugin {
                 },
             })
             .add_plugin(InputManagerPlugin::<SimulationSpeed>::default())
+            .add_plugin(InputManagerPlugin::<SaveAction>::default())
+            .add_plugin(InputManagerPlugin::<WorldAction>::default())
             .add_plugin(UserInterfacePlugin)
             .add_plugin(WidgetPlugin)
             .add_startup_system(spawn_player)
             .add_startup_system(spawn_simulation_input)
+            .add_startup_system(spawn_save_input)
             .add_system(update_simulation_speed)
+            .add_system(spawn_at_target)
+            // Commands given this frame are carried out on the next, so they can be recorded and
+            // replayed before they are.
+            .add_system_to_stage(CoreStage::PreUpdate, apply_player_commands)
+            .add_system(quick_save)
             .add_system(output_fauna_data)
             .add_system(output_flora_data);
     }
 }
 
+/// Something the player asks of the simulation.
+///
+/// Everything the player changes in the simulation goes through these, so a run can be replayed
+/// from the commands given during it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum PlayerCommand {
+    SetSpeed(SimulationSpeed),
+    SpawnFauna(MapIndex),
+}
+
+pub(crate) fn apply_player_commands(
+    mut commands: EventReader<PlayerCommand>,
+    mut speed: EventWriter<TimeMultiplierEvent>,
+    mut spawns: EventWriter<SpawnFauna>,
+) {
+    for command in commands.iter() {
+        match *command {
+            PlayerCommand::SetSpeed(value) => speed.send(TimeMultiplierEvent(value)),
+            PlayerCommand::SpawnFauna(index) => spawns.send(SpawnFauna(Some(index))),
+        }
+    }
+}
+
+/// Changes the player can make to the world.
+#[derive(Actionlike, Debug, Clone, Copy)]
+enum WorldAction {
+    /// Spawns an agent where the camera is looking.
+    SpawnFauna,
+}
+
 fn spawn_simulation_input(mut cmd: Commands) {
     cmd.spawn(InputManagerBundle::<SimulationSpeed> {
         input_map: InputMap::default()
@@ -66,24 +114,74 @@ fn spawn_simulation_input(mut cmd: Commands) {
             .build(),
         ..default()
     });
+    cmd.spawn(InputManagerBundle::<WorldAction> {
+        input_map: InputMap::default()
+            .insert(KeyCode::F, WorldAction::SpawnFauna)
+            .build(),
+        ..default()
+    });
 }
 
 fn update_simulation_speed(
     q: Query<&ActionState<SimulationSpeed>>,
-    mut event: EventWriter<TimeMultiplierEvent>,
+    mut event: EventWriter<PlayerCommand>,
 ) {
     for action in &q {
         if action.just_pressed(SimulationSpeed::Paused) {
-            event.send(TimeMultiplierEvent(SimulationSpeed::Paused));
+            event.send(PlayerCommand::SetSpeed(SimulationSpeed::Paused));
         }
         if action.just_pressed(SimulationSpeed::Normal) {
-            event.send(TimeMultiplierEvent(SimulationSpeed::Normal));
+            event.send(PlayerCommand::SetSpeed(SimulationSpeed::Normal));
         }
         if action.just_pressed(SimulationSpeed::Fast) {
-            event.send(TimeMultiplierEvent(SimulationSpeed::Fast));
+            event.send(PlayerCommand::SetSpeed(SimulationSpeed::Fast));
         }
         if action.just_pressed(SimulationSpeed::SuperFast) {
-            event.send(TimeMultiplierEvent(SimulationSpeed::SuperFast));
+            event.send(PlayerCommand::SetSpeed(SimulationSpeed::SuperFast));
+        }
+    }
+}
+
+fn spawn_at_target(
+    q: Query<&ActionState<WorldAction>>,
+    target: Query<&GlobalTransform, With<CameraTarget>>,
+    mut event: EventWriter<PlayerCommand>,
+    map: Res<Map>,
+) {
+    for action in &q {
+        if action.just_pressed(WorldAction::SpawnFauna) {
+            if let Some(index) = target
+                .get_single()
+                .ok()
+                .and_then(|transform| map.world_to_index(transform.translation()))
+            {
+                event.send(PlayerCommand::SpawnFauna(index));
+            }
+        }
+    }
+}
+
+fn spawn_save_input(mut cmd: Commands) {
+    cmd.spawn(InputManagerBundle::<SaveAction> {
+        input_map: InputMap::default()
+            .insert(KeyCode::F5, SaveAction::QuickSave)
+            .insert(KeyCode::F9, SaveAction::QuickLoad)
+            .build(),
+        ..default()
+    });
+}
+
+fn quick_save(
+    q: Query<&ActionState<SaveAction>>,
+    mut saves: EventWriter<SaveGame>,
+    mut loads: EventWriter<LoadGame>,
+) {
+    for action in &q {
+        if action.just_pressed(SaveAction::QuickSave) {
+            saves.send(SaveGame(PathBuf::from(QUICKSAVE_PATH)));
+        }
+        if action.just_pressed(SaveAction::QuickLoad) {
+            loads.send(LoadGame(PathBuf::from(QUICKSAVE_PATH)));
         }
     }
 }
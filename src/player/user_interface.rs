@@ -10,7 +10,9 @@ use bevy::{
     ui::{AlignItems, Interaction, JustifyContent, Size, Style, UiRect, Val},
 };
 
-use crate::chronos::{Chrono, SimulationSpeed, TimeMultiplier, TimeMultiplierEvent};
+use crate::chronos::{Chrono, SimulationSpeed, TimeMultiplier};
+
+use super::PlayerCommand;
 
 pub(crate) struct UserInterfacePlugin;
 
@@ -44,11 +46,11 @@ struct SimulationSpeedButton(SimulationSpeed);
 
 fn set_simulation_speed(
     q: Query<(&Interaction, &SimulationSpeedButton), Changed<Interaction>>,
-    mut writer: EventWriter<TimeMultiplierEvent>,
+    mut writer: EventWriter<PlayerCommand>,
 ) {
     for (interaction, speed_setting) in &q {
         if matches!(*interaction, Interaction::Clicked) {
-            writer.send(TimeMultiplierEvent(speed_setting.0));
+            writer.send(PlayerCommand::SetSpeed(speed_setting.0));
         }
     }
 }
@@ -0,0 +1,466 @@
+//! Recording runs so they can be played back exactly, e.g. to look into an agent behaving oddly.
+//!
+//! A replay holds what the run was started with, every command the player gave along with the
+//! frame it was carried out on, and checkpoints saved along the way. Runs are recorded and played
+//! back in lockstep, so the same commands on the same frames lead to the same run.
+//!
+//! Playback can seek to a tick by restoring the last checkpoint before it and simulating on from
+//! there. Checkpoints are full saves, so a run carried on from one goes exactly as the recording.
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    app::AppExit,
+    prelude::{
+        error, info, warn, App, CoreStage, EventReader, Events, IntoSystemDescriptor, Mut, Plugin,
+        Res, ResMut, Resource, World,
+    },
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chronos::{Chrono, Lockstep, LockstepPlugin},
+    map::pathfinding::DiagonalMovement,
+    player::{apply_player_commands, PlayerCommand},
+    save::{read_file, save_and_load, write_file, SaveError, SaveFormat, Snapshot},
+};
+
+/// How many frames pass between the checkpoints of a recording.
+const CHECKPOINT_FRAMES: u32 = 900;
+
+/// What a run was started with, besides the commands given during it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RunConfig {
+    pub(crate) seed: u64,
+    /// The map generator, as given on the command line. See `generators::from_spec`.
+    pub(crate) map: Option<String>,
+    pub(crate) map_size: (i32, i32),
+    pub(crate) diagonals: DiagonalMovement,
+}
+
+/// The simulation as it was on a frame of the recording.
+#[derive(Clone, Serialize, Deserialize)]
+struct Checkpoint {
+    frame: u32,
+    tick: u32,
+    /// The binary snapshot of the simulation.
+    snapshot: Vec<u8>,
+}
+
+/// A recorded run.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Replay {
+    pub(crate) config: RunConfig,
+    /// The commands the player gave, along with the frame they were carried out on.
+    commands: Vec<(u32, PlayerCommand)>,
+    /// Saved in the order they were made, so by frame and by tick.
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Replay {
+    pub(crate) fn new(config: RunConfig) -> Self {
+        Self {
+            config,
+            commands: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), SaveError> {
+        write_file(self, path)
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, SaveError> {
+        read_file(path)
+    }
+
+    /// The commands carried out on the frame.
+    fn commands_on(&self, frame: u32) -> impl Iterator<Item = PlayerCommand> + '_ {
+        let start = self.commands.partition_point(|(other, _)| *other < frame);
+        self.commands[start..]
+            .iter()
+            .take_while(move |(other, _)| *other == frame)
+            .map(|(_, command)| *command)
+    }
+
+    /// The last checkpoint saved on or before the tick, or the first one if they are all later.
+    fn checkpoint_before(&self, tick: u32) -> Option<&Checkpoint> {
+        self.checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.tick <= tick)
+            .or_else(|| self.checkpoints.first())
+    }
+}
+
+pub(crate) enum ReplayMode {
+    /// Records the run into the file as it goes.
+    Record { path: PathBuf, config: RunConfig },
+    /// Plays a recorded run back, stopping once the clock reaches the tick, if one is given.
+    Play { replay: Replay, seek: Option<u32> },
+}
+
+pub(crate) struct ReplayPlugin {
+    /// Whether to record or play back the run, if either.
+    pub(crate) mode: Option<ReplayMode>,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        match &self.mode {
+            Some(ReplayMode::Record { path, config }) => {
+                app.add_plugin(LockstepPlugin)
+                    .insert_resource(Recording {
+                        replay: Replay::new(config.clone()),
+                        path: path.clone(),
+                    })
+                    .add_system_to_stage(CoreStage::PreUpdate, record_commands)
+                    .add_system_to_stage(CoreStage::Last, record_checkpoints.after(save_and_load));
+            }
+            Some(ReplayMode::Play { replay, seek }) => {
+                app.add_plugin(LockstepPlugin)
+                    .insert_resource(Playback {
+                        replay: replay.clone(),
+                        seek: *seek,
+                        started: false,
+                        diverged: false,
+                    })
+                    .add_system_to_stage(
+                        CoreStage::PreUpdate,
+                        play_commands.before(apply_player_commands),
+                    )
+                    .add_system_to_stage(CoreStage::Last, play_back.after(save_and_load));
+            }
+            None => {}
+        }
+    }
+}
+
+/// The run being recorded, and where to.
+#[derive(Resource)]
+struct Recording {
+    replay: Replay,
+    path: PathBuf,
+}
+
+fn record_commands(
+    mut commands: EventReader<PlayerCommand>,
+    mut recording: ResMut<Recording>,
+    lockstep: Res<Lockstep>,
+) {
+    let frame = lockstep.frame();
+    recording
+        .replay
+        .commands
+        .extend(commands.iter().map(|command| (frame, *command)));
+}
+
+/// Saves checkpoints into the recording, and writes the recording out whenever it does and when
+/// the app closes.
+fn record_checkpoints(world: &mut World) {
+    let frame = world.resource::<Lockstep>().frame();
+    let exiting = world
+        .get_resource::<Events<AppExit>>()
+        .is_some_and(|events| !events.is_empty());
+
+    let due = frame.is_multiple_of(CHECKPOINT_FRAMES);
+    if due {
+        let tick = world.resource::<Chrono>().tick();
+        match Snapshot::capture(world).to_bytes(SaveFormat::Binary) {
+            Ok(snapshot) => world
+                .resource_mut::<Recording>()
+                .replay
+                .checkpoints
+                .push(Checkpoint {
+                    frame,
+                    tick,
+                    snapshot,
+                }),
+            Err(error) => error!("Could not save a checkpoint on frame {frame}: {error}"),
+        }
+    }
+    if due || exiting {
+        let recording = world.resource::<Recording>();
+        if let Err(error) = recording.replay.save(&recording.path) {
+            error!(
+                "Could not save the recording to {}: {error}",
+                recording.path.display()
+            );
+        }
+    }
+}
+
+/// The run being played back.
+#[derive(Resource)]
+struct Playback {
+    replay: Replay,
+    /// The tick to stop at, if any.
+    seek: Option<u32>,
+    /// Whether the checkpoint to start from has been restored.
+    started: bool,
+    /// Whether the run has gone differently than the recording.
+    diverged: bool,
+}
+
+/// Gives the recorded commands in place of the player's own.
+fn play_commands(
+    mut commands: ResMut<Events<PlayerCommand>>,
+    playback: Res<Playback>,
+    lockstep: Res<Lockstep>,
+) {
+    commands.clear();
+    if !lockstep.is_held() {
+        for command in playback.replay.commands_on(lockstep.frame()) {
+            commands.send(command);
+        }
+    }
+}
+
+/// Starts from the checkpoint before the tick to seek to, stops once it is reached, and checks the
+/// run against the checkpoints on the way.
+fn play_back(world: &mut World) {
+    world.resource_scope(|world, mut playback: Mut<Playback>| {
+        if !playback.started {
+            playback.started = true;
+            let checkpoint = playback
+                .replay
+                .checkpoint_before(playback.seek.unwrap_or(0));
+            // A run played back from its first frame is already where the checkpoint is.
+            let frame = world.resource::<Lockstep>().frame();
+            if let Some(checkpoint) = checkpoint.filter(|checkpoint| checkpoint.frame != frame) {
+                match Snapshot::from_bytes(&checkpoint.snapshot, SaveFormat::Binary) {
+                    Ok(snapshot) => {
+                        snapshot.restore(world);
+                        world.resource_mut::<Lockstep>().set_frame(checkpoint.frame);
+                        info!("Playing back from tick {}", checkpoint.tick);
+                    }
+                    Err(error) => error!("Could not restore the checkpoint: {error}"),
+                }
+                return;
+            }
+        }
+
+        let frame = world.resource::<Lockstep>().frame();
+        if !playback.diverged {
+            let expected = playback
+                .replay
+                .checkpoints
+                .iter()
+                .find(|checkpoint| checkpoint.frame == frame);
+            if let Some(expected) = expected {
+                let actual = Snapshot::capture(world).to_bytes(SaveFormat::Binary);
+                if actual.ok().as_ref() != Some(&expected.snapshot) {
+                    warn!(
+                        "The run has gone differently than the recording by tick {}",
+                        expected.tick
+                    );
+                    playback.diverged = true;
+                }
+            }
+        }
+
+        if let Some(tick) = playback.seek {
+            let reached = world.resource::<Chrono>().tick() >= tick;
+            if reached && !world.resource::<Lockstep>().is_held() {
+                world.resource_mut::<Lockstep>().hold();
+                info!("Stopped at tick {tick}");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use bevy::prelude::{App, Events};
+
+    use crate::{
+        chronos::{Chrono, Lockstep, SimulationSpeed},
+        headless_simulation,
+        map::{
+            generators::GridGenerator, io::parse_ascii, pathfinding::DiagonalMovement,
+            tiles::MapIndex,
+        },
+        player::PlayerCommand,
+        save::{SaveFormat, Snapshot},
+    };
+
+    use super::{
+        Checkpoint, Playback, Recording, Replay, ReplayMode, ReplayPlugin, RunConfig,
+        CHECKPOINT_FRAMES,
+    };
+
+    fn replay() -> Replay {
+        let mut replay = Replay::new(RunConfig {
+            seed: 42,
+            map: Some("island".into()),
+            map_size: (32, 32),
+            diagonals: DiagonalMovement::Always,
+        });
+        replay.commands = vec![
+            (3, PlayerCommand::SetSpeed(SimulationSpeed::Fast)),
+            (5, PlayerCommand::SpawnFauna(MapIndex(7))),
+            (5, PlayerCommand::SetSpeed(SimulationSpeed::Paused)),
+            (9, PlayerCommand::SpawnFauna(MapIndex(1))),
+        ];
+        for (frame, tick) in [(0, 0), (900, 899), (1800, 4000)] {
+            replay.checkpoints.push(Checkpoint {
+                frame,
+                tick,
+                snapshot: vec![frame as u8],
+            });
+        }
+        replay
+    }
+
+    #[test]
+    fn commands_are_found_by_frame() {
+        let replay = replay();
+        assert_eq!(replay.commands_on(4).count(), 0);
+        assert_eq!(
+            replay.commands_on(5).collect::<Vec<_>>(),
+            [
+                PlayerCommand::SpawnFauna(MapIndex(7)),
+                PlayerCommand::SetSpeed(SimulationSpeed::Paused)
+            ]
+        );
+        assert_eq!(replay.commands_on(10).count(), 0);
+    }
+
+    #[test]
+    fn seeking_starts_from_the_checkpoint_before() {
+        let replay = replay();
+        let frame = |tick| {
+            replay
+                .checkpoint_before(tick)
+                .map(|checkpoint| checkpoint.frame)
+        };
+        assert_eq!(frame(0), Some(0));
+        assert_eq!(frame(898), Some(0));
+        assert_eq!(frame(899), Some(900));
+        assert_eq!(frame(100_000), Some(1800));
+        assert_eq!(
+            Replay::new(replay.config.clone())
+                .checkpoint_before(0)
+                .map(|_| ()),
+            None
+        );
+    }
+
+    #[test]
+    fn replays_round_trip() {
+        let replay = replay();
+        for format in [SaveFormat::Ron, SaveFormat::Binary] {
+            let read: Replay = format.decode(&format.encode(&replay).unwrap()).unwrap();
+            assert_eq!(read.config, replay.config);
+            assert_eq!(read.commands, replay.commands);
+            assert_eq!(read.checkpoints.len(), 3);
+        }
+    }
+
+    /// A simulation on a map of a few clusters, with water and walls to find a way around.
+    fn simulation(config: &RunConfig, mode: ReplayMode) -> App {
+        let rows: Vec<String> = (0..20)
+            .map(|y| {
+                (0..36)
+                    .map(|x| match (x, y) {
+                        (3..=5, 3..=5) | (28..=30, 14..=16) | (20, 2) => '~',
+                        (17, 0..=14) | (8..=24, 10) => '#',
+                        _ => '.',
+                    })
+                    .collect()
+            })
+            .collect();
+        let generator = GridGenerator::new(parse_ascii(&rows.join("\n")).unwrap());
+        let mut app = headless_simulation(config, Arc::new(generator));
+        app.add_plugin(ReplayPlugin { mode: Some(mode) });
+        app
+    }
+
+    fn snapshot(app: &mut App) -> Vec<u8> {
+        Snapshot::capture(&mut app.world)
+            .to_bytes(SaveFormat::Binary)
+            .unwrap()
+    }
+
+    /// How many frames the test runs are recorded for, past the second checkpoint.
+    const FRAMES: u32 = CHECKPOINT_FRAMES + 60;
+
+    /// Records a run with crowded agents, for as many frames as the test runs last.
+    fn record(name: &str) -> App {
+        let config = RunConfig {
+            seed: 11,
+            map: None,
+            map_size: (1, 1),
+            diagonals: DiagonalMovement::default(),
+        };
+        let path = std::env::temp_dir().join(name);
+        let mut recorded = simulation(
+            &config,
+            ReplayMode::Record {
+                path: path.clone(),
+                config: config.clone(),
+            },
+        );
+        for frame in 0..FRAMES {
+            // Agents are spawned in twos, so they crowd each other.
+            if frame % 4 == 1 && frame < 60 {
+                let tile = MapIndex(frame as usize * 11);
+                let mut commands = recorded.world.resource_mut::<Events<PlayerCommand>>();
+                commands.send(PlayerCommand::SpawnFauna(tile));
+                commands.send(PlayerCommand::SpawnFauna(tile));
+            }
+            recorded.update();
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            recorded
+                .world
+                .resource::<Recording>()
+                .replay
+                .checkpoints
+                .len(),
+            2
+        );
+        recorded
+    }
+
+    #[test]
+    fn played_back_runs_match_the_recording() {
+        let mut recorded = record("ecosystem-record-play.bin");
+        let replay = recorded.world.resource::<Recording>().replay.clone();
+
+        let mut played = simulation(
+            &replay.config.clone(),
+            ReplayMode::Play { replay, seek: None },
+        );
+        for _ in 0..FRAMES {
+            played.update();
+        }
+        assert!(!played.world.resource::<Playback>().diverged);
+        assert_eq!(snapshot(&mut played), snapshot(&mut recorded));
+    }
+
+    #[test]
+    fn runs_seeked_from_a_checkpoint_match_the_recording() {
+        let mut recorded = record("ecosystem-record-seek.bin");
+        let replay = recorded.world.resource::<Recording>().replay.clone();
+        let tick = recorded.world.resource::<Chrono>().tick();
+
+        // Starts from the second checkpoint, and stops on the last tick of the recording.
+        let mut played = simulation(
+            &replay.config.clone(),
+            ReplayMode::Play {
+                replay,
+                seek: Some(tick),
+            },
+        );
+        for _ in CHECKPOINT_FRAMES..FRAMES {
+            played.update();
+        }
+        assert!(played.world.resource::<Lockstep>().is_held());
+        assert_eq!(played.world.resource::<Chrono>().tick(), tick);
+        assert_eq!(snapshot(&mut played), snapshot(&mut recorded));
+    }
+}
@@ -1,29 +1,51 @@
-use bevy::prelude::{Changed, Commands, Component, Entity, Plugin, Query};
+use bevy::prelude::{Changed, Commands, Component, CoreStage, Entity, Plugin, Query, Without};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    flora::Flora,
+    map::flow::{update_source_flow, SourceFlow},
+};
 
 // RESOURCES
 pub(crate) struct ResourcePlugin;
 
 impl Plugin for ResourcePlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_system(remove_empty_food)
-            .add_system(remove_empty_water);
+        app.add_event::<FoodEaten>()
+            .add_system(remove_empty_food)
+            .add_system(remove_empty_water)
+            .init_resource::<SourceFlow<WaterSource>>()
+            // Runs once the sources that were used up have been despawned.
+            .add_system_to_stage(CoreStage::PostUpdate, update_source_flow::<WaterSource>);
     }
 }
 
-#[derive(Component, Debug, Copy, Clone)]
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
 pub(crate) struct FoodSource {
     /// How much food this contains
     pub content: f32,
 }
 
-#[derive(Component, Debug, Copy, Clone)]
+/// Event that is sent when an entity has finished eating from a food source.
+pub(crate) struct FoodEaten {
+    pub eater: Entity,
+    pub source: Entity,
+}
+
+#[derive(Component, Debug, Copy, Clone, Serialize, Deserialize)]
 pub(crate) struct WaterSource {
     /// How much water this contains
     pub content: f32,
 }
 
 /// Removes any food that have become empty.
-fn remove_empty_food(mut cmd: Commands, q: Query<(Entity, &FoodSource), Changed<FoodSource>>) {
+///
+/// Flora grazed down to nothing dies instead, which returns its nutrients to the soil.
+#[allow(clippy::type_complexity)]
+fn remove_empty_food(
+    mut cmd: Commands,
+    q: Query<(Entity, &FoodSource), (Changed<FoodSource>, Without<Flora>)>,
+) {
     for (entity, food) in &q {
         // info("")
         if food.content <= 0.0 {
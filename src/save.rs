@@ -0,0 +1,1318 @@
+//! Saving the whole simulation to a file, and carrying on from it later.
+//!
+//! Files ending in `.ron` are saved as readable text, anything else in a compact binary format.
+//! Agents are saved along with what they were doing: the plan they had picked and how far along
+//! it they were, the paths they were following and the searches they were waiting for. A loaded
+//! simulation carries on exactly as the saved one would have.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bevy::{
+    ecs::{system::System, world::EntityMut},
+    hierarchy::despawn_with_children_recursive,
+    prelude::{
+        default, error, info, App, Assets, Children, Color, CoreStage, Entity, EventWriter, Events,
+        GlobalTransform, Handle, IntoSystem, Mesh, Or, PbrBundle, Plugin, Quat, Res, ResMut,
+        Resource, StandardMaterial, Transform, TransformBundle, Vec3, With, World,
+    },
+    reflect::Struct,
+    utils::HashMap,
+};
+use bevy_mod_picking::PickableBundle;
+use bevy_turborand::GlobalRng;
+use big_brain::{
+    actions::{steps_system, ActionState, Steps},
+    scorers::Score,
+    thinker::{thinker_component_attach_system, thinker_system, Actor, HasThinker},
+};
+use leafwing_input_manager::Actionlike;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    agent::{
+        actions::{DrinkAbility, DrinkTarget, EatAbility, EatTarget, MoveAbility, MovementPath},
+        navigation::{
+            AwaitingPath, NoPath, PathGoal, PathRequest, PathRequests, PathTask, PathingSnapshot,
+        },
+        scorers::{Hungry, ReproductionScore, Thirsty},
+    },
+    chronos::{Chrono, TimeMultiplier},
+    fauna::{
+        agent_mesh, agent_thinker,
+        needs::{Health, Hunger, Reproduction, Thirst},
+        Plan, AGENT_COLOR,
+    },
+    flora::{dispersal::CarriedSeeds, flora_mesh, water_mesh, Flora, FloraClock, WATER_COLOR},
+    map::{
+        flow::{update_source_flow, SourceFlow},
+        occupancy::{update_occupancy, BodySize, Occupancy},
+        pathfinding::Locomotion,
+        spatial::{update_spatial_index, SpatialIndex},
+        tiles::MapIndex,
+        Map,
+    },
+    resource::{FoodSource, WaterSource},
+    serial::{Serial, Serials},
+    weather::{Weather, WeatherClock},
+};
+
+/// Where the quick save and quick load keys save to and load from.
+pub(crate) const QUICKSAVE_PATH: &str = "quicksave.ron";
+
+#[derive(Default)]
+pub(crate) struct SavePlugin {
+    /// How often checkpoints are saved, if at all.
+    pub(crate) autosave: Option<Autosave>,
+    /// A save to carry on from, in place of the generated world.
+    pub(crate) start_from: Option<PathBuf>,
+}
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGame>()
+            .add_event::<LoadGame>()
+            // Runs last, so no commands queued this frame touch the entities a load replaces.
+            .add_system_to_stage(CoreStage::Last, save_and_load);
+
+        if let Some(autosave) = &self.autosave {
+            app.insert_resource(autosave.clone())
+                .add_system(save_checkpoints);
+        }
+        if let Some(path) = &self.start_from {
+            // Loaded at the end of the first frame, once the generated world has been spawned.
+            app.world
+                .resource_mut::<Events<LoadGame>>()
+                .send(LoadGame(path.clone()));
+        }
+    }
+}
+
+/// Saves checkpoints every few simulated days, overwriting the oldest once there are enough.
+#[derive(Resource, Debug, Clone)]
+pub(crate) struct Autosave {
+    /// How many simulated days pass between checkpoints.
+    interval_days: u32,
+    /// How many checkpoints are kept.
+    keep: usize,
+    /// Where the checkpoints are saved.
+    directory: PathBuf,
+    /// The simulated day of the last checkpoint, or of the first check for one since the
+    /// simulation started or was loaded.
+    last_day: Option<u32>,
+    /// Which of the checkpoints is saved next.
+    next_slot: usize,
+}
+
+impl Autosave {
+    /// Carries on after the most recent checkpoint already in the directory, if any.
+    pub(crate) fn new(interval_days: u32, keep: usize, directory: impl Into<PathBuf>) -> Self {
+        let mut autosave = Self {
+            interval_days: interval_days.max(1),
+            keep: keep.max(1),
+            directory: directory.into(),
+            last_day: None,
+            next_slot: 0,
+        };
+        let newest = (0..autosave.keep)
+            .filter_map(|slot| {
+                let modified = fs::metadata(autosave.path(slot)).ok()?.modified().ok()?;
+                Some((modified, slot))
+            })
+            .max();
+        if let Some((_, slot)) = newest {
+            autosave.next_slot = (slot + 1) % autosave.keep;
+        }
+        autosave
+    }
+
+    /// The file a checkpoint slot is saved to.
+    pub(crate) fn path(&self, slot: usize) -> PathBuf {
+        self.directory.join(format!("checkpoint-{slot}.bin"))
+    }
+
+    /// Where to save a checkpoint on the given day, if one is due.
+    fn due(&mut self, day: u32) -> Option<PathBuf> {
+        let last = *self.last_day.get_or_insert(day);
+        if day < last + self.interval_days {
+            return None;
+        }
+
+        self.last_day = Some(day);
+        let path = self.path(self.next_slot);
+        self.next_slot = (self.next_slot + 1) % self.keep;
+        Some(path)
+    }
+}
+
+fn save_checkpoints(
+    chrono: Res<Chrono>,
+    mut autosave: ResMut<Autosave>,
+    mut saves: EventWriter<SaveGame>,
+) {
+    if let Some(path) = autosave.due(chrono.days()) {
+        if let Err(error) = fs::create_dir_all(&autosave.directory) {
+            error!("Could not create {}: {error}", autosave.directory.display());
+        }
+        saves.send(SaveGame(path));
+    }
+}
+
+#[derive(Actionlike, Debug, Clone, Copy)]
+pub(crate) enum SaveAction {
+    QuickSave,
+    QuickLoad,
+}
+
+/// Event that saves the simulation to a file.
+pub(crate) struct SaveGame(pub(crate) PathBuf);
+
+/// Event that replaces the simulation with the one saved in a file.
+pub(crate) struct LoadGame(pub(crate) PathBuf);
+
+pub(crate) fn save_and_load(world: &mut World) {
+    let saves: Vec<SaveGame> = world.resource_mut::<Events<SaveGame>>().drain().collect();
+    for SaveGame(path) in saves {
+        match Snapshot::capture(world).save(&path) {
+            Ok(()) => info!("Saved the simulation to {}", path.display()),
+            Err(error) => error!("Could not save to {}: {error}", path.display()),
+        }
+    }
+
+    let loads: Vec<LoadGame> = world.resource_mut::<Events<LoadGame>>().drain().collect();
+    for LoadGame(path) in loads {
+        match Snapshot::load(&path) {
+            Ok(snapshot) => {
+                snapshot.restore(world);
+                if let Some(mut autosave) = world.get_resource_mut::<Autosave>() {
+                    autosave.last_day = None;
+                }
+                info!("Loaded the simulation from {}", path.display());
+            }
+            Err(error) => error!("Could not load {}: {error}", path.display()),
+        }
+    }
+}
+
+/// How a snapshot is written to bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SaveFormat {
+    /// Readable text.
+    Ron,
+    /// Compact binary.
+    Binary,
+}
+
+impl SaveFormat {
+    /// The format to use for a file, based on its extension.
+    pub(crate) fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("ron") => SaveFormat::Ron,
+            _ => SaveFormat::Binary,
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, SaveError> {
+        Ok(match self {
+            SaveFormat::Ron => ron::ser::to_string_pretty(value, default())?.into_bytes(),
+            SaveFormat::Binary => bincode::serialize(value)?,
+        })
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, SaveError> {
+        Ok(match self {
+            SaveFormat::Ron => ron::de::from_bytes(bytes)?,
+            SaveFormat::Binary => bincode::deserialize(bytes)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum SaveError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    /// The text of a RON save could not be read.
+    RonSyntax(ron::error::SpannedError),
+    Binary(bincode::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(error) => error.fmt(f),
+            SaveError::Ron(error) => error.fmt(f),
+            SaveError::RonSyntax(error) => error.fmt(f),
+            SaveError::Binary(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+    fn from(error: std::io::Error) -> Self {
+        SaveError::Io(error)
+    }
+}
+
+impl From<ron::Error> for SaveError {
+    fn from(error: ron::Error) -> Self {
+        SaveError::Ron(error)
+    }
+}
+
+impl From<ron::error::SpannedError> for SaveError {
+    fn from(error: ron::error::SpannedError) -> Self {
+        SaveError::RonSyntax(error)
+    }
+}
+
+impl From<bincode::Error> for SaveError {
+    fn from(error: bincode::Error) -> Self {
+        SaveError::Binary(error)
+    }
+}
+
+/// Everything needed to carry on the simulation from the moment it was captured.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Snapshot {
+    map: Map,
+    chrono: Chrono,
+    speed: TimeMultiplier,
+    weather: Weather,
+    weather_clock: WeatherClock,
+    flora_clock: FloraClock,
+    rng: GlobalRng,
+    serials: Serials,
+    fauna: Vec<FaunaState>,
+    flora: Vec<FloraState>,
+    water: Vec<WaterState>,
+    /// The searches agents are waiting for, first come first.
+    path_requests: Vec<PathRequestState>,
+}
+
+/// Where an entity is, both in the world and on the map.
+#[derive(Serialize, Deserialize)]
+struct Placement {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+    index: usize,
+}
+
+impl Placement {
+    fn new(transform: &Transform, index: &MapIndex) -> Self {
+        Self {
+            translation: transform.translation,
+            rotation: transform.rotation,
+            scale: transform.scale,
+            index: index.0,
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        Transform {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale: self.scale,
+        }
+    }
+}
+
+/// An entity saved along with the one referring to it.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum Reference {
+    Serial(Serial),
+    /// An entity that was already gone, numbered in the order they are first referred to. Every
+    /// reference to the same one is restored as the same missing entity.
+    Gone(u64),
+}
+
+#[derive(Serialize, Deserialize)]
+struct FaunaState {
+    serial: Serial,
+    placement: Placement,
+    hunger: Hunger,
+    thirst: Thirst,
+    reproduction: Reproduction,
+    health: Health,
+    eat: EatAbility,
+    drink: DrinkAbility,
+    movement: MoveAbility,
+    size: BodySize,
+    seeds: Option<CarriedSeeds>,
+    activity: Activity,
+}
+
+/// What an agent was doing, and what it was waiting for.
+#[derive(Serialize, Deserialize)]
+struct Activity {
+    /// Agents spawned on the frame they were saved don't have a thinker yet.
+    thinker: Option<ThinkerState>,
+    path: Option<PathState>,
+    search: Option<SearchState>,
+    awaiting_path: bool,
+    no_path: bool,
+    eat_target: Option<Reference>,
+    drink_target: Option<Reference>,
+    /// The tile the agent has reserved room on, along with its body size.
+    reservation: Option<(usize, f32)>,
+}
+
+/// How far along an action is, saved as big-brain's `ActionState`.
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ActionState")]
+enum ActionStateDef {
+    Init,
+    Requested,
+    Executing,
+    Cancelled,
+    Success,
+    Failure,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ThinkerState {
+    #[serde(with = "ActionStateDef")]
+    state: ActionState,
+    /// The plan the thinker picked, unless it has yet to pick one.
+    plan: Option<PlanState>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct PlanState {
+    plan: Plan,
+    #[serde(with = "ActionStateDef")]
+    state: ActionState,
+    /// Which of the steps of the plan is being carried out, if it has any.
+    step: usize,
+    /// How far along the step is, unless it is over and gone.
+    step_state: Option<StepState>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StepState(#[serde(with = "ActionStateDef")] ActionState);
+
+#[derive(Serialize, Deserialize)]
+struct PathState {
+    path: Vec<usize>,
+    target: Option<Reference>,
+    waited: f32,
+}
+
+/// A search that had been started, along with the path it found.
+#[derive(Serialize, Deserialize)]
+struct SearchState {
+    path: Option<Vec<usize>>,
+    target: Option<Reference>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PathRequestState {
+    agent: Reference,
+    start: usize,
+    goal: GoalState,
+    target: Option<Reference>,
+    locomotion: Locomotion,
+}
+
+#[derive(Serialize, Deserialize)]
+enum GoalState {
+    Tile(usize),
+    /// The tiles in order, so the same goal is always saved the same.
+    Nearest(Vec<usize>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct FloraState {
+    serial: Serial,
+    placement: Placement,
+    flora: Flora,
+    food: FoodSource,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WaterState {
+    serial: Serial,
+    placement: Placement,
+    water: WaterSource,
+}
+
+impl Snapshot {
+    /// Captures the current state of the simulation.
+    ///
+    /// Entities are kept in the order of their serials, so the same simulation is always captured
+    /// the same, however its entities came to be. Searches still running are waited for.
+    pub(crate) fn capture(world: &mut World) -> Self {
+        let serials = SerialLookup::new(world);
+        let plans = capture_plans(world);
+        let mut searches: HashMap<Entity, SearchState> = world
+            .query::<(Entity, &mut PathTask)>()
+            .iter_mut(world)
+            .map(|(entity, mut task)| {
+                let search = SearchState {
+                    path: task.wait(),
+                    target: task.target().map(|target| serials.reference(target)),
+                };
+                (entity, search)
+            })
+            .collect();
+
+        let mut agents = world.query::<(
+            (Entity, &Serial, &Transform, &MapIndex),
+            (&Hunger, &Thirst, &Reproduction, &Health),
+            (&EatAbility, &DrinkAbility, &MoveAbility),
+            &BodySize,
+            Option<&CarriedSeeds>,
+            (Option<&HasThinker>, Option<&MovementPath>),
+            (Option<&AwaitingPath>, Option<&NoPath>),
+            (Option<&EatTarget>, Option<&DrinkTarget>),
+        )>();
+        let occupancy = world.get_resource::<Occupancy>();
+        let mut fauna: Vec<FaunaState> = agents
+            .iter(world)
+            .map(
+                |(
+                    (entity, serial, transform, index),
+                    (hunger, thirst, reproduction, health),
+                    (eat, drink, movement),
+                    size,
+                    seeds,
+                    (thinker, path),
+                    (awaiting_path, no_path),
+                    (eat_target, drink_target),
+                )| {
+                    let thinker = thinker.map(|thinker| ThinkerState {
+                        state: world.get::<ActionState>(thinker.entity()).unwrap().clone(),
+                        plan: plans.get(&entity).cloned(),
+                    });
+                    FaunaState {
+                        serial: *serial,
+                        placement: Placement::new(transform, index),
+                        hunger: *hunger,
+                        thirst: *thirst,
+                        reproduction: *reproduction,
+                        health: *health,
+                        eat: eat.clone(),
+                        drink: drink.clone(),
+                        movement: movement.clone(),
+                        size: *size,
+                        seeds: seeds.cloned(),
+                        activity: Activity {
+                            thinker,
+                            path: path.map(|path| PathState {
+                                path: path.path.iter().copied().collect(),
+                                target: path.target.map(|target| serials.reference(target)),
+                                waited: path.waited,
+                            }),
+                            search: searches.remove(&entity),
+                            awaiting_path: awaiting_path.is_some(),
+                            no_path: no_path.is_some(),
+                            eat_target: eat_target.map(|eat| serials.reference(eat.target)),
+                            drink_target: drink_target.map(|drink| serials.reference(drink.target)),
+                            reservation: occupancy
+                                .and_then(|occupancy| occupancy.reservation(entity)),
+                        },
+                    }
+                },
+            )
+            .collect();
+        fauna.sort_by_key(|fauna| fauna.serial);
+        let mut flora: Vec<FloraState> = world
+            .query::<(&Serial, &Transform, &MapIndex, &Flora, &FoodSource)>()
+            .iter(world)
+            .map(|(serial, transform, index, flora, food)| FloraState {
+                serial: *serial,
+                placement: Placement::new(transform, index),
+                flora: flora.clone(),
+                food: *food,
+            })
+            .collect();
+        flora.sort_by_key(|flora| flora.serial);
+        let mut water: Vec<WaterState> = world
+            .query::<(&Serial, &Transform, &MapIndex, &WaterSource)>()
+            .iter(world)
+            .map(|(serial, transform, index, water)| WaterState {
+                serial: *serial,
+                placement: Placement::new(transform, index),
+                water: *water,
+            })
+            .collect();
+        water.sort_by_key(|water| water.serial);
+        let path_requests = world
+            .get_resource::<PathRequests>()
+            .map(|requests| {
+                requests
+                    .iter()
+                    .map(|request| PathRequestState {
+                        agent: serials.reference(request.agent),
+                        start: request.start,
+                        goal: match &request.goal {
+                            PathGoal::Tile(tile) => GoalState::Tile(*tile),
+                            PathGoal::Nearest(tiles) => {
+                                let mut tiles: Vec<usize> = tiles.iter().copied().collect();
+                                tiles.sort_unstable();
+                                GoalState::Nearest(tiles)
+                            }
+                        },
+                        target: request.target.map(|target| serials.reference(target)),
+                        locomotion: request.locomotion,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            map: world.resource::<Map>().clone(),
+            chrono: world.resource::<Chrono>().clone(),
+            speed: world.resource::<TimeMultiplier>().clone(),
+            weather: world.resource::<Weather>().clone(),
+            weather_clock: world.resource::<WeatherClock>().clone(),
+            flora_clock: world.resource::<FloraClock>().clone(),
+            rng: world.resource::<GlobalRng>().clone(),
+            serials: world.resource::<Serials>().clone(),
+            fauna,
+            flora,
+            water,
+            path_requests,
+        }
+        .with_gone_numbered()
+    }
+
+    /// Numbers the entities that were already gone in the order they are first referred to, in
+    /// place of their ids, which are different on every run.
+    fn with_gone_numbered(mut self) -> Self {
+        let mut numbers = HashMap::new();
+        let mut number = |reference: &mut Reference| {
+            if let Reference::Gone(id) = reference {
+                let next = numbers.len() as u64;
+                *id = *numbers.entry(*id).or_insert(next);
+            }
+        };
+        for fauna in &mut self.fauna {
+            let activity = &mut fauna.activity;
+            let path = activity.path.as_mut().and_then(|path| path.target.as_mut());
+            let search = activity
+                .search
+                .as_mut()
+                .and_then(|search| search.target.as_mut());
+            let targets = [
+                path,
+                search,
+                activity.eat_target.as_mut(),
+                activity.drink_target.as_mut(),
+            ];
+            targets.into_iter().flatten().for_each(&mut number);
+        }
+        for request in &mut self.path_requests {
+            number(&mut request.agent);
+            if let Some(target) = &mut request.target {
+                number(target);
+            }
+        }
+        self
+    }
+
+    /// Replaces the simulation in the world with the captured one.
+    pub(crate) fn restore(self, world: &mut World) {
+        let existing: Vec<Entity> = world
+            .query_filtered::<Entity, Or<(
+                With<Hunger>,
+                With<Flora>,
+                With<WaterSource>,
+                With<Actor>,
+            )>>()
+            .iter(world)
+            .collect();
+        for entity in existing {
+            // Steps and scorers might have gone already, along with what they belong to.
+            if world.get_entity(entity).is_some() {
+                despawn_with_children_recursive(world, entity);
+            }
+        }
+        // Despawns this late in the frame go unnoticed by the systems keeping track of the
+        // entities, so everything they worked out is started afresh and worked out again.
+        reset::<SpatialIndex>(world);
+        reset::<Occupancy>(world);
+        reset::<SourceFlow<WaterSource>>(world);
+        reset::<PathingSnapshot>(world);
+        if let Some(mut requests) = world.get_resource_mut::<PathRequests>() {
+            requests.clear();
+        }
+
+        // Replacing the map, rather than changing it, rebuilds everything worked out from it.
+        world.remove_resource::<Map>();
+        world.insert_resource(self.map.settings.clone());
+        world.insert_resource(self.map);
+        world.insert_resource(self.chrono);
+        world.insert_resource(self.speed);
+        world.insert_resource(self.weather);
+        world.insert_resource(self.weather_clock);
+        world.insert_resource(self.flora_clock);
+        world.insert_resource(self.rng);
+        world.insert_resource(self.serials);
+
+        let mut entities = EntityLookup::default();
+        let mut agents = Vec::new();
+        for fauna in self.fauna {
+            let looks = looks(world, agent_mesh(fauna.size), AGENT_COLOR);
+            let mut entity = spawn_placed(world, &fauna.placement, looks);
+            entity.insert((
+                fauna.serial,
+                fauna.hunger,
+                fauna.thirst,
+                fauna.reproduction,
+                fauna.health,
+                fauna.eat,
+                fauna.drink,
+                fauna.movement,
+                fauna.size,
+                agent_thinker(),
+            ));
+            if let Some(seeds) = fauna.seeds {
+                entity.insert(seeds);
+            }
+            entities.serials.insert(fauna.serial, entity.id());
+            agents.push((entity.id(), fauna.activity));
+        }
+        for flora in self.flora {
+            let looks = looks(world, flora_mesh(), flora.flora.color());
+            let entity = spawn_placed(world, &flora.placement, looks)
+                .insert((flora.serial, flora.flora, flora.food))
+                .id();
+            entities.serials.insert(flora.serial, entity);
+        }
+        for water in self.water {
+            let looks = looks(world, water_mesh(), WATER_COLOR);
+            let entity = spawn_placed(world, &water.placement, looks)
+                .insert((water.serial, water.water))
+                .id();
+            entities.serials.insert(water.serial, entity);
+        }
+
+        let mut reservations = Vec::new();
+        let mut thinkers = Vec::new();
+        for (agent, activity) in agents {
+            if let Some(path) = activity.path {
+                let target = path.target.map(|target| entities.get(world, target));
+                let mut movement = MovementPath::new(path.path, target);
+                movement.waited = path.waited;
+                world.entity_mut(agent).insert(movement);
+            }
+            if let Some(search) = activity.search {
+                let target = search.target.map(|target| entities.get(world, target));
+                world
+                    .entity_mut(agent)
+                    .insert(PathTask::finished(search.path, target));
+            }
+            if activity.awaiting_path {
+                world.entity_mut(agent).insert(AwaitingPath);
+            }
+            if activity.no_path {
+                world.entity_mut(agent).insert(NoPath);
+            }
+            if let Some(target) = activity.eat_target {
+                let target = entities.get(world, target);
+                world.entity_mut(agent).insert(EatTarget { target });
+            }
+            if let Some(target) = activity.drink_target {
+                let target = entities.get(world, target);
+                world.entity_mut(agent).insert(DrinkTarget { target });
+            }
+            if let Some(reservation) = activity.reservation {
+                reservations.push((agent, reservation));
+            }
+            thinkers.push((agent, activity.thinker));
+        }
+
+        // Worked out again straight away, as the systems relying on them run before they would
+        // be worked out next frame.
+        if world.contains_resource::<SpatialIndex>() {
+            run_once(world, update_spatial_index);
+        }
+        if world.contains_resource::<Occupancy>() {
+            run_once(world, update_occupancy);
+            let mut occupancy = world.resource_mut::<Occupancy>();
+            for (agent, (index, size)) in reservations {
+                occupancy.hold(agent, index, size);
+            }
+        }
+        if world.contains_resource::<SourceFlow<WaterSource>>() {
+            run_once(world, update_source_flow::<WaterSource>);
+        }
+        let requests: Vec<PathRequest> = self
+            .path_requests
+            .into_iter()
+            .map(|request| PathRequest {
+                agent: entities.get(world, request.agent),
+                start: request.start,
+                goal: match request.goal {
+                    GoalState::Tile(tile) => PathGoal::Tile(tile),
+                    GoalState::Nearest(tiles) => {
+                        PathGoal::Nearest(Arc::new(tiles.into_iter().collect()))
+                    }
+                },
+                target: request.target.map(|target| entities.get(world, target)),
+                locomotion: request.locomotion,
+            })
+            .collect();
+        if let Some(mut queue) = world.get_resource_mut::<PathRequests>() {
+            for request in requests {
+                queue.request(request);
+            }
+        }
+
+        restore_thinkers(world, thinkers);
+    }
+
+    pub(crate) fn to_bytes(&self, format: SaveFormat) -> Result<Vec<u8>, SaveError> {
+        format.encode(self)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8], format: SaveFormat) -> Result<Self, SaveError> {
+        format.decode(bytes)
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> Result<(), SaveError> {
+        write_file(self, path)
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, SaveError> {
+        read_file(path)
+    }
+}
+
+/// The serials of the saved entities, to save references to them by.
+struct SerialLookup(HashMap<Entity, Serial>);
+
+impl SerialLookup {
+    fn new(world: &mut World) -> Self {
+        Self(
+            world
+                .query::<(Entity, &Serial)>()
+                .iter(world)
+                .map(|(entity, serial)| (entity, *serial))
+                .collect(),
+        )
+    }
+
+    fn reference(&self, entity: Entity) -> Reference {
+        match self.0.get(&entity) {
+            Some(serial) => Reference::Serial(*serial),
+            None => Reference::Gone(entity.to_bits()),
+        }
+    }
+}
+
+/// The restored entities, to restore references to them by.
+#[derive(Default)]
+struct EntityLookup {
+    serials: HashMap<Serial, Entity>,
+    /// Entities that were gone when saved, each standing in for one of them.
+    gone: HashMap<u64, Entity>,
+}
+
+impl EntityLookup {
+    fn get(&mut self, world: &mut World, reference: Reference) -> Entity {
+        match reference {
+            Reference::Serial(serial) => self.serials[&serial],
+            Reference::Gone(bits) => *self.gone.entry(bits).or_insert_with(|| {
+                let entity = world.spawn_empty().id();
+                world.despawn(entity);
+                entity
+            }),
+        }
+    }
+}
+
+/// The plans the thinkers of the agents have picked, by agent.
+fn capture_plans(world: &mut World) -> HashMap<Entity, PlanState> {
+    world
+        .query::<(
+            &Actor,
+            &Plan,
+            &ActionState,
+            Option<&Steps>,
+            Option<&Children>,
+        )>()
+        .iter(world)
+        .map(|(Actor(agent), plan, state, steps, children)| {
+            let step_state = children
+                .and_then(|children| children.first())
+                .and_then(|step| world.get::<ActionState>(*step))
+                .map(|state| StepState(state.clone()));
+            let plan = PlanState {
+                plan: *plan,
+                state: state.clone(),
+                step: steps.map_or(0, active_step),
+                step_state,
+            };
+            (*agent, plan)
+        })
+        .collect()
+}
+
+/// Which of its steps the action is carrying out.
+fn active_step(steps: &Steps) -> usize {
+    *steps
+        .field("active_step")
+        .and_then(|step| step.downcast_ref::<usize>())
+        .unwrap()
+}
+
+/// Gives the agents thinkers that are where the saved ones were, along with the actions they had
+/// picked.
+///
+/// The thinkers and their actions are private to big-brain, so they are put in place by running
+/// its systems: the scores are set so each thinker picks its saved plan, and the steps of the plan
+/// are made to succeed until the saved one is reached.
+fn restore_thinkers(world: &mut World, thinkers: Vec<(Entity, Option<ThinkerState>)>) {
+    run_once(world, thinker_component_attach_system);
+
+    let plans: HashMap<Entity, Plan> = thinkers
+        .iter()
+        .filter_map(|(agent, thinker)| Some((*agent, thinker.as_ref()?.plan.as_ref()?.plan)))
+        .collect();
+    let mut scorers = world.query::<(
+        &Actor,
+        &mut Score,
+        Option<&Hungry>,
+        Option<&Thirsty>,
+        Option<&ReproductionScore>,
+    )>();
+    for (Actor(agent), mut score, hungry, thirsty, reproduce) in scorers.iter_mut(world) {
+        let picked = match plans.get(agent) {
+            Some(Plan::Eat) => hungry.is_some(),
+            Some(Plan::Drink) => thirsty.is_some(),
+            Some(Plan::Reproduce) => reproduce.is_some(),
+            Some(Plan::Idle) | None => false,
+        };
+        score.set(if picked { 1.0 } else { 0.0 });
+    }
+    // Only the thinkers with a plan pick one.
+    for (agent, _) in &thinkers {
+        let thinker = world.get::<HasThinker>(*agent).unwrap().entity();
+        *world.get_mut::<ActionState>(thinker).unwrap() = if plans.contains_key(agent) {
+            ActionState::Executing
+        } else {
+            ActionState::Success
+        };
+    }
+    run_once(world, thinker_system);
+
+    let actions: HashMap<Entity, Entity> = world
+        .query_filtered::<(Entity, &Actor), With<Plan>>()
+        .iter(world)
+        .map(|(action, Actor(agent))| (*agent, action))
+        .collect();
+    let steps: Vec<(Entity, usize)> = thinkers
+        .iter()
+        .filter_map(|(agent, thinker)| {
+            Some((actions[agent], thinker.as_ref()?.plan.as_ref()?.step))
+        })
+        .collect();
+    loop {
+        let mut behind = false;
+        for (action, step) in &steps {
+            let active = world.get::<Steps>(*action).map(active_step);
+            if active.is_some_and(|active| active < *step) {
+                behind = true;
+                let child = world.get::<Children>(*action).unwrap()[0];
+                *world.get_mut::<ActionState>(*action).unwrap() = ActionState::Executing;
+                *world.get_mut::<ActionState>(child).unwrap() = ActionState::Success;
+            }
+        }
+        if !behind {
+            break;
+        }
+        run_once(world, steps_system);
+    }
+
+    for (agent, thinker) in thinkers {
+        let thinker_entity = world.get::<HasThinker>(agent).unwrap().entity();
+        // Thinkers attached just now are left as they are, as they would have been by the end of
+        // the frame.
+        let thinker = match thinker {
+            Some(thinker) => thinker,
+            None => {
+                *world.get_mut::<ActionState>(thinker_entity).unwrap() = ActionState::Requested;
+                continue;
+            }
+        };
+        *world.get_mut::<ActionState>(thinker_entity).unwrap() = thinker.state;
+        let plan = match thinker.plan {
+            Some(plan) => plan,
+            None => continue,
+        };
+        let action = actions[&agent];
+        *world.get_mut::<ActionState>(action).unwrap() = plan.state;
+        let step = world
+            .get::<Children>(action)
+            .and_then(|children| children.first().copied());
+        match (step, plan.step_state) {
+            (Some(step), Some(StepState(state))) => {
+                *world.get_mut::<ActionState>(step).unwrap() = state;
+            }
+            // The last step is despawned once the plan is over.
+            (Some(step), None) => despawn_with_children_recursive(world, step),
+            (None, _) => {}
+        }
+    }
+}
+
+/// Runs a system on the world once, applying its commands straight away.
+fn run_once<Params>(world: &mut World, system: impl IntoSystem<(), (), Params>) {
+    let mut system = IntoSystem::into_system(system);
+    system.initialize(world);
+    system.run((), world);
+    system.apply_buffers(world);
+}
+
+/// Replaces the resource with its default, if the world has it.
+fn reset<T: Resource + Default>(world: &mut World) {
+    if world.contains_resource::<T>() {
+        world.insert_resource(T::default());
+    }
+}
+
+/// Writes the value to a file, in the format that suits its extension.
+pub(crate) fn write_file<T: Serialize>(value: &T, path: &Path) -> Result<(), SaveError> {
+    fs::write(path, SaveFormat::from_path(path).encode(value)?)?;
+    Ok(())
+}
+
+/// Reads a value from a file, in the format that suits its extension.
+pub(crate) fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, SaveError> {
+    SaveFormat::from_path(path).decode(&fs::read(path)?)
+}
+
+/// The mesh and material to draw an entity with, if the world draws anything.
+fn looks(
+    world: &mut World,
+    mesh: Mesh,
+    color: Color,
+) -> Option<(Handle<Mesh>, Handle<StandardMaterial>)> {
+    let mesh = world.get_resource_mut::<Assets<Mesh>>()?.add(mesh);
+    let material = world
+        .get_resource_mut::<Assets<StandardMaterial>>()?
+        .add(color.into());
+    Some((mesh, material))
+}
+
+fn spawn_placed<'w>(
+    world: &'w mut World,
+    placement: &Placement,
+    looks: Option<(Handle<Mesh>, Handle<StandardMaterial>)>,
+) -> EntityMut<'w> {
+    let transform = placement.transform();
+    // Placed right away, as the tiles of agents are worked out from where they are placed.
+    let global_transform = GlobalTransform::from(transform);
+    let index = MapIndex(placement.index);
+    if let Some((mesh, material)) = looks {
+        world.spawn((
+            PbrBundle {
+                mesh,
+                material,
+                transform,
+                global_transform,
+                ..default()
+            },
+            PickableBundle::default(),
+            index,
+        ))
+    } else {
+        world.spawn((
+            TransformBundle {
+                local: transform,
+                global: global_transform,
+            },
+            index,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use bevy::prelude::{App, Events, Transform, World};
+    use bevy_turborand::GlobalRng;
+
+    use crate::{
+        agent::{
+            actions::{DrinkAbility, EatAbility, MoveAbility},
+            navigation::PathRequests,
+        },
+        chronos::{Chrono, LockstepPlugin, TimeMultiplier},
+        fauna::needs::{Health, Hunger, Reproduction, Thirst},
+        flora::FloraClock,
+        headless_simulation,
+        map::{
+            flow::SourceFlow,
+            generators::{GridGenerator, MapGenerator},
+            io::parse_ascii,
+            occupancy::BodySize,
+            pathfinding::{DiagonalMovement, Locomotion},
+            plugin::MapSettings,
+            spatial::SpatialIndex,
+            tiles::MapIndex,
+        },
+        player::PlayerCommand,
+        replay::RunConfig,
+        resource::WaterSource,
+        serial::Serials,
+        weather::{Weather, WeatherClock},
+    };
+
+    use super::{Autosave, LoadGame, SaveFormat, SavePlugin, Snapshot};
+
+    fn world() -> World {
+        let settings = MapSettings {
+            width: 1,
+            height: 1,
+            tile_size: 1.0,
+        };
+        let map =
+            GridGenerator::new(parse_ascii("....\n.~..\n....").unwrap()).generate(&settings, 0);
+
+        let mut world = World::new();
+        let mut serials = Serials::default();
+        world.insert_resource(map);
+        world.insert_resource(Chrono::default());
+        world.insert_resource(TimeMultiplier(1));
+        world.insert_resource(Weather::default());
+        world.insert_resource(WeatherClock::default());
+        world.insert_resource(FloraClock::new(1));
+        world.insert_resource(GlobalRng::with_seed(7));
+        for n in 0..3 {
+            world.spawn((
+                Transform::from_xyz(n as f32, 0.0, 0.0),
+                MapIndex(n),
+                serials.next(),
+                Hunger {
+                    per_second: 1.0 + n as f32,
+                    value: 20.0,
+                },
+                Thirst {
+                    per_second: 2.0,
+                    value: 30.0 + n as f32,
+                },
+                Reproduction { value: 50.0 },
+                Health { value: 60.0 },
+                EatAbility { speed: 10.0 },
+                DrinkAbility { speed: 10.0 },
+                MoveAbility {
+                    speed: 2.0,
+                    locomotion: Locomotion::Walker,
+                },
+                BodySize(1.0),
+            ));
+        }
+        world.spawn((
+            Transform::from_xyz(1.0, 0.0, 1.0),
+            MapIndex(5),
+            serials.next(),
+            WaterSource { content: 100.0 },
+        ));
+        world.insert_resource(serials);
+        world
+    }
+
+    #[test]
+    fn snapshots_round_trip() {
+        let mut world = world();
+        let snapshot = Snapshot::capture(&mut world);
+        for format in [SaveFormat::Ron, SaveFormat::Binary] {
+            let bytes = snapshot.to_bytes(format).unwrap();
+            let read = Snapshot::from_bytes(&bytes, format).unwrap();
+            assert_eq!(read.to_bytes(format).unwrap(), bytes);
+            assert_eq!(read.fauna.len(), 3);
+            assert_eq!(read.water.len(), 1);
+        }
+        assert!(Snapshot::from_bytes(b"(map: ", SaveFormat::Ron).is_err());
+    }
+
+    /// A simulation in lockstep on a map of a few clusters, with water and walls to find a way
+    /// around. Only one search is started each tick, so agents queue up for paths.
+    fn lockstep_simulation() -> App {
+        let rows: Vec<String> = (0..20)
+            .map(|y| {
+                (0..36)
+                    .map(|x| match (x, y) {
+                        (3..=5, 3..=5) | (28..=30, 14..=16) | (20, 2) => '~',
+                        (17, 0..=14) | (8..=24, 10) => '#',
+                        _ => '.',
+                    })
+                    .collect()
+            })
+            .collect();
+        let config = RunConfig {
+            seed: 11,
+            map: None,
+            map_size: (1, 1),
+            diagonals: DiagonalMovement::default(),
+        };
+        let generator = GridGenerator::new(parse_ascii(&rows.join("\n")).unwrap());
+        let mut app = headless_simulation(&config, Arc::new(generator));
+        app.add_plugin(SavePlugin::default())
+            .add_plugin(LockstepPlugin);
+        app.world.resource_mut::<PathRequests>().searches_per_tick = 1;
+        app
+    }
+
+    fn snapshot(app: &mut App) -> Vec<u8> {
+        Snapshot::capture(&mut app.world)
+            .to_bytes(SaveFormat::Binary)
+            .unwrap()
+    }
+
+    #[test]
+    fn restored_simulations_carry_on_the_same() {
+        const FRAMES: u32 = 240;
+        let mut original = lockstep_simulation();
+        for frame in 0..FRAMES {
+            // Agents are spawned in twos, so they crowd each other.
+            if frame % 4 == 1 && frame < 60 {
+                let tile = MapIndex(frame as usize * 11);
+                let mut commands = original.world.resource_mut::<Events<PlayerCommand>>();
+                commands.send(PlayerCommand::SpawnFauna(tile));
+                commands.send(PlayerCommand::SpawnFauna(tile));
+            }
+            original.update();
+        }
+        let path = std::env::temp_dir().join("ecosystem-carry-on.bin");
+        let saved = Snapshot::capture(&mut original.world);
+        saved.save(&path).unwrap();
+        // Saved in the middle of things, so there is more to carry on than the needs.
+        let activities = || saved.fauna.iter().map(|fauna| &fauna.activity);
+        assert!(activities().any(|activity| activity
+            .thinker
+            .as_ref()
+            .and_then(|thinker| thinker.plan.as_ref())
+            .is_some_and(|plan| plan.step > 0)));
+        assert!(activities().any(|activity| activity.path.is_some()));
+        assert!(activities().any(|activity| activity.search.is_some()));
+        assert!(!saved.path_requests.is_empty());
+        for _ in 0..FRAMES {
+            original.update();
+        }
+
+        // A different simulation is replaced by the saved one.
+        let mut restored = lockstep_simulation();
+        restored
+            .world
+            .resource_mut::<Events<LoadGame>>()
+            .send(LoadGame(path.clone()));
+        restored.update();
+        fs::remove_file(&path).unwrap();
+        for _ in 0..FRAMES {
+            restored.update();
+        }
+
+        assert_eq!(snapshot(&mut restored), snapshot(&mut original));
+    }
+
+    /// A running simulation on the map, which saves and loads as the game does.
+    fn simulation(map: &str) -> App {
+        let config = RunConfig {
+            seed: 7,
+            map: None,
+            map_size: (1, 1),
+            diagonals: DiagonalMovement::default(),
+        };
+        let generator = GridGenerator::new(parse_ascii(map).unwrap());
+        let mut app = headless_simulation(&config, Arc::new(generator));
+        app.add_plugin(SavePlugin::default());
+        app.update();
+        app
+    }
+
+    #[test]
+    fn loads_forget_the_entities_they_replace() {
+        let mut app = simulation("....\n.~..\n....");
+        let path = std::env::temp_dir().join("ecosystem-load-twice.bin");
+        Snapshot::capture(&mut app.world).save(&path).unwrap();
+        for _ in 0..2 {
+            app.world
+                .resource_mut::<Events<LoadGame>>()
+                .send(LoadGame(path.clone()));
+            app.update();
+        }
+        fs::remove_file(&path).unwrap();
+        app.update();
+
+        let distance = |app: &App| {
+            app.world
+                .resource::<SourceFlow<WaterSource>>()
+                .field(Locomotion::Walker)
+                .and_then(|field| field.distance(0))
+        };
+        assert!(distance(&app).is_some());
+        assert_eq!(app.world.resource::<SpatialIndex>().entities_at(5).len(), 1);
+
+        // Once the only source left has run dry, there is no water anywhere.
+        let mut water = app.world.query::<&mut WaterSource>();
+        water.single_mut(&mut app.world).content = 0.0;
+        app.update();
+        app.update();
+        assert_eq!(distance(&app), None);
+        assert!(app
+            .world
+            .resource::<SpatialIndex>()
+            .entities_at(5)
+            .is_empty());
+    }
+
+    #[test]
+    fn checkpoints_rotate() {
+        let directory = std::env::temp_dir().join("ecosystem-checkpoints-rotate");
+        let _ = fs::remove_dir_all(&directory);
+        let mut autosave = Autosave::new(2, 3, &directory);
+        let slots: Vec<_> = (0..10).map(|day| autosave.due(day)).collect();
+        let path = |slot: usize| Some(directory.join(format!("checkpoint-{slot}.bin")));
+        assert_eq!(
+            slots,
+            [
+                None,
+                None,
+                path(0),
+                None,
+                path(1),
+                None,
+                path(2),
+                None,
+                path(0),
+                None
+            ]
+        );
+
+        // Loading an earlier save counts again from the day it was loaded at.
+        autosave.last_day = None;
+        assert_eq!(autosave.due(3), None);
+        assert_eq!(autosave.due(5), path(1));
+    }
+
+    #[test]
+    fn autosaves_carry_on_after_the_newest_checkpoint() {
+        let directory = std::env::temp_dir().join("ecosystem-checkpoints-resume");
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let autosave = Autosave::new(1, 3, &directory);
+        assert_eq!(autosave.next_slot, 0);
+
+        let now = SystemTime::now();
+        for (slot, age) in [(0, 30), (1, 10), (2, 20)] {
+            let file = fs::File::create(autosave.path(slot)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        assert_eq!(Autosave::new(1, 3, &directory).next_slot, 2);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+//! Numbering the simulated entities in the order they were spawned.
+//!
+//! Queries go through entities in an order that depends on how the world came to be, and entity
+//! ids are handed out again as entities come and go, so neither is the same after a simulation is
+//! loaded. Systems whose outcome depends on the order they go through entities in go through them
+//! by serial instead.
+
+use bevy::prelude::{Component, Entity, Query, Resource};
+use serde::{Deserialize, Serialize};
+
+/// Which entity this is, kept when the simulation is saved and loaded.
+#[derive(
+    Component, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub(crate) struct Serial(u64);
+
+/// Hands out serials, counting up from the first.
+#[derive(Resource, Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Serials {
+    next: u64,
+}
+
+impl Serials {
+    pub(crate) fn next(&mut self) -> Serial {
+        let serial = Serial(self.next);
+        self.next += 1;
+        serial
+    }
+}
+
+/// Puts the items in the order of the serials of the entities they belong to. Those of entities
+/// without a serial go first.
+pub(crate) fn by_serial<T>(
+    items: impl IntoIterator<Item = T>,
+    entity: impl Fn(&T) -> Entity,
+    serials: &Query<&Serial>,
+) -> Vec<T> {
+    let mut items: Vec<T> = items.into_iter().collect();
+    items.sort_by_cached_key(|item| serials.get(entity(item)).ok().copied());
+    items
+}
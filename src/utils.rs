@@ -1,7 +1,6 @@
 //! Collection of various utility functions.
 
 use bevy::prelude::Vec3;
-use bracket_pathfinding::prelude::Point;
 use std::ops::Range;
 
 /// From a set of points, return whichever point is closest to the target.
@@ -30,36 +29,25 @@ pub(crate) fn lerp_range(value: f32, range: &Range<f32>) -> f32 {
     lerp(value, range.start, range.end)
 }
 
-/// Project a vector onto to a plane with the given normal.
-pub(crate) fn project_to_plane(vector: Vec3, normal: Vec3) -> Vec3 {
-    vector - vector.project_onto(normal)
-}
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub(crate) struct Vec2<T> {
-    /// X dimension
-    pub x: T,
-    // Y dimension
-    pub y: T,
-}
-
-impl<T> Vec2<T> {
-    pub fn new(x: T, y: T) -> Self {
-        Vec2 { x, y }
-    }
-}
-
-impl From<Vec2<i32>> for Point {
-    fn from(value: Vec2<i32>) -> Self {
-        Point {
-            x: value.x,
-            y: value.y,
-        }
+/// Returns the value following the named argument on the command line, if any.
+///
+/// E.g. `arg_value("--map")` returns `island` for `ecosystem --map island`.
+pub(crate) fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Parses a size written as `<width>x<height>`, e.g. `512x256`.
+pub(crate) fn parse_size(value: &str) -> Option<(i32, i32)> {
+    let (width, height) = value.split_once('x')?;
+    let size = (width.trim().parse().ok()?, height.trim().parse().ok()?);
+    if size.0 > 0 && size.1 > 0 {
+        Some(size)
+    } else {
+        None
     }
 }
 
-impl From<Point> for Vec2<i32> {
-    fn from(value: Point) -> Self {
-        Vec2::new(value.x, value.y)
-    }
+/// Project a vector onto to a plane with the given normal.
+pub(crate) fn project_to_plane(vector: Vec3, normal: Vec3) -> Vec3 {
+    vector - vector.project_onto(normal)
 }
@@ -0,0 +1,129 @@
+//! The weather model of the simulation.
+
+use std::f32::consts::{PI, TAU};
+
+use bevy::{
+    ecs::schedule::ShouldRun,
+    prelude::{App, Plugin, Res, ResMut, Resource, SystemSet, Vec2},
+};
+use bevy_turborand::{DelegatedRng, GlobalRng, TurboRand};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chronos::{Chrono, TickTimer},
+    utils::lerp,
+};
+
+pub(crate) struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Weather::default())
+            .insert_resource(WeatherClock::default())
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(weather_tick)
+                    .with_system(update_wind)
+                    .with_system(update_temperature),
+            );
+    }
+}
+
+/// How many simulation ticks pass between updates of the weather, a second at normal speed.
+const WEATHER_INTERVAL: u32 = 15;
+
+/// Decides when the weather should next be updated.
+#[derive(Resource, Clone, Serialize, Deserialize)]
+pub(crate) struct WeatherClock(TickTimer);
+
+impl Default for WeatherClock {
+    fn default() -> Self {
+        Self(TickTimer::new(WEATHER_INTERVAL))
+    }
+}
+
+fn weather_tick(chrono: Res<Chrono>, mut clock: ResMut<WeatherClock>) -> ShouldRun {
+    if clock.0.update(chrono.tick()) {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+/// The current state of the weather across the map.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Weather {
+    /// Direction the wind is blowing towards, in the map's x/y plane.
+    wind_direction: Vec2,
+    /// How strong the wind is. Range: 0.0..=1.0
+    pub(crate) wind_strength: f32,
+    /// Air temperature in degrees celsius.
+    pub(crate) temperature: f32,
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self {
+            wind_direction: Vec2::X,
+            wind_strength: 0.3,
+            temperature: seasonal_temperature(0.0, 0.0),
+        }
+    }
+}
+
+impl Weather {
+    /// The current temperature at a location with the given mean temperature.
+    pub(crate) fn local_temperature(&self, mean_temperature: f32) -> f32 {
+        mean_temperature + self.temperature - MEAN_TEMPERATURE
+    }
+
+    /// The wind as a vector, scaled by its strength.
+    pub(crate) fn wind(&self) -> Vec2 {
+        self.wind_direction * self.wind_strength
+    }
+}
+
+/// How far the wind can turn in radians per update.
+const WIND_TURN: f32 = 0.1 * PI;
+/// How much the wind strength can change per update.
+const WIND_GUST: f32 = 0.05;
+
+/// The wind slowly drifts in direction and strength.
+fn update_wind(mut weather: ResMut<Weather>, mut rng: ResMut<GlobalRng>) {
+    let rng = rng.get_mut();
+    let turn = lerp(rng.f32(), -WIND_TURN, WIND_TURN);
+    weather.wind_direction = Vec2::from_angle(turn).rotate(weather.wind_direction);
+    weather.wind_strength =
+        (weather.wind_strength + lerp(rng.f32(), -WIND_GUST, WIND_GUST)).clamp(0.0, 1.0);
+}
+
+/// The mean temperature across the year.
+const MEAN_TEMPERATURE: f32 = 10.0;
+/// How much warmer summer is than the mean, and how much colder winter is.
+const SEASONAL_SWING: f32 = 15.0;
+/// How much warmer the afternoon is than the mean, and how much colder the night is.
+const DAILY_SWING: f32 = 3.0;
+
+/// The temperature follows the seasons, with the coldest point at the start of the year,
+/// and the coldest time of the day at midnight.
+fn seasonal_temperature(year_progress: f32, day_progress: f32) -> f32 {
+    MEAN_TEMPERATURE
+        - SEASONAL_SWING * (year_progress * TAU).cos()
+        - DAILY_SWING * (day_progress * TAU).cos()
+}
+
+fn update_temperature(mut weather: ResMut<Weather>, chrono: Res<Chrono>) {
+    weather.temperature = seasonal_temperature(chrono.year_progress(), chrono.day_progress());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{seasonal_temperature, MEAN_TEMPERATURE};
+
+    /// Winter nights should freeze, while summer days should not.
+    #[test]
+    fn winter_is_colder_than_summer() {
+        assert!(seasonal_temperature(0.0, 0.0) < 0.0);
+        assert!(seasonal_temperature(0.5, 0.5) > MEAN_TEMPERATURE);
+    }
+}